@@ -0,0 +1,16 @@
+use std::time::{Duration, Instant};
+
+/// Runs `f` `runs` times after one warm-up run and prints the fastest run under `label`.
+pub fn bench<T>(label: &str, runs: u32, mut f: impl FnMut() -> T) -> Duration {
+    std::hint::black_box(f());
+    let fastest = (0..runs)
+        .map(|_| {
+            let started = Instant::now();
+            std::hint::black_box(f());
+            started.elapsed()
+        })
+        .min()
+        .unwrap_or_default();
+    println!("{label:<48} {:>10.3} ms", fastest.as_secs_f64() * 1e3);
+    fastest
+}
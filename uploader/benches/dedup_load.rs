@@ -0,0 +1,47 @@
+//! Times loading a large dedup hashset against deserializing it through serde, which grows
+//! the set as it goes, and a text set with and without `--dedup-hashset-capacity`. Run with
+//! `cargo bench --bench dedup_load`.
+
+mod common;
+
+use std::{collections::HashSet, fs::File, io::BufReader};
+
+use solana_address::Address;
+use uploader::{
+    merge::{load_dedup_hashset, load_dedup_hashset_with_capacity},
+    save_dedup_hashset,
+    types::DedupFormat,
+};
+
+const ADDRESSES: u32 = 5_000_000;
+
+fn main() {
+    let dir = tempfile::tempdir().unwrap();
+    let dedup_hashset: HashSet<Address> = (0..ADDRESSES)
+        .map(|index| {
+            let mut address = [0x55; 32];
+            address[..4].copy_from_slice(&index.to_be_bytes());
+            Address::from(address)
+        })
+        .collect();
+    let bincode_path = dir.path().join("dedup");
+    let text_path = dir.path().join("dedup.txt");
+    save_dedup_hashset(&dedup_hashset, &bincode_path, DedupFormat::Bincode).unwrap();
+    save_dedup_hashset(&dedup_hashset, &text_path, DedupFormat::Text).unwrap();
+    drop(dedup_hashset);
+
+    println!("{ADDRESSES} addresses");
+    common::bench("  bincode, sized from its length prefix", 3, || {
+        load_dedup_hashset(&bincode_path).unwrap()
+    });
+    common::bench("  bincode, serde deserialize_from (before)", 3, || {
+        let reader = BufReader::new(File::open(&bincode_path).unwrap());
+        bincode::deserialize_from::<_, HashSet<Address>>(reader).unwrap()
+    });
+    common::bench("  text, no hint", 3, || {
+        load_dedup_hashset(&text_path).unwrap()
+    });
+    common::bench("  text, --dedup-hashset-capacity hint", 3, || {
+        load_dedup_hashset_with_capacity(&text_path, Some(ADDRESSES as usize)).unwrap()
+    });
+}
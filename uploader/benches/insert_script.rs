@@ -0,0 +1,66 @@
+//! Times `build_insert_script` on large and small seeds against the per-byte `write!` hex
+//! encoding it replaced. Run with `cargo bench --bench insert_script`.
+
+mod common;
+
+use std::fmt::Write as _;
+
+use solana_address::Address;
+use uploader::{PdaSqlite, build_insert_script, cloudflare::UploadOptions};
+
+fn entries(count: usize, seed_len: usize) -> Vec<PdaSqlite> {
+    (0..count)
+        .map(|index| PdaSqlite {
+            pda: Address::from([index as u8; 32]),
+            seeds: vec![vec![index as u8; seed_len]],
+            program_id: Address::from([0xAA; 32]),
+        })
+        .collect()
+}
+
+/// The blob literals of every row, hex-encoded a byte at a time into a script sized at 256
+/// bytes per entry, as the script was built before.
+fn per_byte_literals(entries: &[PdaSqlite]) -> String {
+    fn to_blob_literal(bytes: &[u8]) -> String {
+        let mut literal = String::with_capacity(bytes.len() * 2 + 3);
+        literal.push_str("X'");
+        for byte in bytes {
+            write!(&mut literal, "{byte:02X}").unwrap();
+        }
+        literal.push('\'');
+        literal
+    }
+
+    let mut script = String::with_capacity(entries.len() * 256);
+    for entry in entries {
+        let mut seed_bytes = (entry.seeds.len() as u32).to_le_bytes().to_vec();
+        for seed in &entry.seeds {
+            seed_bytes.extend_from_slice(&(seed.len() as u32).to_le_bytes());
+            seed_bytes.extend_from_slice(seed);
+        }
+        script.push_str(&format!(
+            "({}, {}, {}, {}),\n",
+            to_blob_literal(entry.pda.as_ref()),
+            to_blob_literal(entry.program_id.as_ref()),
+            entry.seeds.len(),
+            to_blob_literal(&seed_bytes)
+        ));
+    }
+    script
+}
+
+fn main() {
+    let options = UploadOptions::default();
+    for (label, entries) in [
+        ("64 entries with a 4 MiB seed", entries(64, 4 << 20)),
+        ("200k entries with a 32-byte seed", entries(200_000, 32)),
+    ] {
+        println!("{label}");
+        common::bench("  build_insert_script", 3, || {
+            build_insert_script(&entries, &options).unwrap()
+        });
+        common::bench("  per-byte write! literals (before)", 3, || {
+            per_byte_literals(&entries)
+        });
+    }
+}
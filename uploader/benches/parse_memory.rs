@@ -0,0 +1,121 @@
+//! Peak heap use of a merge whose files repeat each other's entries, against holding every
+//! parsed entry until one final sort and dedup as the merge did before it dropped duplicates
+//! per file. Run with `cargo bench --bench parse_memory`.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    fs::File,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, SystemTime},
+};
+
+use solana_address::Address;
+use uploader::{MergeOptions, PdaSqlite, merge, summary::PhaseTimings};
+
+/// Counts live heap bytes and their high-water mark.
+struct Counting;
+
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc(layout) };
+        if !ptr.is_null() {
+            let live = LIVE.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK.fetch_max(live, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+        LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+/// Heap bytes `f` kept live at its peak, beyond what was live when it started.
+fn peak_heap<T>(f: impl FnOnce() -> T) -> usize {
+    let baseline = LIVE.load(Ordering::Relaxed);
+    PEAK.store(baseline, Ordering::Relaxed);
+    drop(std::hint::black_box(f()));
+    PEAK.load(Ordering::Relaxed) - baseline
+}
+
+const FILES: usize = 16;
+const ENTRIES_PER_FILE: u32 = 100_000;
+
+/// Writes `FILES` blobs that each hold the same entries, backdated so the scan reads them.
+fn write_duplicated_blobs(dir: &Path) -> Vec<PathBuf> {
+    let entries: Vec<PdaSqlite> = (0..ENTRIES_PER_FILE)
+        .map(|index| {
+            let mut pda = [0; 32];
+            pda[..4].copy_from_slice(&index.to_be_bytes());
+            PdaSqlite {
+                pda: Address::from(pda),
+                seeds: vec![index.to_le_bytes().to_vec(), b"vault".to_vec()],
+                program_id: Address::from([0xAA; 32]),
+            }
+        })
+        .collect();
+    let blob = bincode::serialize(&entries).unwrap();
+    (0..FILES)
+        .map(|index| {
+            let path = dir.join(format!("pda_collector_{index}.blob"));
+            std::fs::write(&path, &blob).unwrap();
+            File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(SystemTime::now() - Duration::from_secs(60))
+                .unwrap();
+            path
+        })
+        .collect()
+}
+
+fn main() {
+    let dir = tempfile::tempdir().unwrap();
+    let files = write_duplicated_blobs(dir.path());
+    println!(
+        "{FILES} files, each the same {ENTRIES_PER_FILE} entries ({} MiB on disk)",
+        (FILES * std::fs::metadata(&files[0]).unwrap().len() as usize) >> 20
+    );
+
+    let merged = peak_heap(|| {
+        let output = merge(
+            Some(dir.path().to_owned()),
+            dir.path().join("dedup"),
+            &MergeOptions::default(),
+            &mut PhaseTimings::default(),
+        )
+        .unwrap();
+        assert_eq!(output.entries.len(), ENTRIES_PER_FILE as usize);
+        output
+    });
+    let held = peak_heap(|| {
+        let mut entries: Vec<PdaSqlite> = files
+            .iter()
+            .flat_map(|path| {
+                bincode::deserialize::<Vec<PdaSqlite>>(&std::fs::read(path).unwrap()).unwrap()
+            })
+            .collect();
+        entries.sort_unstable_by_key(|entry| entry.pda);
+        entries.dedup_by_key(|entry| entry.pda);
+        entries
+    });
+    println!(
+        "{:<48} {:>10} MiB",
+        "merge, dropping duplicates per file",
+        merged >> 20
+    );
+    println!(
+        "{:<48} {:>10} MiB",
+        "every parsed entry held (before)",
+        held >> 20
+    );
+}
@@ -3,9 +3,13 @@ CREATE TABLE pda_registry (
     pda BLOB PRIMARY KEY,
     program_id BLOB NOT NULL,
     seed_count INTEGER NOT NULL,
-    seed_bytes BLOB NOT NULL
+    seed_bytes BLOB NOT NULL,
+    -- JSON array of hex-encoded seeds, only populated when uploading with --with-seeds-json
+    seeds_json TEXT
 ) WITHOUT ROWID;
 
+-- Existing databases: ALTER TABLE pda_registry ADD COLUMN seeds_json TEXT;
+
 CREATE INDEX 
 IF NOT EXISTS idx_pda_registry_program_pda
 ON pda_registry(program_id, pda);
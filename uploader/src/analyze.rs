@@ -0,0 +1,141 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use eyre::{Result, WrapErr};
+use log::info;
+use rayon::prelude::*;
+use serde::Serialize;
+use solana_address::Address;
+
+use crate::types::PdaSqlite;
+
+/// Entries of one program.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProgramCount {
+    /// Base58 program id.
+    pub program_id: String,
+    pub entries: u64,
+    /// Percentage of all entries.
+    pub share: f64,
+}
+
+/// Which programs dominate a set of entries and how many seeds those entries carry, written by
+/// the `analyze` subcommand for capacity planning.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProgramProfile {
+    pub entries: u64,
+    pub distinct_programs: usize,
+    /// Programs with the most entries, largest first.
+    pub top_programs: Vec<ProgramCount>,
+    /// Entries keyed by their number of seeds.
+    pub seed_counts: BTreeMap<usize, u64>,
+}
+
+#[derive(Default)]
+struct Counts {
+    programs: HashMap<Address, u64>,
+    seed_counts: BTreeMap<usize, u64>,
+}
+
+impl Counts {
+    fn merge(mut self, other: Self) -> Self {
+        for (program_id, count) in other.programs {
+            *self.programs.entry(program_id).or_default() += count;
+        }
+        for (seeds, count) in other.seed_counts {
+            *self.seed_counts.entry(seeds).or_default() += count;
+        }
+        self
+    }
+}
+
+impl ProgramProfile {
+    /// Profiles `entries`, which must already be deduplicated by pda, keeping the `top`
+    /// programs with the most entries.
+    pub fn collect(entries: &[PdaSqlite], top: usize) -> Self {
+        let counts = entries
+            .par_iter()
+            .fold(Counts::default, |mut counts, entry| {
+                *counts.programs.entry(entry.program_id).or_default() += 1;
+                *counts.seed_counts.entry(entry.seeds.len()).or_default() += 1;
+                counts
+            })
+            .reduce(Counts::default, Counts::merge);
+
+        let total = entries.len() as u64;
+        let distinct_programs = counts.programs.len();
+        let mut programs: Vec<(Address, u64)> = counts.programs.into_iter().collect();
+        // Ties break on the program id so the report is stable across runs.
+        programs.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        let top_programs = programs
+            .into_iter()
+            .take(top)
+            .map(|(program_id, entries)| ProgramCount {
+                program_id: program_id.to_string(),
+                entries,
+                share: percent(entries, total),
+            })
+            .collect();
+
+        Self {
+            entries: total,
+            distinct_programs,
+            top_programs,
+            seed_counts: counts.seed_counts,
+        }
+    }
+
+    /// Prints the profile to stdout as aligned tables.
+    pub fn print_table(&self) {
+        println!("entries: {}", self.entries);
+        println!("distinct programs: {}", self.distinct_programs);
+
+        println!();
+        println!(
+            "{:>4}  {:<44}  {:>12}  {:>6}",
+            "rank", "program_id", "entries", "share"
+        );
+        for (rank, program) in self.top_programs.iter().enumerate() {
+            println!(
+                "{:>4}  {:<44}  {:>12}  {:>5.1}%",
+                rank + 1,
+                program.program_id,
+                program.entries,
+                program.share
+            );
+        }
+
+        println!();
+        println!("{:>5}  {:>12}  {:>6}", "seeds", "entries", "share");
+        for (seeds, &entries) in &self.seed_counts {
+            println!(
+                "{seeds:>5}  {entries:>12}  {:>5.1}%",
+                percent(entries, self.entries)
+            );
+        }
+    }
+}
+
+fn percent(count: u64, total: u64) -> f64 {
+    if total > 0 {
+        count as f64 / total as f64 * 100.0
+    } else {
+        0.0
+    }
+}
+
+pub fn write_profile(profile: &ProgramProfile, path: &Path) -> Result<()> {
+    let file = File::create(path)
+        .wrap_err_with(|| format!("failed to create profile file {}", path.display()))?;
+    let mut writer = BufWriter::new(file);
+    serde_json::to_writer_pretty(&mut writer, profile)
+        .wrap_err_with(|| format!("failed to write profile file {}", path.display()))?;
+    writeln!(writer)?;
+    writer.flush()?;
+    info!("Wrote program profile to {}", path.display());
+    Ok(())
+}
@@ -0,0 +1,227 @@
+use std::{borrow::Cow, collections::HashMap, future::Future, sync::Arc, time::Duration};
+
+use eyre::Result;
+
+use crate::{
+    cloudflare::{
+        ImportReport, UploadOptions, build_insert_script, import_to_d1, query_insert_d1,
+        with_chunk_timeout,
+    },
+    s3::upload_to_s3,
+    types::{PdaSqlite, S3PayloadFormat, UploadMode},
+};
+
+/// Renders a chunk's payload without touching the network, see [`UploadBackend::renderer`].
+/// Returns `None` for chunks with nothing to render ahead.
+pub type Renderer = Arc<dyn Fn(&[PdaSqlite]) -> Result<Option<String>> + Send + Sync>;
+
+/// A sink that receives chunks of merged entries for one target database.
+pub trait UploadBackend {
+    /// Uploads one chunk of entries to the database identified by `database_id`, reporting any
+    /// statements or rows that failed without failing the whole chunk.
+    fn upload(
+        &self,
+        database_id: &str,
+        entries: &[PdaSqlite],
+    ) -> impl Future<Output = Result<ImportReport>> + Send;
+
+    /// Renders chunks for `database_id` ahead of their upload, so rendering the next chunk can
+    /// overlap the network wait of the current one. `None` for backends whose uploads have no
+    /// CPU-bound rendering worth moving.
+    fn renderer(&self, _database_id: &str) -> Option<Renderer> {
+        None
+    }
+
+    /// Uploads a chunk whose payload the [`renderer`](Self::renderer) already rendered.
+    fn upload_rendered(
+        &self,
+        database_id: &str,
+        entries: &[PdaSqlite],
+        _rendered: String,
+    ) -> impl Future<Output = Result<ImportReport>> + Send {
+        self.upload(database_id, entries)
+    }
+}
+
+/// Imports entries into Cloudflare D1 through the R2-staged import API, or through the query
+/// API as selected by `upload_mode`.
+pub struct CloudflareD1Backend {
+    /// Cloudflare API base URL, normally [`CLOUDFLARE_API_BASE`](crate::cloudflare::CLOUDFLARE_API_BASE).
+    pub api_base: String,
+    pub api_token: String,
+    pub account_id: String,
+    pub options: UploadOptions,
+    /// Table names keyed by database id, replacing `options.table_name` for those databases.
+    pub table_overrides: HashMap<String, String>,
+    /// Abandon a chunk's import once it takes longer than this.
+    pub chunk_timeout: Option<Duration>,
+    pub upload_mode: UploadMode,
+    /// Largest chunk [`UploadMode::Auto`] sends through the query API.
+    pub query_mode_max_entries: usize,
+}
+
+impl CloudflareD1Backend {
+    /// Imports a chunk as [`upload_to_d1`](crate::cloudflare::upload_to_d1) does, starting from
+    /// `script` when it was already rendered with `options`.
+    async fn import(
+        &self,
+        database_id: &str,
+        entries: &[PdaSqlite],
+        options: &UploadOptions,
+        script: Option<String>,
+    ) -> Result<ImportReport> {
+        let import = import_to_d1(
+            &self.api_base,
+            &self.api_token,
+            &self.account_id,
+            database_id,
+            entries,
+            options,
+            script,
+        );
+        with_chunk_timeout(import, self.chunk_timeout, || {
+            format!(
+                "D1 import of {} entries into database {database_id}",
+                entries.len()
+            )
+        })
+        .await
+    }
+}
+
+/// Whether `upload_mode` sends a chunk of `entry_count` entries through the query API.
+fn via_query(upload_mode: UploadMode, query_mode_max_entries: usize, entry_count: usize) -> bool {
+    match upload_mode {
+        UploadMode::Import => false,
+        UploadMode::Query => true,
+        UploadMode::Auto => entry_count <= query_mode_max_entries,
+    }
+}
+
+impl UploadBackend for CloudflareD1Backend {
+    async fn upload(&self, database_id: &str, entries: &[PdaSqlite]) -> Result<ImportReport> {
+        let options = options_for(&self.options, &self.table_overrides, database_id);
+        if via_query(self.upload_mode, self.query_mode_max_entries, entries.len()) {
+            query_insert_d1(
+                &self.api_base,
+                &self.api_token,
+                &self.account_id,
+                database_id,
+                entries,
+                &options,
+                self.chunk_timeout,
+            )
+            .await
+        } else {
+            self.import(database_id, entries, &options, None).await
+        }
+    }
+
+    /// Renders the import script of the chunks that go through the R2-staged import; query
+    /// mode batches its statements itself.
+    fn renderer(&self, database_id: &str) -> Option<Renderer> {
+        let options = options_for(&self.options, &self.table_overrides, database_id).into_owned();
+        let (upload_mode, query_mode_max_entries) = (self.upload_mode, self.query_mode_max_entries);
+        Some(Arc::new(move |entries| {
+            if via_query(upload_mode, query_mode_max_entries, entries.len()) {
+                return Ok(None);
+            }
+            build_insert_script(entries, &options)
+        }))
+    }
+
+    async fn upload_rendered(
+        &self,
+        database_id: &str,
+        entries: &[PdaSqlite],
+        rendered: String,
+    ) -> Result<ImportReport> {
+        let options = options_for(&self.options, &self.table_overrides, database_id);
+        self.import(database_id, entries, &options, Some(rendered))
+            .await
+    }
+}
+
+/// Writes each chunk as an object to an S3-compatible bucket.
+pub struct S3Backend {
+    pub client: aws_sdk_s3::Client,
+    pub bucket: String,
+    pub prefix: String,
+    pub format: S3PayloadFormat,
+    pub options: UploadOptions,
+    /// Table names keyed by database id, replacing `options.table_name` for those databases.
+    pub table_overrides: HashMap<String, String>,
+}
+
+impl UploadBackend for S3Backend {
+    async fn upload(&self, database_id: &str, entries: &[PdaSqlite]) -> Result<ImportReport> {
+        upload_to_s3(
+            &self.client,
+            &self.bucket,
+            &self.prefix,
+            database_id,
+            entries,
+            self.format,
+            &options_for(&self.options, &self.table_overrides, database_id),
+        )
+        .await?;
+        Ok(ImportReport::default())
+    }
+}
+
+/// `options` with the table name overridden for `database_id`, if it has an override.
+fn options_for<'a>(
+    options: &'a UploadOptions,
+    table_overrides: &HashMap<String, String>,
+    database_id: &str,
+) -> Cow<'a, UploadOptions> {
+    match table_overrides.get(database_id) {
+        Some(table_name) => Cow::Owned(UploadOptions {
+            table_name: table_name.clone(),
+            ..options.clone()
+        }),
+        None => Cow::Borrowed(options),
+    }
+}
+
+/// The backend selected with `--backend`.
+pub enum Backend {
+    CloudflareD1(CloudflareD1Backend),
+    S3(S3Backend),
+}
+
+impl UploadBackend for Backend {
+    async fn upload(&self, database_id: &str, entries: &[PdaSqlite]) -> Result<ImportReport> {
+        match self {
+            Backend::CloudflareD1(backend) => backend.upload(database_id, entries).await,
+            Backend::S3(backend) => backend.upload(database_id, entries).await,
+        }
+    }
+
+    fn renderer(&self, database_id: &str) -> Option<Renderer> {
+        match self {
+            Backend::CloudflareD1(backend) => backend.renderer(database_id),
+            Backend::S3(backend) => backend.renderer(database_id),
+        }
+    }
+
+    async fn upload_rendered(
+        &self,
+        database_id: &str,
+        entries: &[PdaSqlite],
+        rendered: String,
+    ) -> Result<ImportReport> {
+        match self {
+            Backend::CloudflareD1(backend) => {
+                backend
+                    .upload_rendered(database_id, entries, rendered)
+                    .await
+            }
+            Backend::S3(backend) => {
+                backend
+                    .upload_rendered(database_id, entries, rendered)
+                    .await
+            }
+        }
+    }
+}
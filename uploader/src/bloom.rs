@@ -0,0 +1,104 @@
+use std::{
+    ffi::OsString,
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use eyre::{Result, WrapErr, eyre};
+use serde::{Deserialize, Serialize};
+use solana_address::Address;
+
+/// Fixed-size bloom filter over addresses, persisted next to the dedup hashset so a run does
+/// not have to hold the whole set to find candidate duplicates.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BloomFilter {
+    num_bits: u64,
+    num_hashes: u32,
+    /// Number of addresses in the dedup hashset this filter was built from, used to tell
+    /// whether a persisted filter still matches the set on disk.
+    source_len: u64,
+    bits: Vec<u64>,
+}
+
+impl BloomFilter {
+    /// Creates an empty filter of `num_bits` bits, with the hash count chosen for
+    /// `expected_len` addresses.
+    pub fn new(num_bits: u64, expected_len: u64) -> Self {
+        let num_bits = num_bits.max(64);
+        let num_hashes = (num_bits as f64 / expected_len.max(1) as f64 * std::f64::consts::LN_2)
+            .round()
+            .clamp(1.0, 16.0) as u32;
+        Self {
+            num_bits,
+            num_hashes,
+            source_len: 0,
+            bits: vec![0; num_bits.div_ceil(64) as usize],
+        }
+    }
+
+    pub fn num_bits(&self) -> u64 {
+        self.num_bits
+    }
+
+    pub fn source_len(&self) -> u64 {
+        self.source_len
+    }
+
+    pub fn set_source_len(&mut self, source_len: u64) {
+        self.source_len = source_len;
+    }
+
+    pub fn insert(&mut self, address: &Address) {
+        for index in self.indexes(address) {
+            self.bits[(index / 64) as usize] |= 1 << (index % 64);
+        }
+    }
+
+    /// `false` means the address is definitely not in the set; `true` means it may be.
+    pub fn contains(&self, address: &Address) -> bool {
+        self.indexes(address)
+            .all(|index| self.bits[(index / 64) as usize] & (1 << (index % 64)) != 0)
+    }
+
+    /// Double hashing over the address bytes. PDAs are sha256 outputs, so their bytes are
+    /// already uniformly distributed and need no further hashing.
+    fn indexes(&self, address: &Address) -> impl Iterator<Item = u64> + use<> {
+        let bytes = address.as_ref();
+        let h1 = u64::from_le_bytes(bytes[..8].try_into().expect("address is 32 bytes"));
+        let h2 = u64::from_le_bytes(bytes[8..16].try_into().expect("address is 32 bytes")) | 1;
+        let num_bits = self.num_bits;
+        (0..u64::from(self.num_hashes)).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % num_bits)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = File::open(path)
+            .wrap_err_with(|| format!("failed to open bloom filter {}", path.display()))?;
+        bincode::deserialize_from(BufReader::new(file)).map_err(|err| {
+            eyre!(
+                "failed to deserialize bloom filter {}: {err}",
+                path.display()
+            )
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let temp_path = path.with_extension("bloom.tmp");
+        let mut writer = BufWriter::new(
+            File::create(&temp_path)
+                .wrap_err_with(|| format!("failed to create {}", temp_path.display()))?,
+        );
+        bincode::serialize_into(&mut writer, self)?;
+        writer.flush()?;
+        std::fs::rename(&temp_path, path)
+            .wrap_err_with(|| format!("failed to replace bloom filter {}", path.display()))?;
+        Ok(())
+    }
+}
+
+/// Path of the bloom filter persisted alongside `dedup_hashset_path`.
+pub fn bloom_path(dedup_hashset_path: &Path) -> PathBuf {
+    let mut path = OsString::from(dedup_hashset_path.as_os_str());
+    path.push(".bloom");
+    PathBuf::from(path)
+}
@@ -1,4 +1,11 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    fmt,
+    num::NonZeroUsize,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use cloudflare::{
     endpoints::workerskv::{
@@ -12,23 +19,41 @@ use cloudflare::{
     },
 };
 use eyre::{Result, WrapErr, eyre};
-use log::{debug, info};
+use log::{debug, info, warn};
 use md5::compute as md5_compute;
 use reqwest::{
-    Client as HttpClient,
-    header::{AUTHORIZATION, CONTENT_TYPE},
+    Client as HttpClient, RequestBuilder, Response, StatusCode,
+    header::{AUTHORIZATION, CONTENT_TYPE, HeaderMap, RETRY_AFTER},
 };
-use serde::Deserialize;
-use serde_json::json;
+use serde::{Deserialize, de::DeserializeOwned};
+use serde_json::{Map, Value, json};
+use solana_address::Address;
 use tokio::time::sleep;
 
-use crate::types::PdaSqlite;
+use crate::{
+    exit::TimedOut,
+    merge::decode_address,
+    schema::{self, Column},
+    types::PdaSqlite,
+};
 
-pub fn new_client(credentials: Credentials) -> Result<Arc<Client>> {
+/// Base URL of the Cloudflare v4 REST API used for the D1 endpoints.
+pub const CLOUDFLARE_API_BASE: &str = "https://api.cloudflare.com/client/v4";
+
+/// Creates the API client used for KV. `api_base` is given without a trailing slash, like
+/// [`CLOUDFLARE_API_BASE`].
+pub fn new_client(credentials: Credentials, api_base: &str) -> Result<Arc<Client>> {
+    let environment = if api_base == CLOUDFLARE_API_BASE {
+        Environment::Production
+    } else {
+        // Endpoint paths are joined onto the base, which drops its last segment unless the
+        // base ends with a slash.
+        Environment::Custom(format!("{api_base}/"))
+    };
     Ok(Arc::new(Client::new(
         credentials,
         ClientConfig::default(),
-        Environment::Production,
+        environment,
     )?))
 }
 
@@ -71,64 +96,432 @@ pub async fn put_kv(
     Ok(())
 }
 
+/// Table import scripts insert into unless `--table-name` or `--db-table` says otherwise.
+pub const DEFAULT_TABLE_NAME: &str = "pda_registry";
+
+/// Rows per `INSERT` statement unless `--entries-per-statement` says otherwise.
+pub const DEFAULT_ENTRIES_PER_STATEMENT: usize = 10;
+/// Floor for `--entries-per-statement-auto`, so shrinking never degenerates into one
+/// statement per row.
+pub const MIN_ENTRIES_PER_STATEMENT: usize = 2;
+
+/// Knobs controlling how entries are rendered into the import script and imported.
+#[derive(Debug, Clone)]
+pub struct UploadOptions {
+    /// Also populate the `seeds_json` column with a JSON array of hex-encoded seeds.
+    pub with_seeds_json: bool,
+    /// Also populate the `seed_checksum` column with the crc32 of `seed_bytes`.
+    pub with_checksums: bool,
+    /// Wrap the script in a single `BEGIN;`/`COMMIT;` transaction. D1's importer rejects
+    /// explicit transactions, so this is only for scripts replayed into plain sqlite.
+    pub transactional: bool,
+    /// Table the script inserts into. It is interpolated unquoted, so it must be a plain
+    /// identifier.
+    pub table_name: String,
+    /// Upload a fresh copy of the script even when D1 reports it already has the file, in case
+    /// that earlier upload was corrupt. Only used by D1 imports.
+    pub force_reupload: bool,
+    /// Rows per `INSERT` statement.
+    pub entries_per_statement: usize,
+    /// When D1 rejects a statement as too large, halve `entries_per_statement` (down to
+    /// [`MIN_ENTRIES_PER_STATEMENT`]) and retry the chunk. Only used by D1 imports.
+    pub adaptive_entries_per_statement: bool,
+    /// Split a chunk into import files of at most this many entries. Only used by D1 imports.
+    pub max_rows_per_file: Option<NonZeroUsize>,
+    /// Split a chunk into import files whose scripts take at most this many bytes. Only used
+    /// by D1 imports.
+    pub max_file_bytes: Option<usize>,
+    /// `Content-Type` of the script staged in R2, or `None` to send none. Only used by D1
+    /// imports.
+    pub r2_content_type: Option<String>,
+    /// `x-amz-meta-*` headers, keyed without the prefix, attached to the script staged in R2
+    /// along with its `entry-count` and `first-pda` when not empty. Only used by D1 imports.
+    pub r2_metadata: Vec<(String, String)>,
+}
+
+impl Default for UploadOptions {
+    fn default() -> Self {
+        Self {
+            with_seeds_json: false,
+            with_checksums: false,
+            transactional: false,
+            table_name: DEFAULT_TABLE_NAME.to_owned(),
+            force_reupload: false,
+            entries_per_statement: DEFAULT_ENTRIES_PER_STATEMENT,
+            adaptive_entries_per_statement: false,
+            max_rows_per_file: None,
+            max_file_bytes: None,
+            r2_content_type: None,
+            r2_metadata: Vec::new(),
+        }
+    }
+}
+
+/// Imports one chunk of entries into a D1 database. With `chunk_timeout`, the whole
+/// init → upload → ingest → poll sequence is abandoned with a [`TimedOut`] error once it runs
+/// longer than that, so the caller can retry just this chunk.
 pub async fn upload_to_d1(
+    api_base: &str,
     api_token: &str,
     account_identifier: &str,
     database_identifier: &str,
     entries: &[PdaSqlite],
-) -> Result<()> {
+    options: &UploadOptions,
+    chunk_timeout: Option<Duration>,
+) -> Result<ImportReport> {
+    let import = import_to_d1(
+        api_base,
+        api_token,
+        account_identifier,
+        database_identifier,
+        entries,
+        options,
+        None,
+    );
+    with_chunk_timeout(import, chunk_timeout, || {
+        format!(
+            "D1 import of {} entries into database {database_identifier}",
+            entries.len()
+        )
+    })
+    .await
+}
+
+/// Inserts one chunk of entries into a D1 database by sending its `INSERT` statements straight
+/// to the query API, skipping the R2-staged import and its fixed latency. Statements are sent
+/// in batches of up to [`QUERY_BATCH_BYTES`]; each batch is one request, so the chunk is not
+/// atomic, but every statement is `INSERT OR IGNORE` and a retried chunk converges. Meant for
+/// small chunks: D1 caps each statement at 100 KB, so entries with large seeds need a low
+/// `entries_per_statement`. `chunk_timeout` behaves as in [`upload_to_d1`].
+pub async fn query_insert_d1(
+    api_base: &str,
+    api_token: &str,
+    account_identifier: &str,
+    database_identifier: &str,
+    entries: &[PdaSqlite],
+    options: &UploadOptions,
+    chunk_timeout: Option<Duration>,
+) -> Result<ImportReport> {
+    let insert = async {
+        let batches = query_batches(entries, options, QUERY_BATCH_BYTES)?;
+        if batches.is_empty() {
+            info!("Skip D1 query insert for database {database_identifier}: no new entries");
+            return Ok(ImportReport::default());
+        }
+
+        let mut report = ImportReport::default();
+        for batch in &batches {
+            let results = post_query(
+                api_base,
+                api_token,
+                account_identifier,
+                database_identifier,
+                &json!({ "sql": batch.sql }),
+            )
+            .await?;
+            if results.len() != batch.statement_entries.len() {
+                return Err(eyre!(
+                    "D1 query returned {} result set(s) for a batch of {} statement(s)",
+                    results.len(),
+                    batch.statement_entries.len()
+                ));
+            }
+            for (result, &rows) in results.iter().zip(&batch.statement_entries) {
+                if !result.success {
+                    report.failed_statements += 1;
+                    report.failed_rows += rows;
+                }
+            }
+            report.uploaded_bytes += batch.sql.len() as u64;
+        }
+        info!(
+            "Inserted {} entries into D1 database {database_identifier} through {} query request(s)",
+            entries.len(),
+            batches.len()
+        );
+        Ok(report)
+    };
+    with_chunk_timeout(insert, chunk_timeout, || {
+        format!(
+            "D1 query insert of {} entries into database {database_identifier}",
+            entries.len()
+        )
+    })
+    .await
+}
+
+/// Largest batch of statements [`query_insert_d1`] sends in one request.
+pub const QUERY_BATCH_BYTES: usize = 1 << 20;
+
+/// Statements sent together in one D1 query request.
+#[derive(Debug)]
+struct QueryBatch {
+    sql: String,
+    /// Rows inserted by each statement of `sql`, in order.
+    statement_entries: Vec<usize>,
+}
+
+/// Renders `entries` as single `INSERT` statements and packs them into batches of at most
+/// `max_bytes`, except that a statement longer than that gets a batch of its own.
+fn query_batches(
+    entries: &[PdaSqlite],
+    options: &UploadOptions,
+    max_bytes: usize,
+) -> Result<Vec<QueryBatch>> {
+    let options = UploadOptions {
+        transactional: false,
+        ..options.clone()
+    };
+    let mut batches: Vec<QueryBatch> = Vec::new();
+    for statement_entries in entries.chunks(options.entries_per_statement.max(1)) {
+        let Some(statement) = build_insert_script(statement_entries, &options)? else {
+            continue;
+        };
+        match batches.last_mut() {
+            Some(batch) if batch.sql.len() + statement.len() <= max_bytes => {
+                batch.sql.push_str(&statement);
+                batch.statement_entries.push(statement_entries.len());
+            }
+            _ => batches.push(QueryBatch {
+                sql: statement,
+                statement_entries: vec![statement_entries.len()],
+            }),
+        }
+    }
+    Ok(batches)
+}
+
+/// Awaits `upload`, failing with [`TimedOut`] once it runs longer than `chunk_timeout`.
+pub(crate) async fn with_chunk_timeout<T>(
+    upload: impl Future<Output = Result<T>>,
+    chunk_timeout: Option<Duration>,
+    describe: impl FnOnce() -> String,
+) -> Result<T> {
+    let Some(chunk_timeout) = chunk_timeout else {
+        return upload.await;
+    };
+    tokio::time::timeout(chunk_timeout, upload)
+        .await
+        .map_err(|_| {
+            TimedOut(format!(
+                "{} did not finish within {chunk_timeout:?}",
+                describe()
+            ))
+        })?
+}
+
+/// The import behind [`upload_to_d1`], without its timeout. `script`, when given, is the
+/// [`build_insert_script`] output for `entries` and `options`, rendered ahead of time.
+///
+/// Entries beyond `options.max_rows_per_file` or `options.max_file_bytes` are split across
+/// several import files, each imported to completion before the next starts. A file over the
+/// byte cap is halved until it fits, down to a single entry.
+pub(crate) async fn import_to_d1(
+    api_base: &str,
+    api_token: &str,
+    account_identifier: &str,
+    database_identifier: &str,
+    entries: &[PdaSqlite],
+    options: &UploadOptions,
+    script: Option<String>,
+) -> Result<ImportReport> {
     if entries.is_empty() {
         info!("Skip D1 upload for database {database_identifier}: no new entries");
-        return Ok(());
+        return Ok(ImportReport::default());
     }
 
-    let script = match build_insert_script(entries)? {
-        Some(script) => script,
-        None => {
-            info!("Skip D1 upload for database {database_identifier}: nothing to insert");
-            return Ok(());
-        }
+    // Files still to import, the next one last. A script rendered ahead only matches the
+    // whole of `entries`.
+    let max_rows = options
+        .max_rows_per_file
+        .map_or(usize::MAX, NonZeroUsize::get);
+    let mut pending: Vec<(&[PdaSqlite], Option<String>)> = if entries.len() <= max_rows {
+        vec![(entries, script)]
+    } else {
+        entries
+            .chunks(max_rows)
+            .rev()
+            .map(|part| (part, None))
+            .collect()
     };
+    let mut report = ImportReport::default();
+    let mut file_num = 0;
+    while let Some((part, script)) = pending.pop() {
+        let script = match script {
+            Some(script) => script,
+            None => match build_insert_script(part, options)? {
+                Some(script) => script,
+                None => {
+                    info!("Skip D1 upload for database {database_identifier}: nothing to insert");
+                    continue;
+                }
+            },
+        };
+        if let Some(max_bytes) = options.max_file_bytes
+            && script.len() > max_bytes
+        {
+            if part.len() > 1 {
+                let (first, second) = part.split_at(part.len() / 2);
+                debug!(
+                    "Import file of {} entries renders to {} bytes, over {max_bytes}; splitting it in two",
+                    part.len(),
+                    script.len()
+                );
+                pending.push((second, None));
+                pending.push((first, None));
+                continue;
+            }
+            warn!(
+                "A single entry renders to {} bytes, over the {max_bytes} byte import file cap; importing it anyway",
+                script.len()
+            );
+        }
+
+        file_num += 1;
+        if file_num > 1 || !pending.is_empty() {
+            info!(
+                "Importing file {file_num} of the chunk into D1 database {database_identifier}: {} entries, {} bytes",
+                part.len(),
+                script.len()
+            );
+        }
+        report.add(
+            import_file(
+                api_base,
+                api_token,
+                account_identifier,
+                database_identifier,
+                part,
+                options,
+                script,
+            )
+            .await?,
+        );
+    }
+    Ok(report)
+}
 
-    let payload_size_bytes = script.len();
-    let checksum = format!("{:x}", md5_compute(script.as_bytes()));
+/// Imports one file rendered from `entries`. When D1 rejects a statement as too large and
+/// `options.adaptive_entries_per_statement` is set, the file is rendered again with fewer
+/// entries per statement and retried.
+async fn import_file(
+    api_base: &str,
+    api_token: &str,
+    account_identifier: &str,
+    database_identifier: &str,
+    entries: &[PdaSqlite],
+    options: &UploadOptions,
+    script: String,
+) -> Result<ImportReport> {
+    let mut options = Cow::Borrowed(options);
+    let mut script = Some(script);
+    loop {
+        // A statement-size retry renders the script again, with fewer entries per statement.
+        let script = match script.take() {
+            Some(script) => script,
+            None => match build_insert_script(entries, &options)? {
+                Some(script) => script,
+                None => return Ok(ImportReport::default()),
+            },
+        };
+        let result = import_script(
+            api_base,
+            api_token,
+            account_identifier,
+            database_identifier,
+            entries,
+            script,
+            &options,
+        )
+        .await;
+
+        match result {
+            Err(err)
+                if options.adaptive_entries_per_statement
+                    && options.entries_per_statement > MIN_ENTRIES_PER_STATEMENT
+                    && is_statement_too_large(&err) =>
+            {
+                let entries_per_statement =
+                    (options.entries_per_statement / 2).max(MIN_ENTRIES_PER_STATEMENT);
+                warn!(
+                    "D1 database {database_identifier} rejected a statement of {} entries as too large, retrying with {entries_per_statement}: {err:#}",
+                    options.entries_per_statement
+                );
+                options = Cow::Owned(UploadOptions {
+                    entries_per_statement,
+                    ..options.into_owned()
+                });
+            }
+            result => return result,
+        }
+    }
+}
+
+/// The `x-amz-meta-*` headers, without their prefix, of the script staged for `entries`:
+/// `options.r2_metadata` followed by the file's entry count and first pda, or nothing when no
+/// metadata was asked for.
+fn r2_metadata(entries: &[PdaSqlite], options: &UploadOptions) -> Vec<(String, String)> {
+    if options.r2_metadata.is_empty() {
+        return Vec::new();
+    }
+    let mut metadata = options.r2_metadata.clone();
+    metadata.push(("entry-count".to_owned(), entries.len().to_string()));
+    if let Some(first) = entries.first() {
+        metadata.push(("first-pda".to_owned(), first.pda.to_string()));
+    }
+    metadata
+}
+
+/// Whether a failed import was rejected for a statement exceeding D1's or SQLite's size
+/// limits, as opposed to anything a smaller statement would not fix.
+fn is_statement_too_large(err: &eyre::Report) -> bool {
+    let message = format!("{err:#}").to_ascii_lowercase();
+    ["too big", "toobig", "too large", "too long"]
+        .iter()
+        .any(|needle| message.contains(needle))
+}
+
+/// Uploads one rendered script and imports it, through init → upload → ingest → poll.
+async fn import_script(
+    api_base: &str,
+    api_token: &str,
+    account_identifier: &str,
+    database_identifier: &str,
+    entries: &[PdaSqlite],
+    mut script: String,
+    options: &UploadOptions,
+) -> Result<ImportReport> {
+    let entry_count = entries.len();
+    let mut checksum = format!("{:x}", md5_compute(script.as_bytes()));
     info!(
-        "Uploading {} entries ({} bytes) to D1 database {database_identifier}",
-        entries.len(),
-        payload_size_bytes
+        "Uploading {entry_count} entries ({} bytes) to D1 database {database_identifier}",
+        script.len()
     );
 
-    let sql_payload = script.into_bytes();
-    let http = HttpClient::builder()
-        .user_agent("pda-directory-uploader/1.0")
-        .build()
-        .wrap_err("failed to construct HTTP client")?;
+    let http = http_client()?;
 
     let import_url = format!(
-        "https://api.cloudflare.com/client/v4/accounts/{account_identifier}/d1/database/{database_identifier}/import"
+        "{api_base}/accounts/{account_identifier}/d1/database/{database_identifier}/import"
     );
 
-    let init_response: CloudflareResponse<InitResult> = http
-        .post(&import_url)
-        .header(CONTENT_TYPE, "application/json")
-        .header(AUTHORIZATION, format!("Bearer {api_token}"))
-        .json(&json!({
-            "action": "init",
-            "etag": checksum,
-        }))
-        .send()
-        .await
-        .wrap_err("failed to send D1 init request")?
-        .error_for_status()
-        .wrap_err("D1 init request returned error status")?
-        .json::<CloudflareResponse<InitResult>>()
-        .await
-        .wrap_err("failed to deserialize D1 init response")?;
-
-    init_response.ensure_success()?;
-
-    let init_result = unpack_response(init_response)?;
+    let mut init_result = init_import(&http, &import_url, api_token, &checksum).await?;
+    if options.force_reupload && matches!(init_result, InitResult::Status(_)) {
+        warn!(
+            "D1 database {database_identifier} already has this file; --force-reupload is set, uploading a fresh copy"
+        );
+        // D1 recognizes staged files by etag, so a trailing comment gives the script a new
+        // etag without changing what it does.
+        let nonce = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        script.push_str(&format!("-- reupload {nonce}\n"));
+        checksum = format!("{:x}", md5_compute(script.as_bytes()));
+        init_result = init_import(&http, &import_url, api_token, &checksum).await?;
+    }
+    let sql_payload = script.into_bytes();
 
+    let mut uploaded_bytes = 0;
     let import_status = match init_result {
         InitResult::Upload(init_result) => {
             debug!(
@@ -136,12 +529,18 @@ pub async fn upload_to_d1(
                 init_result.upload_url, init_result.filename
             );
 
-            let upload_response = http
-                .put(&init_result.upload_url)
-                .body(sql_payload)
-                .send()
-                .await
-                .wrap_err("failed to upload SQL payload to R2")?
+            let payload_size_bytes = sql_payload.len();
+            // R2's ETag is the md5 of the body alone, so these headers leave it as expected.
+            let mut request = http.put(&init_result.upload_url).body(sql_payload);
+            if let Some(content_type) = options.r2_content_type.as_deref() {
+                request = request.header(CONTENT_TYPE, content_type);
+            }
+            for (key, value) in r2_metadata(entries, options) {
+                request = request.header(format!("x-amz-meta-{key}"), value);
+            }
+            let upload_response = request
+                .send_with_retry("R2 upload")
+                .await?
                 .error_for_status()
                 .wrap_err("D1 upload to R2 returned error status")?;
 
@@ -158,9 +557,15 @@ pub async fn upload_to_d1(
                 ));
             }
 
+            // The ETag is the md5 of every byte R2 stored, so a match also rules out a
+            // truncated upload.
             debug!("Verified upload etag {response_etag}");
+            info!(
+                "Uploaded {payload_size_bytes} bytes to R2 for D1 database {database_identifier}"
+            );
+            uploaded_bytes = payload_size_bytes as u64;
 
-            let ingest_response: CloudflareResponse<ImportStatus> = http
+            let response = http
                 .post(&import_url)
                 .header(CONTENT_TYPE, "application/json")
                 .header(AUTHORIZATION, format!("Bearer {api_token}"))
@@ -169,18 +574,20 @@ pub async fn upload_to_d1(
                     "etag": checksum,
                     "filename": init_result.filename,
                 }))
-                .send()
-                .await
-                .wrap_err("failed to send D1 ingest request")?
-                .error_for_status()
-                .wrap_err("D1 ingest request returned error status")?
-                .json::<CloudflareResponse<ImportStatus>>()
-                .await
-                .wrap_err("failed to deserialize D1 ingest response")?;
+                .send_with_retry("D1 ingest")
+                .await?;
+            let ingest_response: CloudflareResponse<ImportStatus> =
+                read_api_response(response, "D1 ingest").await?;
 
             ingest_response.ensure_success()?;
 
-            unpack_response(ingest_response)?
+            let status = unpack_response(ingest_response)?;
+            if let Some(bookmark) = status.at_bookmark.as_deref() {
+                info!(
+                    "D1 import for database {database_identifier} started at bookmark {bookmark} (finish an interrupted poll with `continue-import --database-id {database_identifier} --bookmark {bookmark}`)"
+                );
+            }
+            status
         }
         InitResult::Status(status) => {
             info!(
@@ -190,23 +597,299 @@ pub async fn upload_to_d1(
         }
     };
 
-    poll_import_until_complete(
+    let report = poll_import_until_complete(
         &http,
         &import_url,
         api_token,
         database_identifier,
         import_status,
     )
+    .await?;
+    Ok(ImportReport {
+        uploaded_bytes,
+        ..report
+    })
+}
+
+/// Starts an import of the script with md5 `checksum`, which either hands back an R2 upload
+/// URL or, when D1 already has a file with that etag, its import status.
+async fn init_import(
+    http: &HttpClient,
+    import_url: &str,
+    api_token: &str,
+    checksum: &str,
+) -> Result<InitResult> {
+    let response = http
+        .post(import_url)
+        .header(CONTENT_TYPE, "application/json")
+        .header(AUTHORIZATION, format!("Bearer {api_token}"))
+        .json(&json!({
+            "action": "init",
+            "etag": checksum,
+        }))
+        .send_with_retry("D1 init")
+        .await?;
+    let init_response: CloudflareResponse<InitResult> =
+        read_api_response(response, "D1 init").await?;
+
+    init_response.ensure_success()?;
+
+    unpack_response(init_response)
+}
+
+/// Polls an import already running on a D1 database to completion without uploading anything,
+/// for runs that died while polling. `bookmark` is the last `at_bookmark` the import reported,
+/// if known. An import that already finished reports as complete.
+pub async fn continue_d1_import(
+    api_base: &str,
+    api_token: &str,
+    account_identifier: &str,
+    database_identifier: &str,
+    bookmark: Option<String>,
+) -> Result<ImportReport> {
+    let http = http_client()?;
+    let import_url = format!(
+        "{api_base}/accounts/{account_identifier}/d1/database/{database_identifier}/import"
+    );
+    info!("Continuing D1 import for database {database_identifier} (bookmark: {bookmark:?})");
+
+    let status = ImportStatus {
+        success: true,
+        error: None,
+        errors: Vec::new(),
+        messages: Vec::new(),
+        status: None,
+        at_bookmark: bookmark,
+    };
+    poll_import_until_complete(&http, &import_url, api_token, database_identifier, status).await
+}
+
+/// Runs a single SQL statement through the D1 query API and returns the rows of its result set.
+pub async fn query_d1(
+    api_base: &str,
+    api_token: &str,
+    account_identifier: &str,
+    database_identifier: &str,
+    sql: &str,
+) -> Result<Vec<Map<String, Value>>> {
+    query_d1_with_params(
+        api_base,
+        api_token,
+        account_identifier,
+        database_identifier,
+        sql,
+        &[],
+    )
     .await
 }
 
+/// Like [`query_d1`], binding `params` to the `?` placeholders of `sql`. The query API only
+/// carries JSON strings and numbers, so blobs are bound as hex text and decoded in SQL with
+/// `unhex(?)`.
+pub async fn query_d1_with_params(
+    api_base: &str,
+    api_token: &str,
+    account_identifier: &str,
+    database_identifier: &str,
+    sql: &str,
+    params: &[Value],
+) -> Result<Vec<Map<String, Value>>> {
+    debug!("Querying D1 database {database_identifier}: {sql} {params:?}");
+    let mut body = json!({ "sql": sql });
+    if !params.is_empty() {
+        body["params"] = Value::from(params);
+    }
+    let results = post_query(
+        api_base,
+        api_token,
+        account_identifier,
+        database_identifier,
+        &body,
+    )
+    .await?;
+    let result = results
+        .into_iter()
+        .next()
+        .ok_or_else(|| eyre!("D1 query returned no result set"))?;
+    if !result.success {
+        return Err(eyre!("D1 query failed: {sql}"));
+    }
+    Ok(result.results)
+}
+
+/// Posts `body` to the D1 query API, returning one result set per statement in its `sql`.
+async fn post_query(
+    api_base: &str,
+    api_token: &str,
+    account_identifier: &str,
+    database_identifier: &str,
+    body: &Value,
+) -> Result<Vec<QueryResult>> {
+    let query_url =
+        format!("{api_base}/accounts/{account_identifier}/d1/database/{database_identifier}/query");
+    let response = http_client()?
+        .post(&query_url)
+        .header(CONTENT_TYPE, "application/json")
+        .header(AUTHORIZATION, format!("Bearer {api_token}"))
+        .json(body)
+        .send_with_retry("D1 query")
+        .await?;
+    let response: CloudflareResponse<Vec<QueryResult>> =
+        read_api_response(response, "D1 query").await?;
+    unpack_response(response)
+}
+
+/// A row of the D1 table as read back by [`lookup_d1_pda`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredEntry {
+    pub program_id: Address,
+    pub seed_count: u64,
+    /// The stored `seed_bytes` value, still encoded.
+    pub seed_bytes: Vec<u8>,
+}
+
+/// Reads the row of `pda` from `table` in a D1 database, if there is one. The pda is bound as
+/// a parameter and matched through the primary key; blob columns come back hex-encoded.
+pub async fn lookup_d1_pda(
+    api_base: &str,
+    api_token: &str,
+    account_identifier: &str,
+    database_identifier: &str,
+    table: &str,
+    pda: &Address,
+) -> Result<Option<StoredEntry>> {
+    let rows = query_d1_with_params(
+        api_base,
+        api_token,
+        account_identifier,
+        database_identifier,
+        &format!(
+            "SELECT hex(program_id) AS program_id, seed_count, hex(seed_bytes) AS seed_bytes FROM {table} WHERE pda = unhex(?1)"
+        ),
+        &[Value::from(hex::encode(pda.as_ref()))],
+    )
+    .await?;
+    let Some(row) = rows.first() else {
+        return Ok(None);
+    };
+
+    let hex_column = |key: &str| {
+        row.get(key)
+            .and_then(Value::as_str)
+            .ok_or_else(|| eyre!("lookup row missing {key}: {row:?}"))
+            .and_then(|text| {
+                hex::decode(text).wrap_err_with(|| format!("lookup row has invalid {key}"))
+            })
+    };
+    Ok(Some(StoredEntry {
+        program_id: decode_address(
+            &hex_column("program_id")?,
+            "program_id",
+            format_args!("D1 database {database_identifier}"),
+        )?,
+        seed_count: row
+            .get("seed_count")
+            .and_then(Value::as_u64)
+            .ok_or_else(|| eyre!("lookup row missing seed_count: {row:?}"))?,
+        seed_bytes: hex_column("seed_bytes")?,
+    }))
+}
+
+/// Reads every pda in `table` of a D1 database, `page_size` rows per query. Pages are keyed on
+/// the last pda read rather than an offset, so each query is a primary key range scan.
+pub async fn d1_pdas(
+    api_base: &str,
+    api_token: &str,
+    account_identifier: &str,
+    database_identifier: &str,
+    table: &str,
+    page_size: usize,
+) -> Result<HashSet<Address>> {
+    let sql = format!(
+        "SELECT hex(pda) AS pda FROM {table} WHERE pda > unhex(?1) ORDER BY pda LIMIT {page_size}"
+    );
+    let mut pdas = HashSet::new();
+    let mut last = String::new();
+    loop {
+        let rows = query_d1_with_params(
+            api_base,
+            api_token,
+            account_identifier,
+            database_identifier,
+            &sql,
+            &[Value::from(last.as_str())],
+        )
+        .await?;
+        let page_len = rows.len();
+        for row in rows {
+            let Some(Value::String(pda_hex)) = row.get("pda") else {
+                return Err(eyre!("pda page row missing pda: {row:?}"));
+            };
+            let pda = hex::decode(pda_hex).wrap_err("pda page row has invalid hex")?;
+            pdas.insert(decode_address(
+                &pda,
+                "pda",
+                format_args!("D1 database {database_identifier}"),
+            )?);
+            last.clone_from(pda_hex);
+        }
+        debug!(
+            "Read {page_len} pdas from D1 database {database_identifier} ({} so far)",
+            pdas.len()
+        );
+        if page_len < page_size {
+            return Ok(pdas);
+        }
+    }
+}
+
+/// Reads the columns of `table` in a D1 database via `PRAGMA table_info`.
+pub async fn d1_table_columns(
+    api_base: &str,
+    api_token: &str,
+    account_identifier: &str,
+    database_identifier: &str,
+    table: &str,
+) -> Result<Vec<Column>> {
+    let rows = query_d1(
+        api_base,
+        api_token,
+        account_identifier,
+        database_identifier,
+        &format!("PRAGMA table_info({table})"),
+    )
+    .await?;
+
+    rows.iter()
+        .map(|row| {
+            let text = |key: &str| {
+                row.get(key)
+                    .and_then(Value::as_str)
+                    .map(str::to_owned)
+                    .ok_or_else(|| eyre!("PRAGMA table_info row missing {key}: {row:?}"))
+            };
+            Ok(Column {
+                name: text("name")?,
+                decl_type: text("type")?,
+            })
+        })
+        .collect()
+}
+
+pub(crate) fn http_client() -> Result<HttpClient> {
+    HttpClient::builder()
+        .user_agent("pda-directory-uploader/1.0")
+        .build()
+        .wrap_err("failed to construct HTTP client")
+}
+
 async fn poll_import_until_complete(
     http: &HttpClient,
     import_url: &str,
     api_token: &str,
     database_identifier: &str,
     mut status: ImportStatus,
-) -> Result<()> {
+) -> Result<ImportReport> {
     const MAX_ATTEMPTS: usize = 300;
     let mut attempts = 0usize;
     let auth_header = format!("Bearer {api_token}");
@@ -223,36 +906,39 @@ async fn poll_import_until_complete(
             }
         }
 
-        if let Some(err) = status.error.as_deref() {
-            if err == "Not currently importing anything." {
-                info!("D1 import already complete for database {database_identifier}");
-                return Ok(());
-            }
-        }
-
-        if let Some(status_text) = status.status.as_deref() {
-            let status_lower = status_text.to_ascii_lowercase();
-            if status_lower == "complete" {
-                info!("D1 import completed for database {database_identifier}");
-                return Ok(());
-            }
-
-            if status_lower.contains("fail") || status_lower.contains("error") {
-                let message = import_status_error_message(&status);
-                return Err(eyre!("D1 import failed: {message}"));
+        match import_state(&status) {
+            ImportState::Complete(report) => {
+                let report = ImportReport {
+                    final_bookmark: status.at_bookmark.clone(),
+                    ..report
+                };
+                if let Some(bookmark) = report.final_bookmark.as_deref() {
+                    info!(
+                        "D1 database {database_identifier} can be restored to this import with time-travel bookmark {bookmark}"
+                    );
+                }
+                if report.is_clean() {
+                    info!("D1 import completed for database {database_identifier}");
+                } else {
+                    warn!(
+                        "D1 import completed for database {database_identifier} with {} failed statement(s) and {} failed row(s): {}",
+                        report.failed_statements,
+                        report.failed_rows,
+                        status.errors.join(", ")
+                    );
+                }
+                return Ok(report);
             }
-        }
-
-        if !status.success {
-            let message = import_status_error_message(&status);
-            return Err(eyre!("D1 import failed: {message}"));
+            ImportState::Failed(message) => return Err(eyre!("D1 import failed: {message}")),
+            ImportState::Pending => {}
         }
 
         attempts += 1;
         if attempts >= MAX_ATTEMPTS {
-            return Err(eyre!(
+            return Err(TimedOut(format!(
                 "Timed out after {MAX_ATTEMPTS} attempts while polling D1 import"
-            ));
+            ))
+            .into());
         }
 
         let bookmark = status.at_bookmark.clone();
@@ -263,7 +949,7 @@ async fn poll_import_until_complete(
 
         sleep(Duration::from_secs(1)).await;
 
-        let poll_response: CloudflareResponse<ImportStatus> = http
+        let response = http
             .post(import_url)
             .header(CONTENT_TYPE, "application/json")
             .header(AUTHORIZATION, auth_header.as_str())
@@ -271,14 +957,10 @@ async fn poll_import_until_complete(
                 "action": "poll",
                 "current_bookmark": bookmark,
             }))
-            .send()
-            .await
-            .wrap_err("failed to send D1 poll request")?
-            .error_for_status()
-            .wrap_err("D1 poll request returned error status")?
-            .json::<CloudflareResponse<ImportStatus>>()
-            .await
-            .wrap_err("failed to deserialize D1 poll response")?;
+            .send_with_retry("D1 poll")
+            .await?;
+        let poll_response: CloudflareResponse<ImportStatus> =
+            read_api_response(response, "D1 poll").await?;
 
         poll_response.ensure_success()?;
 
@@ -286,6 +968,148 @@ async fn poll_import_until_complete(
     }
 }
 
+/// Statements and rows a completed D1 import reported as failed while the rest of the
+/// import went through, the bytes uploaded to R2 for it, and where it left the database.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ImportReport {
+    pub failed_statements: usize,
+    pub failed_rows: usize,
+    /// Zero when D1 already had the file and nothing was uploaded.
+    pub uploaded_bytes: u64,
+    /// `at_bookmark` of the completed import: a D1 time-travel point restoring the database to
+    /// its state right after the import. Across several imports, the latest one reported.
+    pub final_bookmark: Option<String>,
+}
+
+impl ImportReport {
+    pub fn is_clean(&self) -> bool {
+        self.failed_statements == 0 && self.failed_rows == 0
+    }
+
+    pub fn add(&mut self, other: ImportReport) {
+        self.failed_statements += other.failed_statements;
+        self.failed_rows += other.failed_rows;
+        self.uploaded_bytes += other.uploaded_bytes;
+        if other.final_bookmark.is_some() {
+            self.final_bookmark = other.final_bookmark;
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum ImportState {
+    Complete(ImportReport),
+    Failed(String),
+    Pending,
+}
+
+/// Classifies one import status payload. A `complete` status still counts as complete when it
+/// carries errors: those are statements that failed while the others were applied.
+fn import_state(status: &ImportStatus) -> ImportState {
+    if let Some(err) = status.error.as_deref()
+        && err == "Not currently importing anything."
+    {
+        return ImportState::Complete(ImportReport::default());
+    }
+
+    if let Some(status_text) = status.status.as_deref() {
+        let status_lower = status_text.to_ascii_lowercase();
+        if status_lower == "complete" {
+            return ImportState::Complete(partial_failures(status));
+        }
+
+        if status_lower.contains("fail") || status_lower.contains("error") {
+            return ImportState::Failed(import_status_error_message(status));
+        }
+    }
+
+    if !status.success {
+        return ImportState::Failed(import_status_error_message(status));
+    }
+
+    ImportState::Pending
+}
+
+/// Counts failed statements (one per reported error) and failed rows, the latter parsed from
+/// messages of the form `"<n> rows failed ..."` in either `errors` or `messages`.
+fn partial_failures(status: &ImportStatus) -> ImportReport {
+    let failed_rows = status
+        .errors
+        .iter()
+        .chain(&status.messages)
+        .filter(|text| text.to_ascii_lowercase().contains("fail"))
+        .filter_map(|text| {
+            let words: Vec<&str> = text.split_whitespace().collect();
+            words.windows(2).find_map(|pair| {
+                let count = pair[0].parse::<usize>().ok()?;
+                pair[1]
+                    .to_ascii_lowercase()
+                    .starts_with("row")
+                    .then_some(count)
+            })
+        })
+        .sum();
+
+    ImportReport {
+        failed_statements: status.errors.len(),
+        failed_rows,
+        ..ImportReport::default()
+    }
+}
+
+/// Attempts per request when Cloudflare answers 429 or 503.
+const RATE_LIMIT_ATTEMPTS: u32 = 5;
+/// Upper bound on a single `Retry-After` wait, so a bogus header cannot stall the run.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(300);
+
+pub(crate) trait SendWithRetry {
+    /// Sends the request, retrying 429 and 503 responses up to `RATE_LIMIT_ATTEMPTS` times.
+    /// Each wait honours `Retry-After` (delta-seconds or HTTP-date), falling back to
+    /// exponential backoff when the header is missing or unparsable.
+    async fn send_with_retry(self, what: &str) -> Result<Response>;
+}
+
+impl SendWithRetry for RequestBuilder {
+    async fn send_with_retry(self, what: &str) -> Result<Response> {
+        let mut attempt = 1;
+        loop {
+            let request = self
+                .try_clone()
+                .ok_or_else(|| eyre!("{what} request cannot be retried"))?;
+            let response = request
+                .send()
+                .await
+                .wrap_err_with(|| format!("failed to send {what} request"))?;
+
+            let status = response.status();
+            let retryable = status == StatusCode::TOO_MANY_REQUESTS
+                || status == StatusCode::SERVICE_UNAVAILABLE;
+            if !retryable || attempt >= RATE_LIMIT_ATTEMPTS {
+                return Ok(response);
+            }
+
+            let wait = retry_after(response.headers(), SystemTime::now())
+                .unwrap_or_else(|| Duration::from_secs(1 << attempt.min(6)))
+                .min(MAX_RETRY_AFTER);
+            warn!(
+                "{what} request returned {status} (attempt {attempt}/{RATE_LIMIT_ATTEMPTS}), retrying in {wait:?}"
+            );
+            sleep(wait).await;
+            attempt += 1;
+        }
+    }
+}
+
+/// Parses a `Retry-After` header in either of its forms, relative to `now`.
+fn retry_after(headers: &HeaderMap, now: SystemTime) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = httpdate::parse_http_date(value).ok()?;
+    Some(at.duration_since(now).unwrap_or_default())
+}
+
 fn import_status_error_message(status: &ImportStatus) -> String {
     if let Some(err) = status.error.as_ref() {
         return err.clone();
@@ -298,45 +1122,35 @@ fn import_status_error_message(status: &ImportStatus) -> String {
     "unknown error".to_owned()
 }
 
-fn build_insert_script(entries: &[PdaSqlite]) -> Result<Option<String>> {
+pub fn build_insert_script(
+    entries: &[PdaSqlite],
+    options: &UploadOptions,
+) -> Result<Option<String>> {
     if entries.is_empty() {
         return Ok(None);
     }
 
-    const CHUNK_SIZE: usize = 10;
-    let mut script = String::with_capacity(entries.len() * 256);
-
-    for chunk in entries.chunks(CHUNK_SIZE) {
-        script.push_str(
-            "INSERT OR IGNORE INTO pda_registry (pda, program_id, seed_count, seed_bytes) VALUES\n",
-        );
+    let mut script = String::with_capacity(script_capacity(entries, options));
+    if options.transactional {
+        script.push_str("BEGIN;\n");
+    }
+    let mut columns = String::from("pda, program_id, seed_count, seed_bytes");
+    if options.with_seeds_json {
+        columns.push_str(", seeds_json");
+    }
+    if options.with_checksums {
+        columns.push_str(", seed_checksum");
+    }
 
-        fn encode_seeds_for_storage(seeds: &[Vec<u8>]) -> Vec<u8> {
-            let total_seed_bytes = seeds.iter().map(|seed| seed.len()).sum::<usize>();
-            let mut encoded = Vec::with_capacity(
-                total_seed_bytes + (seeds.len() + 1) * std::mem::size_of::<u32>(),
-            );
-            encoded.extend_from_slice(&(seeds.len() as u32).to_le_bytes());
-            for seed in seeds {
-                encoded.extend_from_slice(&(seed.len() as u32).to_le_bytes());
-                encoded.extend_from_slice(seed);
-            }
-            encoded
-        }
+    for chunk in entries.chunks(options.entries_per_statement.max(1)) {
+        script.push_str(&format!(
+            "INSERT OR IGNORE INTO {} ({columns}) VALUES\n",
+            options.table_name
+        ));
 
         for (index, entry) in chunk.iter().enumerate() {
-            let pda_blob = to_blob_literal(entry.pda.as_ref());
-            let program_blob = to_blob_literal(entry.program_id.as_ref());
-            let seed_bytes = encode_seeds_for_storage(&entry.seeds);
-            let seed_blob = to_blob_literal(&seed_bytes);
-
-            script.push_str(&format!(
-                "({pda}, {program}, {seed_count}, {seed})",
-                pda = pda_blob,
-                program = program_blob,
-                seed_count = entry.seeds.len(),
-                seed = seed_blob
-            ));
+            push_row(&mut script, entry, options)
+                .wrap_err_with(|| format!("failed to render entry for pda {}", entry.pda))?;
 
             if index + 1 == chunk.len() {
                 script.push_str(";\n");
@@ -346,22 +1160,119 @@ fn build_insert_script(entries: &[PdaSqlite]) -> Result<Option<String>> {
         }
     }
 
+    if options.transactional {
+        script.push_str("COMMIT;\n");
+    }
     Ok(Some(script))
 }
 
-fn to_blob_literal(bytes: &[u8]) -> String {
-    if bytes.is_empty() {
-        return "X''".to_owned();
+/// Appends the `VALUES` tuple of one entry, failing for an entry whose seeds the stored
+/// encoding cannot hold.
+fn push_row(script: &mut String, entry: &PdaSqlite, options: &UploadOptions) -> Result<()> {
+    let seed_bytes = encode_seeds_for_storage(&entry.seeds)?;
+
+    script.push('(');
+    push_blob_literal(script, entry.pda.as_ref());
+    script.push_str(", ");
+    push_blob_literal(script, entry.program_id.as_ref());
+    script.push_str(&format!(", {}, ", entry.seeds.len()));
+    push_blob_literal(script, &seed_bytes);
+
+    if options.with_seeds_json {
+        let hex_seeds = entry.seeds.iter().map(hex::encode).collect::<Vec<_>>();
+        let seeds_json =
+            serde_json::to_string(&hex_seeds).wrap_err("failed to encode seeds as JSON")?;
+        script.push_str(", ");
+        script.push_str(&to_text_literal(&seeds_json));
+    }
+    if options.with_checksums {
+        script.push_str(&format!(", {}", schema::seed_checksum(&seed_bytes)));
+    }
+    script.push(')');
+    Ok(())
+}
+
+/// The `seed_bytes` column: the seed count, then each seed prefixed by its length, all as
+/// little-endian `u32`s.
+fn encode_seeds_for_storage(seeds: &[Vec<u8>]) -> Result<Vec<u8>> {
+    let total_seed_bytes = seeds.iter().map(|seed| seed.len()).sum::<usize>();
+    let mut encoded =
+        Vec::with_capacity(total_seed_bytes + (seeds.len() + 1) * std::mem::size_of::<u32>());
+    let prefix = |len: usize| {
+        u32::try_from(len)
+            .map(u32::to_le_bytes)
+            .map_err(|_| eyre!("{len} does not fit the u32 prefix of the seed encoding"))
+    };
+    encoded.extend_from_slice(&prefix(seeds.len())?);
+    for seed in seeds {
+        encoded.extend_from_slice(&prefix(seed.len())?);
+        encoded.extend_from_slice(seed);
     }
+    Ok(encoded)
+}
+
+/// Renders `entry` as [`build_insert_script`] would, only to check that it can be.
+pub fn check_renderable(entry: &PdaSqlite, options: &UploadOptions) -> Result<()> {
+    push_row(&mut String::new(), entry, options)
+}
+
+/// Splits off the entries `render` fails on, with their errors, so `--skip-unserializable`
+/// can drop them instead of failing their whole chunk. Pass [`check_renderable`].
+pub fn split_unrenderable(
+    entries: Vec<PdaSqlite>,
+    render: impl Fn(&PdaSqlite) -> Result<()>,
+) -> (Vec<PdaSqlite>, Vec<(PdaSqlite, eyre::Report)>) {
+    let mut renderable = Vec::with_capacity(entries.len());
+    let mut failed = Vec::new();
+    for entry in entries {
+        match render(&entry) {
+            Ok(()) => renderable.push(entry),
+            Err(err) => failed.push((entry, err)),
+        }
+    }
+    (renderable, failed)
+}
+
+/// Generous estimate of the script [`build_insert_script`] produces for `entries`, so the
+/// script is allocated once even when single seeds run to megabytes.
+fn script_capacity(entries: &[PdaSqlite], options: &UploadOptions) -> usize {
+    // `INSERT OR IGNORE INTO <table> (<columns>) VALUES` plus the statement terminator.
+    let statement_len = 128 + options.table_name.len();
+    let statements = entries.len().div_ceil(options.entries_per_statement.max(1));
+    let rows: usize = entries
+        .iter()
+        .map(|entry| {
+            let seed_lens = entry.seeds.iter().map(Vec::len);
+            let seed_bytes_len = 4 + seed_lens.clone().map(|len| 4 + len).sum::<usize>();
+            // Three `X'..'` blobs, the seed count and separators.
+            let mut row = 2 * (32 + 32 + seed_bytes_len) + 32;
+            if options.with_seeds_json {
+                row += 8 + seed_lens.map(|len| 2 * len + 3).sum::<usize>();
+            }
+            if options.with_checksums {
+                row += 12;
+            }
+            row
+        })
+        .sum();
+    statements * statement_len + rows + "BEGIN;\nCOMMIT;\n".len()
+}
 
-    let mut literal = String::with_capacity(bytes.len() * 2 + 3);
-    literal.push_str("X'");
-    for byte in bytes {
-        use std::fmt::Write as _;
-        write!(&mut literal, "{byte:02X}").expect("writing to string cannot fail");
+/// Appends `bytes` to `script` as an SQL blob literal, `X'..'` in upper-case hex.
+fn push_blob_literal(script: &mut String, bytes: &[u8]) {
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+
+    script.reserve(bytes.len() * 2 + 3);
+    script.push_str("X'");
+    for &byte in bytes {
+        script.push(char::from(HEX[usize::from(byte >> 4)]));
+        script.push(char::from(HEX[usize::from(byte & 0x0F)]));
     }
-    literal.push('\'');
-    literal
+    script.push('\'');
+}
+
+fn to_text_literal(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "''"))
 }
 
 fn unpack_response<T>(response: CloudflareResponse<T>) -> Result<T>
@@ -411,7 +1322,15 @@ where
             return Ok(());
         }
 
-        Err(eyre!("Cloudflare API error: {}", self.error_message()))
+        Err(self.api_error())
+    }
+
+    fn api_error(&self) -> eyre::Report {
+        ApiError {
+            codes: self.errors.iter().filter_map(|err| err.code).collect(),
+            message: self.error_message(),
+        }
+        .into()
     }
 
     fn into_result(self) -> Result<T> {
@@ -419,11 +1338,71 @@ where
             self.result
                 .ok_or_else(|| eyre!("Cloudflare API response missing result payload"))
         } else {
-            Err(eyre!("Cloudflare API error: {}", self.error_message()))
+            Err(self.api_error())
         }
     }
 }
 
+/// Reads a Cloudflare API response of `what`. Error statuses still carry the API's error body,
+/// which is parsed so the failure keeps its [`ApiError`] codes.
+async fn read_api_response<T>(response: Response, what: &str) -> Result<CloudflareResponse<T>>
+where
+    T: DeserializeOwned + std::fmt::Debug,
+{
+    let status = response.status();
+    if status.is_client_error() || status.is_server_error() {
+        let body = response.text().await.unwrap_or_default();
+        return match serde_json::from_str::<CloudflareResponse<Value>>(&body) {
+            Ok(parsed) if !parsed.errors.is_empty() => Err(parsed.api_error())
+                .wrap_err_with(|| format!("{what} request returned error status {status}")),
+            _ => Err(eyre!(
+                "{what} request returned error status {status}: {body}"
+            )),
+        };
+    }
+    response
+        .json::<CloudflareResponse<T>>()
+        .await
+        .wrap_err_with(|| format!("failed to deserialize {what} response"))
+}
+
+/// Cloudflare API error codes that no retry can fix: bad or under-scoped credentials and
+/// unknown accounts or databases.
+pub const FATAL_API_ERROR_CODES: &[u64] = &[
+    7000,  // no route for that URI
+    7003,  // could not route to the account or database
+    7404,  // database not found
+    9103,  // unknown auth key or email
+    9106,  // missing auth headers
+    9109,  // invalid access token
+    10000, // authentication error, e.g. a token without the D1 scope
+];
+
+/// A failed Cloudflare API response with the numeric codes of its errors.
+#[derive(Debug)]
+pub struct ApiError {
+    pub codes: Vec<u64>,
+    message: String,
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Cloudflare API error: {}", self.message)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+/// Whether `err` is a Cloudflare API error that retrying cannot fix: one carrying a code from
+/// [`FATAL_API_ERROR_CODES`] or from `halt_codes`, the codes the operator forced fatal. Any
+/// other error, including API errors with unknown codes, is retryable.
+pub fn is_fatal_api_error(err: &eyre::Report, halt_codes: &[u64]) -> bool {
+    err.chain()
+        .filter_map(|cause| cause.downcast_ref::<ApiError>())
+        .flat_map(|api_error| &api_error.codes)
+        .any(|code| FATAL_API_ERROR_CODES.contains(code) || halt_codes.contains(code))
+}
+
 fn none<T>() -> Option<T> {
     None
 }
@@ -434,6 +1413,13 @@ struct CloudflareApiError {
     message: String,
 }
 
+#[derive(Debug, Deserialize)]
+struct QueryResult {
+    #[serde(default)]
+    results: Vec<Map<String, Value>>,
+    success: bool,
+}
+
 #[derive(Debug, Deserialize)]
 struct InitUploadResult {
     upload_url: String,
@@ -461,3 +1447,295 @@ struct ImportStatus {
     #[serde(default)]
     at_bookmark: Option<String>,
 }
+
+#[cfg(test)]
+mod tests {
+    use solana_address::Address;
+
+    use super::*;
+
+    fn entry(pda: u8, program_id: u8, seeds: &[&[u8]]) -> PdaSqlite {
+        PdaSqlite {
+            pda: Address::from([pda; 32]),
+            program_id: Address::from([program_id; 32]),
+            seeds: seeds.iter().map(|seed| seed.to_vec()).collect(),
+        }
+    }
+
+    #[test]
+    fn unrenderable_entries_are_split_off_with_their_errors() {
+        let entries = vec![
+            entry(1, 9, &[b"a"]),
+            entry(2, 9, &[b"b"]),
+            entry(3, 9, &[b"c"]),
+        ];
+        let options = UploadOptions::default();
+        // Stands in for an entry whose seeds fail to encode.
+        let render = |entry: &PdaSqlite| {
+            if entry.pda == Address::from([2; 32]) {
+                return Err(eyre!("mocked seed encoding failure"));
+            }
+            check_renderable(entry, &options)
+        };
+
+        let (renderable, failed) = split_unrenderable(entries.clone(), render);
+        let pdas =
+            |entries: &[PdaSqlite]| entries.iter().map(|entry| entry.pda).collect::<Vec<_>>();
+        assert_eq!(pdas(&renderable), vec![entries[0].pda, entries[2].pda]);
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].0.pda, entries[1].pda);
+        assert_eq!(failed[0].1.to_string(), "mocked seed encoding failure");
+
+        let script = build_insert_script(&renderable, &options).unwrap().unwrap();
+        assert_eq!(script.matches("X'0101").count(), 1);
+        assert!(!script.contains("X'0202"));
+    }
+
+    #[test]
+    fn api_error_codes_classify_as_fatal_or_retryable() {
+        let response: CloudflareResponse<Value> = serde_json::from_str(
+            r#"{"success": false, "errors": [{"code": 7500, "message": "D1_ERROR"}]}"#,
+        )
+        .unwrap();
+        let err = response.into_result().unwrap_err().wrap_err("chunk failed");
+        assert_eq!(
+            err.root_cause().to_string(),
+            "Cloudflare API error: 7500: D1_ERROR"
+        );
+        assert!(!is_fatal_api_error(&err, &[]));
+        assert!(is_fatal_api_error(&err, &[7500]));
+
+        let response: CloudflareResponse<Value> = serde_json::from_str(
+            r#"{"success": false, "errors": [{"code": 10000, "message": "Authentication error"}]}"#,
+        )
+        .unwrap();
+        assert!(is_fatal_api_error(
+            &response.into_result().unwrap_err(),
+            &[]
+        ));
+        assert!(!is_fatal_api_error(&eyre!("connection reset"), &[10000]));
+    }
+
+    fn single_entry() -> Vec<PdaSqlite> {
+        vec![entry(1, 2, &[b"vault", &[3, 4]])]
+    }
+
+    fn empty_seeds() -> Vec<PdaSqlite> {
+        vec![entry(5, 6, &[]), entry(7, 6, &[b""])]
+    }
+
+    fn batch_boundary() -> Vec<PdaSqlite> {
+        (0..11u8)
+            .map(|index| entry(index, 0xAA, &[&[index]]))
+            .collect()
+    }
+
+    fn assert_golden(entries: &[PdaSqlite], options: &UploadOptions, golden: &str) {
+        let script = build_insert_script(entries, options)
+            .unwrap()
+            .expect("non-empty entries must produce a script");
+        assert_eq!(script, golden);
+    }
+
+    fn status(payload: serde_json::Value) -> ImportStatus {
+        serde_json::from_value(payload).unwrap()
+    }
+
+    #[test]
+    fn clean_import_is_complete() {
+        let status = status(json!({
+            "success": true,
+            "status": "complete",
+            "messages": ["Import complete"],
+        }));
+        assert_eq!(
+            import_state(&status),
+            ImportState::Complete(ImportReport::default())
+        );
+    }
+
+    #[test]
+    fn partial_import_reports_failed_statements_and_rows() {
+        let status = status(json!({
+            "success": true,
+            "status": "complete",
+            "errors": [
+                "statement 12: UNIQUE constraint failed: pda_registry.pda",
+                "statement 40: 3 rows failed to insert",
+            ],
+            "messages": ["2 rows failed during import", "Processed 1000 queries"],
+        }));
+        assert_eq!(
+            import_state(&status),
+            ImportState::Complete(ImportReport {
+                failed_statements: 2,
+                failed_rows: 5,
+                ..ImportReport::default()
+            })
+        );
+    }
+
+    #[test]
+    fn failed_import_is_an_error() {
+        let status = status(json!({
+            "success": false,
+            "status": "error",
+            "errors": ["SQLITE_ERROR: no such table: pda_registry"],
+        }));
+        assert_eq!(
+            import_state(&status),
+            ImportState::Failed("SQLITE_ERROR: no such table: pda_registry".to_owned())
+        );
+    }
+
+    #[test]
+    fn running_import_is_pending() {
+        let status = status(json!({
+            "success": true,
+            "status": "active",
+            "at_bookmark": "0000001-abc",
+        }));
+        assert_eq!(import_state(&status), ImportState::Pending);
+    }
+
+    #[test]
+    fn statement_size_errors_are_recognized() {
+        for message in [
+            "D1 import failed: SQLITE_TOOBIG: string or blob too big",
+            "D1 import failed: Statement too long",
+        ] {
+            assert!(is_statement_too_large(&eyre!("{message}")), "{message}");
+        }
+        assert!(!is_statement_too_large(&eyre!(
+            "D1 import failed: no such table: pda_registry"
+        )));
+    }
+
+    #[test]
+    fn retry_after_accepts_seconds_and_http_date() {
+        let now = httpdate::parse_http_date("Wed, 21 Oct 2015 07:28:00 GMT").unwrap();
+        let mut headers = HeaderMap::new();
+
+        headers.insert(RETRY_AFTER, "120".parse().unwrap());
+        assert_eq!(retry_after(&headers, now), Some(Duration::from_secs(120)));
+
+        headers.insert(
+            RETRY_AFTER,
+            "Wed, 21 Oct 2015 07:28:30 GMT".parse().unwrap(),
+        );
+        assert_eq!(retry_after(&headers, now), Some(Duration::from_secs(30)));
+
+        headers.insert(
+            RETRY_AFTER,
+            "Wed, 21 Oct 2015 07:27:00 GMT".parse().unwrap(),
+        );
+        assert_eq!(retry_after(&headers, now), Some(Duration::ZERO));
+
+        headers.insert(RETRY_AFTER, "soon".parse().unwrap());
+        assert_eq!(retry_after(&headers, now), None);
+    }
+
+    #[test]
+    fn empty_entries_produce_no_script() {
+        assert!(
+            build_insert_script(&[], &UploadOptions::default())
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn empty_blob_literal() {
+        let mut literal = String::new();
+        push_blob_literal(&mut literal, &[]);
+        assert_eq!(literal, "X''");
+    }
+
+    #[test]
+    fn single_entry_matches_golden() {
+        assert_golden(
+            &single_entry(),
+            &UploadOptions::default(),
+            include_str!("../tests/golden/single_entry.sql"),
+        );
+    }
+
+    #[test]
+    fn empty_seeds_match_golden() {
+        assert_golden(
+            &empty_seeds(),
+            &UploadOptions::default(),
+            include_str!("../tests/golden/empty_seeds.sql"),
+        );
+    }
+
+    #[test]
+    fn batch_boundary_matches_golden() {
+        assert_golden(
+            &batch_boundary(),
+            &UploadOptions::default(),
+            include_str!("../tests/golden/batch_boundary.sql"),
+        );
+    }
+
+    #[test]
+    fn transactional_matches_golden() {
+        let options = UploadOptions {
+            transactional: true,
+            ..UploadOptions::default()
+        };
+        assert_golden(
+            &single_entry(),
+            &options,
+            include_str!("../tests/golden/single_entry_transactional.sql"),
+        );
+    }
+
+    #[test]
+    fn seeds_json_matches_golden() {
+        let options = UploadOptions {
+            with_seeds_json: true,
+            ..UploadOptions::default()
+        };
+        assert_golden(
+            &single_entry(),
+            &options,
+            include_str!("../tests/golden/single_entry_seeds_json.sql"),
+        );
+        assert_golden(
+            &empty_seeds(),
+            &options,
+            include_str!("../tests/golden/empty_seeds_seeds_json.sql"),
+        );
+    }
+
+    #[test]
+    fn generated_sql_executes_in_sqlite() {
+        let mut entries: Vec<PdaSqlite> =
+            [single_entry(), empty_seeds(), batch_boundary()].concat();
+        entries.sort_by_key(|entry| entry.pda);
+        entries.dedup_by_key(|entry| entry.pda);
+        for (with_seeds_json, with_checksums, transactional) in [
+            (false, false, false),
+            (true, false, false),
+            (false, true, false),
+            (true, true, true),
+        ] {
+            let options = UploadOptions {
+                with_seeds_json,
+                with_checksums,
+                transactional,
+                entries_per_statement: 4,
+                ..UploadOptions::default()
+            };
+            let script = build_insert_script(&entries, &options).unwrap().unwrap();
+            let rows = schema::execute_in_memory(
+                &options.table_name,
+                &schema::expected_d1_columns(with_seeds_json, with_checksums),
+                [script],
+            )
+            .unwrap();
+            assert_eq!(rows, entries.len() as u64);
+        }
+    }
+}
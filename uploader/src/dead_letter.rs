@@ -0,0 +1,68 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use eyre::{Result, WrapErr};
+use log::warn;
+use serde::Serialize;
+
+use crate::types::{PdaJson, PdaSqlite};
+
+/// JSON lines of entries the run rejected, each with the reason, written with `--dead-letter`.
+/// Records carry the [`PdaJson`] fields, so the file can be fed back through `--pda-list-in`
+/// once the cause is fixed.
+pub struct DeadLetter {
+    writer: BufWriter<File>,
+    path: PathBuf,
+}
+
+#[derive(Serialize)]
+struct DeadLetterRecord<'a> {
+    #[serde(flatten)]
+    entry: PdaJson,
+    reason: &'a str,
+}
+
+impl DeadLetter {
+    /// Opens `path` for appending, creating it if missing, so records of earlier runs stay
+    /// until the file is re-processed and removed.
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .wrap_err_with(|| format!("failed to open dead letter file {}", path.display()))?;
+        Ok(Self {
+            writer: BufWriter::new(file),
+            path: path.to_owned(),
+        })
+    }
+
+    /// Appends `entries` with why they were rejected, flushed so the records survive the run
+    /// aborting afterwards.
+    pub fn record(&mut self, entries: &[PdaSqlite], reason: &str) -> Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        for entry in entries {
+            let record = DeadLetterRecord {
+                entry: PdaJson::from(entry),
+                reason,
+            };
+            serde_json::to_writer(&mut self.writer, &record)
+                .wrap_err("failed to encode dead letter record")?;
+            self.writer.write_all(b"\n")?;
+        }
+        self.writer
+            .flush()
+            .wrap_err_with(|| format!("failed to write {}", self.path.display()))?;
+        warn!(
+            "Wrote {} rejected entries to {}: {reason}",
+            entries.len(),
+            self.path.display()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,379 @@
+use std::{
+    future::Future,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use aws_sdk_s3::{Client, primitives::ByteStream};
+use eyre::{Result, WrapErr};
+use log::{info, warn};
+use tokio::io::AsyncWriteExt;
+
+use crate::s3::new_s3_client;
+
+/// Where the dedup hashset persists between runs. The merge and save code always works on a
+/// local file; a store only moves that file in before the merge and out after the save.
+pub trait DedupStore {
+    /// Local file the run reads and writes the hashset through.
+    fn local_path(&self) -> &Path;
+
+    /// Makes the latest persisted hashset available at [`local_path`](Self::local_path),
+    /// removing any stale local copy when nothing has been persisted yet.
+    fn fetch(&self) -> impl Future<Output = Result<()>> + Send;
+
+    /// Persists the hashset written to [`local_path`](Self::local_path). Readers must see
+    /// either the previous hashset or the new one, never a partial write.
+    fn publish(&self) -> impl Future<Output = Result<()>> + Send;
+}
+
+/// A hashset on the local filesystem. Saves already replace it atomically, so there is nothing
+/// to fetch or publish.
+pub struct LocalDedupStore {
+    pub path: PathBuf,
+}
+
+impl DedupStore for LocalDedupStore {
+    fn local_path(&self) -> &Path {
+        &self.path
+    }
+
+    async fn fetch(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn publish(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// A hashset kept as one object in an S3-compatible bucket (including R2), cached in a local
+/// file for the duration of the run and removed with the store.
+pub struct S3DedupStore {
+    pub client: Client,
+    pub bucket: String,
+    pub key: String,
+    pub local_path: PathBuf,
+}
+
+impl DedupStore for S3DedupStore {
+    fn local_path(&self) -> &Path {
+        &self.local_path
+    }
+
+    async fn fetch(&self) -> Result<()> {
+        let (bucket, key) = (&self.bucket, &self.key);
+        let output = match self
+            .client
+            .get_object()
+            .bucket(bucket)
+            .key(key)
+            .send()
+            .await
+        {
+            Ok(output) => output,
+            Err(err)
+                if err
+                    .as_service_error()
+                    .is_some_and(|err| err.is_no_such_key()) =>
+            {
+                info!("No dedup hashset at s3://{bucket}/{key}, starting fresh");
+                match tokio::fs::remove_file(&self.local_path).await {
+                    Ok(()) => info!(
+                        "Removed stale cached dedup hashset {}",
+                        self.local_path.display()
+                    ),
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(err) => {
+                        return Err(err).wrap_err_with(|| {
+                            format!("failed to remove {}", self.local_path.display())
+                        });
+                    }
+                }
+                return Ok(());
+            }
+            Err(err) => {
+                return Err(err)
+                    .wrap_err_with(|| format!("failed to download s3://{bucket}/{key}"));
+            }
+        };
+
+        // Stream into a sibling file and rename it over the cache, so an interrupted download
+        // never leaves a truncated hashset behind.
+        let download_path = self.local_path.with_extension("download");
+        let mut file = tokio::fs::File::create(&download_path)
+            .await
+            .wrap_err_with(|| format!("failed to create {}", download_path.display()))?;
+        let mut body = output.body;
+        let mut downloaded = 0;
+        while let Some(bytes) = body
+            .try_next()
+            .await
+            .wrap_err_with(|| format!("failed to download s3://{bucket}/{key}"))?
+        {
+            file.write_all(&bytes).await?;
+            downloaded += bytes.len();
+        }
+        file.flush().await?;
+        drop(file);
+        tokio::fs::rename(&download_path, &self.local_path)
+            .await
+            .wrap_err_with(|| format!("failed to replace {}", self.local_path.display()))?;
+        info!(
+            "Downloaded dedup hashset s3://{bucket}/{key} ({downloaded} bytes) to {}",
+            self.local_path.display()
+        );
+        Ok(())
+    }
+
+    /// Uploads to a temporary key and then copies it over the real one server-side, so the
+    /// real key only ever changes to a complete object. CopyObject caps objects at 5 GiB.
+    async fn publish(&self) -> Result<()> {
+        let (bucket, key) = (&self.bucket, &self.key);
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let temp_key = format!("{key}.tmp-{}-{nanos}", std::process::id());
+
+        let body = ByteStream::from_path(&self.local_path)
+            .await
+            .wrap_err_with(|| format!("failed to read {}", self.local_path.display()))?;
+        self.client
+            .put_object()
+            .bucket(bucket)
+            .key(&temp_key)
+            .body(body)
+            .send()
+            .await
+            .wrap_err_with(|| format!("failed to upload s3://{bucket}/{temp_key}"))?;
+
+        let copied = self
+            .client
+            .copy_object()
+            .copy_source(format!("{bucket}/{temp_key}"))
+            .bucket(bucket)
+            .key(key)
+            .send()
+            .await
+            .wrap_err_with(|| format!("failed to copy s3://{bucket}/{temp_key} over {key}"));
+
+        if let Err(err) = self
+            .client
+            .delete_object()
+            .bucket(bucket)
+            .key(&temp_key)
+            .send()
+            .await
+        {
+            warn!("Failed to delete temporary object s3://{bucket}/{temp_key}: {err}");
+        }
+        copied?;
+
+        info!("Published dedup hashset to s3://{bucket}/{key}");
+        Ok(())
+    }
+}
+
+impl Drop for S3DedupStore {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_file(&self.local_path)
+            && err.kind() != std::io::ErrorKind::NotFound
+        {
+            warn!(
+                "Failed to remove cached dedup hashset {}: {err}",
+                self.local_path.display()
+            );
+        }
+    }
+}
+
+/// The store selected by `--dedup-hashset-file`.
+pub enum AnyDedupStore {
+    Local(LocalDedupStore),
+    S3(S3DedupStore),
+}
+
+impl AnyDedupStore {
+    /// Opens an [`S3DedupStore`] for `s3://bucket/key` locations, cached under the system temp
+    /// directory, and a [`LocalDedupStore`] for anything else. The cache is keyed by `run_id`
+    /// as well as the location, so concurrent runs never share one.
+    pub async fn open(location: &Path, s3_endpoint: Option<&str>, run_id: Option<&str>) -> Self {
+        let Some((bucket, key)) = location.to_str().and_then(parse_s3_uri) else {
+            return Self::Local(LocalDedupStore {
+                path: location.to_owned(),
+            });
+        };
+        Self::S3(S3DedupStore {
+            client: new_s3_client(s3_endpoint).await,
+            bucket: bucket.to_owned(),
+            key: key.to_owned(),
+            local_path: s3_cache_path(location, run_id),
+        })
+    }
+}
+
+/// Local cache of the S3 dedup hashset at `location` for the run `run_id`.
+fn s3_cache_path(location: &Path, run_id: Option<&str>) -> PathBuf {
+    let mut name = format!(
+        "pda-dedup-{:x}",
+        md5::compute(location.as_os_str().as_encoded_bytes())
+    );
+    if let Some(run_id) = run_id {
+        name.push('-');
+        name.push_str(run_id);
+    }
+    std::env::temp_dir().join(name)
+}
+
+impl DedupStore for AnyDedupStore {
+    fn local_path(&self) -> &Path {
+        match self {
+            AnyDedupStore::Local(store) => store.local_path(),
+            AnyDedupStore::S3(store) => store.local_path(),
+        }
+    }
+
+    async fn fetch(&self) -> Result<()> {
+        match self {
+            AnyDedupStore::Local(store) => store.fetch().await,
+            AnyDedupStore::S3(store) => store.fetch().await,
+        }
+    }
+
+    async fn publish(&self) -> Result<()> {
+        match self {
+            AnyDedupStore::Local(store) => store.publish().await,
+            AnyDedupStore::S3(store) => store.publish().await,
+        }
+    }
+}
+
+/// Splits `s3://bucket/key` into its bucket and key.
+fn parse_s3_uri(uri: &str) -> Option<(&str, &str)> {
+    let (bucket, key) = uri.strip_prefix("s3://")?.split_once('/')?;
+    (!bucket.is_empty() && !key.is_empty()).then_some((bucket, key))
+}
+
+#[cfg(test)]
+mod tests {
+    use aws_sdk_s3::config::{BehaviorVersion, Credentials, Region};
+    use wiremock::{
+        Mock, MockServer, ResponseTemplate,
+        matchers::{header_exists, method, path, path_regex},
+    };
+
+    use super::*;
+
+    async fn s3_store(server: &MockServer, local_path: PathBuf) -> S3DedupStore {
+        let config = aws_sdk_s3::config::Builder::new()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("auto"))
+            .credentials_provider(Credentials::new("key", "secret", None, None, "test"))
+            .endpoint_url(server.uri())
+            .force_path_style(true)
+            .build();
+        S3DedupStore {
+            client: Client::from_conf(config),
+            bucket: "bucket".to_owned(),
+            key: "dedup".to_owned(),
+            local_path,
+        }
+    }
+
+    #[test]
+    fn s3_caches_are_kept_apart_per_run() {
+        let location = Path::new("s3://bucket/dedup");
+        let first = s3_cache_path(location, Some("run-1"));
+        assert_ne!(first, s3_cache_path(location, Some("run-2")));
+        assert_ne!(
+            first,
+            s3_cache_path(Path::new("s3://bucket/other"), Some("run-1"))
+        );
+        assert_eq!(first, s3_cache_path(location, Some("run-1")));
+        assert_eq!(parse_s3_uri("s3://bucket/a/b"), Some(("bucket", "a/b")));
+        assert_eq!(parse_s3_uri("s3://bucket/"), None);
+        assert_eq!(parse_s3_uri("/tmp/dedup"), None);
+    }
+
+    #[tokio::test]
+    async fn s3_store_fetches_and_publishes_through_a_temporary_key() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/bucket/dedup"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(b"persisted".to_vec()))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path_regex(r"^/bucket/dedup\.tmp-\d+-\d+$"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path("/bucket/dedup"))
+            .and(header_exists("x-amz-copy-source"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string("<CopyObjectResult><ETag>\"etag\"</ETag></CopyObjectResult>"),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("DELETE"))
+            .and(path_regex(r"^/bucket/dedup\.tmp-"))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let local_path = dir.path().join("cache");
+        let store = s3_store(&server, local_path.clone()).await;
+        store.fetch().await.unwrap();
+        assert_eq!(std::fs::read(&local_path).unwrap(), b"persisted");
+
+        std::fs::write(&local_path, b"advanced").unwrap();
+        store.publish().await.unwrap();
+        let requests = server.received_requests().await.unwrap();
+        let copy = requests
+            .iter()
+            .find(|request| request.headers.contains_key("x-amz-copy-source"))
+            .unwrap();
+        let temp_key = requests
+            .iter()
+            .find(|request| {
+                request.method.as_str() == "PUT" && request.url.path().contains(".tmp-")
+            })
+            .unwrap()
+            .url
+            .path()
+            .trim_start_matches('/')
+            .to_owned();
+        assert_eq!(copy.headers["x-amz-copy-source"], temp_key.as_str());
+
+        drop(store);
+        assert!(!local_path.exists());
+    }
+
+    #[tokio::test]
+    async fn missing_s3_object_clears_the_stale_cache() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/bucket/dedup"))
+            .respond_with(
+                ResponseTemplate::new(404).set_body_string(
+                    "<Error><Code>NoSuchKey</Code><Message>missing</Message></Error>",
+                ),
+            )
+            .mount(&server)
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let local_path = dir.path().join("cache");
+        std::fs::write(&local_path, b"stale").unwrap();
+        let store = s3_store(&server, local_path.clone()).await;
+        store.fetch().await.unwrap();
+        assert!(!local_path.exists());
+    }
+}
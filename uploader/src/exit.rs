@@ -0,0 +1,65 @@
+use std::fmt;
+
+/// Failure classes the scheduler branches on, each mapped to a distinct process exit code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// Bad arguments, credentials, or database configuration.
+    Config = 2,
+    /// Reading or deduplicating collector output failed.
+    Merge = 3,
+    /// Uploading, toggling the active database, or saving the dedup hashset failed.
+    Upload = 4,
+    /// An operation gave up waiting, e.g. D1 import polling or toggle verification.
+    Timeout = 5,
+    /// `--fail-on-empty` was set and the merge produced no new entries.
+    Empty = 6,
+}
+
+/// Marker error for operations that gave up waiting. It maps to [`Failure::Timeout`] wherever
+/// it appears in the error chain, whichever phase it was raised in.
+#[derive(Debug)]
+pub struct TimedOut(pub String);
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// An error together with the failure class it should exit with.
+#[derive(Debug)]
+pub struct RunError {
+    pub failure: Failure,
+    pub report: eyre::Report,
+}
+
+impl RunError {
+    pub fn new(failure: Failure, report: eyre::Report) -> Self {
+        Self { failure, report }
+    }
+
+    pub fn code(&self) -> u8 {
+        let failure = if self.report.chain().any(|cause| cause.is::<TimedOut>()) {
+            Failure::Timeout
+        } else {
+            self.failure
+        };
+        failure as u8
+    }
+}
+
+pub trait ResultExt<T> {
+    /// Tags the error with the failure class it should exit with.
+    fn or_exit(self, failure: Failure) -> Result<T, RunError>;
+}
+
+impl<T, E> ResultExt<T> for Result<T, E>
+where
+    E: Into<eyre::Report>,
+{
+    fn or_exit(self, failure: Failure) -> Result<T, RunError> {
+        self.map_err(|err| RunError::new(failure, err.into()))
+    }
+}
@@ -0,0 +1,192 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    sync::Arc,
+};
+
+use arrow_array::{
+    ArrayRef, RecordBatch,
+    builder::{BinaryBuilder, Int32Builder, ListBuilder},
+};
+use arrow_schema::{DataType, Field, Schema};
+use eyre::{Result, WrapErr, eyre};
+use log::info;
+use parquet::{
+    arrow::ArrowWriter,
+    basic::{Compression, ZstdLevel},
+    file::properties::WriterProperties,
+};
+
+use serde::Serialize;
+use solana_address::Address;
+
+use crate::types::PdaSqlite;
+
+/// Entries per Parquet row group. Each group is built and flushed on its own so memory stays
+/// bounded by one group rather than the whole entry set.
+const ROW_GROUP_SIZE: usize = 65_536;
+
+fn parquet_schema() -> Schema {
+    Schema::new(vec![
+        Field::new("pda", DataType::Binary, false),
+        Field::new("program_id", DataType::Binary, false),
+        Field::new("seed_count", DataType::Int32, false),
+        Field::new_list("seeds", Field::new("item", DataType::Binary, true), false),
+    ])
+}
+
+/// Writes `entries` to a Parquet file with `pda`, `program_id`, `seed_count` and `seeds`
+/// columns, one row group per [`ROW_GROUP_SIZE`] entries.
+pub fn export_parquet(entries: &[PdaSqlite], path: &Path) -> Result<()> {
+    let schema = Arc::new(parquet_schema());
+    let file = File::create(path)
+        .wrap_err_with(|| format!("failed to create parquet file {}", path.display()))?;
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::ZSTD(ZstdLevel::default()))
+        .set_max_row_group_size(ROW_GROUP_SIZE)
+        .build();
+    let mut writer = ArrowWriter::try_new(file, schema.clone(), Some(properties))
+        .wrap_err("failed to initialize parquet writer")?;
+
+    for chunk in entries.chunks(ROW_GROUP_SIZE) {
+        let batch = record_batch(&schema, chunk)?;
+        writer
+            .write(&batch)
+            .wrap_err_with(|| format!("failed to write row group to {}", path.display()))?;
+        writer
+            .flush()
+            .wrap_err_with(|| format!("failed to flush row group to {}", path.display()))?;
+    }
+
+    writer
+        .close()
+        .wrap_err_with(|| format!("failed to finalize parquet file {}", path.display()))?;
+    info!(
+        "Exported {} entries to parquet file {}",
+        entries.len(),
+        path.display()
+    );
+    Ok(())
+}
+
+fn record_batch(schema: &Arc<Schema>, entries: &[PdaSqlite]) -> Result<RecordBatch> {
+    let mut pdas = BinaryBuilder::with_capacity(entries.len(), entries.len() * 32);
+    let mut program_ids = BinaryBuilder::with_capacity(entries.len(), entries.len() * 32);
+    let mut seed_counts = Int32Builder::with_capacity(entries.len());
+    let mut seeds = ListBuilder::new(BinaryBuilder::new());
+
+    for entry in entries {
+        pdas.append_value(entry.pda.as_ref());
+        program_ids.append_value(entry.program_id.as_ref());
+        seed_counts.append_value(
+            i32::try_from(entry.seeds.len()).wrap_err("seed count does not fit in an i32")?,
+        );
+        for seed in &entry.seeds {
+            seeds.values().append_value(seed);
+        }
+        seeds.append(true);
+    }
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(pdas.finish()),
+        Arc::new(program_ids.finish()),
+        Arc::new(seed_counts.finish()),
+        Arc::new(seeds.finish()),
+    ];
+    RecordBatch::try_new(schema.clone(), columns).wrap_err("failed to build parquet record batch")
+}
+
+/// Where one program's entries sit in the written output, written with `--program-index-out`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ProgramIndexEntry {
+    /// Base58 program id.
+    pub program_id: String,
+    pub entries: usize,
+    /// Row of the program's first entry in the uploaded entries, as exported to Parquet or a
+    /// `--new-entries-out` list.
+    pub first_row: usize,
+    /// Byte offset of the program's first entry in the uncompressed blob written by
+    /// `--merge-only`, and the bytes its entries take there. Absent when no blob is written.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blob_offset: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blob_len: Option<u64>,
+}
+
+/// Builds one [`ProgramIndexEntry`] per program from the entries in the order they are
+/// written, sorted by program as `--sort-by program` leaves them.
+#[derive(Debug, Default)]
+pub struct ProgramIndex {
+    programs: Vec<ProgramIndexEntry>,
+    previous: Option<Address>,
+    rows: usize,
+}
+
+impl ProgramIndex {
+    /// Records the next written entry, and the `(offset, len)` bytes it took in a blob when
+    /// one is written. Fails if a program's entries are not contiguous, since the offsets
+    /// could not point at all of them.
+    pub fn push(&mut self, entry: &PdaSqlite, blob_span: Option<(u64, u64)>) -> Result<()> {
+        let row = self.rows;
+        self.rows += 1;
+        match self.programs.last_mut() {
+            Some(last) if self.previous == Some(entry.program_id) => {
+                last.entries += 1;
+                if let (Some(blob_len), Some((_, len))) = (last.blob_len.as_mut(), blob_span) {
+                    *blob_len += len;
+                }
+            }
+            _ => {
+                if self
+                    .previous
+                    .is_some_and(|program| program > entry.program_id)
+                {
+                    return Err(eyre!(
+                        "entries are not sorted by program (program {} at row {row}); the program index needs --sort-by program",
+                        entry.program_id
+                    ));
+                }
+                self.programs.push(ProgramIndexEntry {
+                    program_id: entry.program_id.to_string(),
+                    entries: 1,
+                    first_row: row,
+                    blob_offset: blob_span.map(|(offset, _)| offset),
+                    blob_len: blob_span.map(|(_, len)| len),
+                });
+                self.previous = Some(entry.program_id);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn programs(&self) -> &[ProgramIndexEntry] {
+        &self.programs
+    }
+
+    /// Writes the index to `path` as a JSON array.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let file = File::create(path)
+            .wrap_err_with(|| format!("failed to create program index {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut writer, &self.programs)
+            .wrap_err_with(|| format!("failed to write program index {}", path.display()))?;
+        writeln!(writer)?;
+        writer.flush()?;
+        info!(
+            "Wrote index of {} programs to {}",
+            self.programs.len(),
+            path.display()
+        );
+        Ok(())
+    }
+}
+
+/// Indexes the rows of `entries`, for runs that write no blob the offsets could point into.
+pub fn program_index(entries: &[PdaSqlite]) -> Result<ProgramIndex> {
+    let mut index = ProgramIndex::default();
+    for entry in entries {
+        index.push(entry, None)?;
+    }
+    Ok(index)
+}
@@ -0,0 +1,43 @@
+use std::{future::Future, sync::Arc};
+
+use cloudflare::framework::client::async_api::Client;
+use eyre::Result;
+
+use crate::cloudflare::{get_kv, put_kv};
+
+/// Key-value store holding the `ACTIVE_DB` pointer that selects the production database.
+pub trait KvStore {
+    fn get(&self, key: &str) -> impl Future<Output = Result<Option<String>>> + Send;
+
+    fn put(&self, key: &str, value: &str) -> impl Future<Output = Result<()>> + Send;
+}
+
+/// A Workers KV namespace accessed through the Cloudflare API.
+pub struct CloudflareKv {
+    pub client: Arc<Client>,
+    pub account_id: String,
+    pub namespace_id: String,
+}
+
+impl KvStore for CloudflareKv {
+    async fn get(&self, key: &str) -> Result<Option<String>> {
+        get_kv(
+            self.client.clone(),
+            &self.account_id,
+            &self.namespace_id,
+            key,
+        )
+        .await
+    }
+
+    async fn put(&self, key: &str, value: &str) -> Result<()> {
+        put_kv(
+            self.client.clone(),
+            &self.account_id,
+            &self.namespace_id,
+            key,
+            value,
+        )
+        .await
+    }
+}
@@ -0,0 +1,29 @@
+//! Merges PDA collector output, deduplicates it against a persisted hashset, and uploads the
+//! new entries to blue/green Cloudflare D1 databases (or another [`UploadBackend`]), toggling
+//! the active database through a [`KvStore`].
+
+pub mod analyze;
+pub mod backend;
+pub mod bloom;
+pub mod cloudflare;
+pub mod dead_letter;
+pub mod dedup_store;
+pub mod exit;
+pub mod export;
+pub mod kv;
+pub mod merge;
+pub mod merge_state;
+pub mod mirror;
+pub mod pipeline;
+pub mod s3;
+pub mod schema;
+pub mod seed_stats;
+pub mod summary;
+pub mod types;
+
+pub use backend::{Backend, CloudflareD1Backend, S3Backend, UploadBackend};
+pub use cloudflare::build_insert_script;
+pub use dedup_store::{AnyDedupStore, DedupStore, LocalDedupStore, S3DedupStore};
+pub use kv::{CloudflareKv, KvStore};
+pub use merge::{MergeOptions, MergeOutput, MergeStats, merge, save_dedup_hashset};
+pub use types::PdaSqlite;
@@ -1,143 +1,85 @@
-use ::cloudflare::framework::auth::Credentials;
-use clap::Parser;
-use log::info;
+use std::{io::Write, process::ExitCode};
 
-use crate::{
-    cloudflare::{get_kv, new_client, put_kv, upload_to_d1},
-    types::Args,
+use clap::Parser;
+use log::{LevelFilter, error};
+use uploader::{
+    pipeline::run,
+    summary::{self, PhaseTimings, RunSummary},
+    types::{Args, LogFormat},
 };
 
-mod cloudflare;
-mod merge;
-mod types;
-
-const NAMESPACE_ID: &str = "05dc24c1e32e433ba403340ffcb21fb2";
-const ACTIVE_DB_KEY: &str = "ACTIVE_DB";
-
 #[tokio::main]
-async fn main() {
-    env_logger::init();
-    let args = Args::parse();
-    let api_token = args.token.clone();
-
-    let client = new_client(Credentials::UserAuthToken {
-        token: api_token.clone(),
-    })
-    .expect("failed to create client");
-    let active_db = get_kv(
-        client.clone(),
-        &args.account_id,
-        NAMESPACE_ID,
-        ACTIVE_DB_KEY,
-    )
-    .await
-    .expect("failed to get current db")
-    .expect("no current db");
-
-    info!("Current production db: {active_db}");
-
-    // merge
-    let (entries, files, mut dedup_hashset) =
-        merge::merge(args.path.clone(), args.dedup_hashset_file.clone()).unwrap();
-    info!(
-        "Merged {} files into {} new entries",
-        files.len(),
-        entries.len()
-    );
-
-    if let (Some(blue_db_id), Some(green_db_id)) =
-        (args.blue_db_id.as_deref(), args.green_db_id.as_deref())
-    {
-        let (inactive_db_id, new_active_label, secondary_db_id) = match active_db.as_str() {
-            "blue" => (green_db_id, "green", blue_db_id),
-            "green" => (blue_db_id, "blue", green_db_id),
-            other => panic!("unexpected active db: {other}"),
-        };
-
-        const CHUNK_SIZE: usize = 100_000;
-        let total_entries = entries.len();
-        let num_chunks = total_entries.div_ceil(CHUNK_SIZE);
-
-        // Step 1: Upload to inactive database in chunks
-        info!(
-            "Step 1: Uploading {total_entries} entries to inactive database {inactive_db_id} in {num_chunks} chunk(s) of up to {CHUNK_SIZE} entries"
-        );
-
-        for (chunk_idx, chunk) in entries.chunks(CHUNK_SIZE).enumerate() {
-            let chunk_num = chunk_idx + 1;
-            info!(
-                "Uploading chunk {}/{} to inactive database: {} entries",
-                chunk_num,
-                num_chunks,
-                chunk.len()
-            );
-
-            upload_to_d1(&api_token, &args.account_id, inactive_db_id, chunk)
-                .await
-                .expect("failed to upload chunk to inactive D1 database");
-
-            info!("Successfully uploaded chunk {chunk_num}/{num_chunks} to inactive database");
+async fn main() -> ExitCode {
+    let mut args = Args::parse();
+    let run_id = args.run_id.get_or_insert_with(summary::new_run_id).clone();
+    init_logger(&args, &run_id);
+    let profile = args.profile;
+    let summary_json = args.summary_json.clone();
+
+    let mut summary = RunSummary {
+        run_id,
+        ..RunSummary::default()
+    };
+    let mut timings = PhaseTimings::default();
+    let result = run(args, &mut summary, &mut timings).await;
+    let code = match &result {
+        Ok(()) => 0,
+        Err(err) => {
+            eprintln!("Error: {:?}", err.report);
+            err.code()
         }
+    };
 
-        // Step 2: Toggle the active database
-        info!("Step 2: Toggling active database to {new_active_label}");
-        put_kv(
-            client.clone(),
-            &args.account_id,
-            NAMESPACE_ID,
-            ACTIVE_DB_KEY,
-            new_active_label,
-        )
-        .await
-        .expect("failed to put kv");
-        info!("Database toggle complete");
-
-        // Step 3: Upload to secondary database in chunks
-        info!(
-            "Step 3: Uploading {total_entries} entries to secondary database {secondary_db_id} in {num_chunks} chunk(s)"
-        );
-
-        for (chunk_idx, chunk) in entries.chunks(CHUNK_SIZE).enumerate() {
-            let chunk_num = chunk_idx + 1;
-            info!(
-                "Uploading chunk {}/{} to secondary database: {} entries",
-                chunk_num,
-                num_chunks,
-                chunk.len()
-            );
-
-            upload_to_d1(&api_token, &args.account_id, secondary_db_id, chunk)
-                .await
-                .expect("failed to upload chunk to secondary D1 database");
-
-            info!("Successfully uploaded chunk {chunk_num}/{num_chunks} to secondary database");
+    if profile {
+        timings.log_table();
+    }
+    if let Some(summary_json) = summary_json {
+        summary.exit_code = code;
+        summary.error = result.err().map(|err| format!("{:#}", err.report));
+        summary.phases = profile.then(|| timings.phases().to_vec());
+        if let Err(err) = summary::write_summary(&summary, &summary_json) {
+            error!("{err:#}");
         }
+    }
 
-        // Step 4: Update and save dedup hashset to disk only after all uploads succeed
-        info!("Step 4: Updating and saving dedup hashset to disk");
-        dedup_hashset.extend(entries.iter().map(|entry| entry.pda));
-        info!(
-            "Extended dedup hashset with {} new entries (now contains {} total)",
-            entries.len(),
-            dedup_hashset.len()
-        );
-        merge::save_dedup_hashset(&dedup_hashset, &args.dedup_hashset_file)
-            .expect("failed to save dedup hashset");
+    ExitCode::from(code)
+}
 
-        info!("All operations completed successfully!");
+/// Initializes `env_logger`, emitting one JSON object per record when `--log-format json` is
+/// selected. Every record carries `run_id`, as a JSON field or appended to env_logger's default
+/// text line. `--quiet`/`--verbose` set the level unless `RUST_LOG` is present.
+fn init_logger(args: &Args, run_id: &str) {
+    let mut builder = env_logger::Builder::from_default_env();
+    let level = if args.quiet {
+        Some(LevelFilter::Warn)
+    } else if args.verbose {
+        Some(LevelFilter::Debug)
     } else {
-        info!("Skipping D1 uploads because --blue-db-id and --green-db-id were not provided");
-        // Still save the hashset even when skipping uploads (for testing)
-        merge::save_dedup_hashset(&dedup_hashset, &args.dedup_hashset_file)
-            .expect("failed to save dedup hashset");
+        None
+    };
+    if let Some(level) = level
+        && std::env::var_os(env_logger::DEFAULT_FILTER_ENV).is_none()
+    {
+        builder.filter_level(level);
     }
-
-    // todo: update telegram bot
-
-    // remove old files
-    // for file in files {
-    //     if let Err(err) = std::fs::remove_file(&file) {
-    //         warn!("Failed to remove source blob {}: {err}", file.display());
-    //     }
-    // }
+    match args.log_format {
+        LogFormat::Json => {
+            let run_id = run_id.to_owned();
+            builder.format(move |buf, record| {
+                let line = serde_json::json!({
+                    "timestamp": buf.timestamp_millis().to_string(),
+                    "level": record.level().as_str(),
+                    "target": record.target(),
+                    "run_id": run_id,
+                    "message": record.args().to_string(),
+                });
+                writeln!(buf, "{line}")
+            })
+        }
+        // The suffix outlives the builder, and the logger is only set up once.
+        LogFormat::Text => {
+            builder.format_suffix(Box::leak(format!(" run={run_id}\n").into_boxed_str()))
+        }
+    };
+    builder.init();
 }
@@ -1,78 +1,277 @@
 use eyre::{Result, WrapErr, eyre};
-use log::{info, warn};
+use log::{debug, info, warn};
 use rayon::prelude::*;
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     convert::TryInto,
     fs::File,
-    io::{BufReader, BufWriter, Write},
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
     path::{Path, PathBuf},
     sync::{
-        Arc, RwLock,
+        Arc, Mutex, RwLock,
         atomic::{self, AtomicUsize},
     },
-    time::{Duration, SystemTime},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
+use serde::{Serialize, de::DeserializeOwned};
 use solana_address::Address;
 
-use crate::types::PdaSqlite;
+use crate::{
+    bloom::{BloomFilter, bloom_path},
+    dead_letter::DeadLetter,
+    export::ProgramIndex,
+    merge_state::MergeState,
+    schema,
+    seed_stats::SeedStats,
+    summary::PhaseTimings,
+    types::{
+        DedupFormat, FsyncMode, InputFormat, PdaCsvRow, PdaJson, PdaShard, PdaSqlite, SortOrder,
+        parse_address,
+    },
+};
+
+/// Optional filters applied while merging collector output.
+#[derive(Debug, Clone, Default)]
+pub struct MergeOptions {
+    /// Drop entries whose `pda` lies on the ed25519 curve (i.e. is not a real PDA).
+    pub require_off_curve: bool,
+    /// Drop entries whose seeds and program id do not derive their `pda`.
+    pub verify_pda: bool,
+    /// Final order of the returned entries. Deduplication always runs in pda order first.
+    pub sort_by: SortOrder,
+    /// JSON array (or JSON lines) of hand-built entries merged alongside the scanned files.
+    pub pda_list_in: Option<PathBuf>,
+    /// Append the entries dropped by `require_off_curve` and `verify_pda` to this
+    /// [`DeadLetter`] file.
+    pub dead_letter: Option<PathBuf>,
+    /// Dedup against the persisted hashset through a bloom filter of this many bits instead
+    /// of loading the set. The returned hashset then only holds additions, which must be
+    /// written with [`save_dedup_hashset_streaming`].
+    pub dedup_bloom_bits: Option<u64>,
+    /// Treat pdas added to a [`DedupFormat::Timestamped`] dedup hashset within this many
+    /// seconds as new, so they are uploaded again. They stay in the returned hashset.
+    pub dedup_lookback_secs: Option<u64>,
+    /// Skip blob and sqlite files larger than this many bytes instead of parsing them.
+    pub max_file_size: Option<u64>,
+    /// Strip trailing zero bytes from seeds exactly this long, undoing fixed-width padding.
+    pub trim_seed_padding: Option<usize>,
+    /// Parser applied to files under the input directory.
+    pub input_format: InputFormat,
+    /// Record parsed source files and spill their entries here, skipping files a previous,
+    /// interrupted merge already parsed. See [`MergeState`].
+    pub merge_state: Option<PathBuf>,
+    /// Fail when source files were parsed but none of them held an entry.
+    pub fail_on_empty: bool,
+    /// Blocklisted pdas dropped from every source on every run, unlike the dedup hashset which
+    /// only tracks what was already uploaded.
+    pub exclude_pdas: HashSet<Address>,
+    /// Re-attribute entries of the key program to the value program before dedup. Dedup is
+    /// keyed by pda alone, so pdas already in the dedup hashset stay under their old program.
+    pub remap_programs: HashMap<Address, Address>,
+    /// Log the seed count and seed size distribution of the new entries, without dropping
+    /// any of them.
+    pub warn_on_large_seeds: bool,
+    /// Keep only the pdas of this shard, dropped before dedup so other shards' pdas never
+    /// reach this instance's dedup hashset.
+    pub pda_shard: Option<PdaShard>,
+    /// Skip blob files whose names are not valid UTF-8 instead of matching their raw bytes.
+    pub strict_utf8: bool,
+    /// Retry opening a sqlite file this many times while it reports busy or locked, as
+    /// networked filesystems do transiently.
+    pub sqlite_open_retries: u32,
+    /// Skip blob files modified further in the future than [`MTIME_SKEW_TOLERANCE`] instead of
+    /// treating them as settled.
+    pub skip_future_mtime: bool,
+    /// Expected number of addresses in a text dedup hashset, to size the set before loading
+    /// it. The bincode formats record their length and ignore this.
+    pub dedup_hashset_capacity: Option<usize>,
+    /// Load the dedup hashset while scanning for source files rather than before.
+    pub dedup_readahead: bool,
+    /// Blobs downloaded from `--url` sources, parsed alongside the blobs found on disk.
+    pub remote_blobs: Vec<RemoteBlob>,
+}
+
+/// A blob downloaded over HTTP, named by its URL in logs and errors. A URL whose path ends in
+/// `.zst` is decompressed like a `.blob.zst` file.
+#[derive(Debug, Clone)]
+pub struct RemoteBlob {
+    pub url: String,
+    pub bytes: Vec<u8>,
+}
+
+/// Retries used when reading sqlite files outside of [`merge`].
+pub const DEFAULT_SQLITE_OPEN_RETRIES: u32 = 3;
+
+/// How [`merge`] whittled the parsed entries down to the new ones, written with
+/// `--dedup-stats-json`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct MergeStats {
+    /// Source files parsed by this merge, excluding those an interrupted merge already parsed.
+    pub blob_files: usize,
+    pub sqlite_files: usize,
+    pub csv_files: usize,
+    /// Blobs downloaded from `--url` sources.
+    pub remote_blobs: usize,
+    /// Entries read before deduplication, counting those dropped as they were parsed.
+    pub initial_entries: usize,
+    /// Entries dropped for repeating a pda seen earlier in this merge.
+    pub vec_deduped: usize,
+    /// Entries dropped because the dedup hashset already holds their pda.
+    pub hashset_deduped: usize,
+    /// Entries returned by the merge, after every filter.
+    pub new_entries: usize,
+}
+
+/// What [`merge`] returns.
+#[derive(Debug)]
+pub struct MergeOutput {
+    /// Entries not yet in the dedup hashset.
+    pub entries: Vec<PdaSqlite>,
+    /// Blob and csv files parsed by this merge, or by the interrupted merge it resumed.
+    pub files: Vec<PathBuf>,
+    /// The dedup hashset as loaded; new entries are added only after successful uploads.
+    pub dedup_hashset: HashSet<Address>,
+    pub stats: MergeStats,
+}
 
 pub fn merge(
-    path: PathBuf,
+    path: Option<PathBuf>,
     dedup_hashset_path: PathBuf,
-) -> Result<(Vec<PdaSqlite>, Vec<PathBuf>, HashSet<Address>)> {
-    info!("Starting merge operation for path: {}", path.display());
+    options: &MergeOptions,
+    timings: &mut PhaseTimings,
+) -> Result<MergeOutput> {
+    match path.as_deref() {
+        Some(path) => {
+            info!("Starting merge operation for path: {}", path.display());
+            validate_input_dir(path)?;
+        }
+        None => info!("Starting merge operation without an input directory"),
+    }
 
-    let dedup_hashset: HashSet<Address> = if dedup_hashset_path.exists() {
-        info!(
-            "Loading existing dedup hashset from {}",
-            dedup_hashset_path.display()
-        );
-        let dedup_hashset = File::open(&dedup_hashset_path)?;
-        let dedup_hashset = BufReader::new(dedup_hashset);
-        let loaded: HashSet<Address> = bincode::deserialize_from(dedup_hashset).unwrap_or_default();
-        info!("Loaded dedup hashset with {} entries", loaded.len());
-        loaded
+    // The dedup load is bound by deserializing, the scan by directory I/O, so with
+    // --dedup-hashset-readahead they overlap. Both finish, and a failed load is handled as
+    // usual, before any source is checked against the set.
+    let load = || {
+        let started = Instant::now();
+        (
+            load_merge_dedup(&dedup_hashset_path, options),
+            started.elapsed(),
+        )
+    };
+    let scan = || {
+        let started = Instant::now();
+        (scan_sources(path.as_deref(), options), started.elapsed())
+    };
+    let ((dedup, load_elapsed), (sources, scan_elapsed)) = if options.dedup_readahead {
+        info!("Loading the dedup hashset while scanning for source files");
+        rayon::join(load, scan)
     } else {
-        info!("No existing dedup hashset found, starting fresh");
-        HashSet::new()
+        (load(), scan())
     };
+    timings.record("load dedup hashset", load_elapsed);
+    timings.record("scan", scan_elapsed);
+    let (dedup_hashset, recent) = dedup?;
+    let (mut blob_files, mut sqlite_files, mut csv_files) = sources?;
 
-    let blob_files = collect_blob_files(&path)?;
-    let sqlite_files = collect_sqlite_files(&path)?;
-
-    info!(
-        "Discovered {} blob file(s) and {} sqlite file(s) in {}",
-        blob_files.len(),
-        sqlite_files.len(),
-        path.display()
-    );
+    let (merge_state, resumed_entries) = match options.merge_state.as_deref() {
+        Some(state_path) => {
+            let (state, entries) =
+                timings.time("load merge state", || MergeState::open(state_path))?;
+            (Some(state), entries)
+        }
+        None => (None, Vec::new()),
+    };
+    // Files an interrupted merge already parsed are not parsed again, but they are still
+    // sources of this run and are returned with the others.
+    let mut resumed_files = Vec::new();
+    if let Some(state) = &merge_state {
+        resumed_files = blob_files
+            .iter()
+            .chain(&csv_files)
+            .filter(|file| state.is_done(file))
+            .cloned()
+            .collect();
+        let before = blob_files.len() + sqlite_files.len() + csv_files.len();
+        for files in [&mut blob_files, &mut sqlite_files, &mut csv_files] {
+            files.retain(|file| !state.is_done(file));
+        }
+        let skipped = before - blob_files.len() - sqlite_files.len() - csv_files.len();
+        if skipped > 0 {
+            info!("Skipping {skipped} file(s) already parsed by an interrupted merge");
+        }
+    }
 
-    let total_sources = blob_files.len() + sqlite_files.len();
-    let entries: Arc<RwLock<Vec<PdaSqlite>>> = Arc::new(RwLock::new(Vec::new()));
+    let remote_blobs = &options.remote_blobs;
+    let total_sources =
+        blob_files.len() + sqlite_files.len() + csv_files.len() + remote_blobs.len();
+    let seen = SeenPdas::new(&dedup_hashset, &recent, &resumed_entries);
+    let hooks = ParseHooks {
+        merge_state: merge_state.as_ref(),
+        seen: &seen,
+    };
+    let entries: Arc<RwLock<Vec<PdaSqlite>>> = Arc::new(RwLock::new(resumed_entries));
     let processed = AtomicUsize::new(0);
 
     if total_sources > 0 {
         info!("Starting deserialization of {total_sources} files");
-        process_paths(
-            "blob",
-            &blob_files,
-            &entries,
-            &processed,
-            total_sources,
-            from_blob,
-        )?;
-
-        process_paths(
-            "sqlite",
-            &sqlite_files,
-            &entries,
-            &processed,
-            total_sources,
-            from_sqlite,
-        )?;
-    } else {
+        timings.time("parse blobs", || {
+            process_paths(
+                "blob",
+                &blob_files,
+                &entries,
+                &processed,
+                total_sources,
+                Some(hooks),
+                from_blob,
+            )
+        })?;
+
+        timings.time("parse remote blobs", || {
+            remote_blobs.par_iter().try_for_each(|blob| -> Result<()> {
+                let mut parsed = from_remote_blob(blob)
+                    .wrap_err_with(|| format!("failed to parse blob from {}", blob.url))?;
+                seen.retain_new(&mut parsed)?;
+                let current_len = {
+                    let mut guard = entries
+                        .write()
+                        .map_err(|err| eyre!("entries lock poisoned: {err}"))?;
+                    guard.extend(parsed);
+                    guard.len()
+                };
+                let processed = processed.fetch_add(1, atomic::Ordering::Relaxed) + 1;
+                info!(
+                    "Finished processing remote blob ({processed}/{total_sources}) {current_len} entries so far from {}",
+                    blob.url
+                );
+                Ok(())
+            })
+        })?;
+
+        timings.time("parse sqlite", || {
+            process_paths(
+                "sqlite",
+                &sqlite_files,
+                &entries,
+                &processed,
+                total_sources,
+                Some(hooks),
+                |path| from_sqlite(path, options.sqlite_open_retries),
+            )
+        })?;
+
+        timings.time("parse csv", || {
+            process_paths(
+                "csv",
+                &csv_files,
+                &entries,
+                &processed,
+                total_sources,
+                Some(hooks),
+                from_csv,
+            )
+        })?;
+    } else if let Some(path) = path.as_deref() {
         info!("No PDA sources found under {}", path.display());
     }
 
@@ -81,191 +280,1752 @@ pub fn merge(
         .into_inner()
         .map_err(eyre::Report::from)?;
 
+    let parsed = seen.parsed.into_inner();
+    let known_skipped = seen.known_skipped.into_inner();
+    let duplicates_skipped = seen.duplicates_skipped.into_inner();
+    info!(
+        "Parsed {parsed} entries, skipping {known_skipped} already in the dedup hashset and {duplicates_skipped} duplicates as they were read"
+    );
+
+    // Files that parse cleanly yet hold nothing are almost always corrupt, truncated, or read
+    // with the wrong parser, unlike an input directory that simply has no files.
+    if total_sources > 0 && parsed == 0 {
+        warn!(
+            "!!! Parsed {total_sources} source file(s) but extracted 0 entries: check that they are not corrupt or truncated and that --input-format matches them !!!"
+        );
+        if options.fail_on_empty {
+            return Err(eyre!(
+                "parsed {total_sources} source file(s) but extracted 0 entries and --fail-on-empty is set"
+            ));
+        }
+    }
+
+    // Listed entries are uploaded again even when the dedup hashset holds their pda, as the
+    // pdas of an import that partially failed and was recorded in a dead letter file are.
+    let mut listed = HashSet::new();
+    if let Some(pda_list_in) = options.pda_list_in.as_deref() {
+        let list = read_pda_list_json(pda_list_in)?;
+        listed.extend(list.iter().map(|entry| entry.pda));
+        entries.extend(list);
+    }
+
+    if !options.exclude_pdas.is_empty() {
+        let before = entries.len();
+        entries.retain(|entry| !options.exclude_pdas.contains(&entry.pda));
+        info!(
+            "Excluded {} blocklisted entries ({} pdas on the exclude list)",
+            before - entries.len(),
+            options.exclude_pdas.len()
+        );
+    }
+
+    if let Some(shard) = options.pda_shard {
+        let before = entries.len();
+        entries.retain(|entry| shard.contains(&entry.pda));
+        info!(
+            "Kept {} of {before} entries in pda shard {shard}",
+            entries.len()
+        );
+    }
+
+    if !options.remap_programs.is_empty() {
+        let remapped = timings.time("remap programs", || {
+            remap_programs(&mut entries, &options.remap_programs)
+        });
+        info!(
+            "Remapped the program id of {remapped} entries across {} program(s)",
+            options.remap_programs.len()
+        );
+    }
+
+    if let Some(pad_len) = options.trim_seed_padding {
+        let trimmed = timings.time("trim seed padding", || {
+            trim_seed_padding(&mut entries, pad_len)
+        });
+        info!("Trimmed zero padding from {trimmed} seed(s) of {pad_len} bytes");
+    }
+
     let initial_count = entries.len();
     info!("Starting deduplication on {initial_count} entries");
 
     info!("Sorting entries by PDA");
-    entries.sort_by_key(|entry| entry.pda);
+    timings.time("sort", || entries.sort_by_key(|entry| entry.pda));
 
+    let started = Instant::now();
     info!("Deduplicating entries within vector");
     entries.dedup_by_key(|entry| entry.pda);
     let after_vec_dedup = entries.len();
-    let vec_deduped = initial_count.saturating_sub(after_vec_dedup);
+    let vec_deduped = duplicates_skipped + initial_count.saturating_sub(after_vec_dedup);
 
-    entries.retain(|entry| !dedup_hashset.contains(&entry.pda));
+    match options.dedup_bloom_bits {
+        Some(num_bits) => {
+            let duplicates = bloom_confirmed_duplicates(&dedup_hashset_path, num_bits, &entries)?;
+            entries.retain(|entry| !duplicates.contains(&entry.pda) || listed.contains(&entry.pda));
+        }
+        None => entries.retain(|entry| {
+            !dedup_hashset.contains(&entry.pda)
+                || recent.contains(&entry.pda)
+                || listed.contains(&entry.pda)
+        }),
+    }
     let after_hashset_dedup = entries.len();
-    let hashset_deduped = after_vec_dedup.saturating_sub(after_hashset_dedup);
+    let hashset_deduped = known_skipped + after_vec_dedup.saturating_sub(after_hashset_dedup);
+    timings.record("dedup", started.elapsed());
 
     info!(
         "Deduplication stats: {vec_deduped} deduped from vec, {hashset_deduped} deduped from hashset, {after_hashset_dedup} new entries"
     );
 
+    let mut dead_letter = options
+        .dead_letter
+        .as_deref()
+        .map(DeadLetter::open)
+        .transpose()?;
+
+    if options.require_off_curve {
+        info!("Checking {after_hashset_dedup} new entries for on-curve pdas");
+        let on_curve: Vec<PdaSqlite>;
+        (entries, on_curve) = timings.time("off-curve filter", || {
+            entries
+                .into_par_iter()
+                .partition(|entry| !entry.pda.is_on_curve())
+        });
+        if let Some(dead_letter) = dead_letter.as_mut() {
+            dead_letter.record(&on_curve, "pda is on the ed25519 curve")?;
+        }
+        let on_curve_rejected = on_curve.len();
+        if on_curve_rejected > 0 {
+            warn!("Dropped {on_curve_rejected} entries whose pda is on the ed25519 curve");
+        } else {
+            info!("All new entries are off-curve");
+        }
+    }
+
+    if options.verify_pda {
+        let to_verify = entries.len();
+        info!("Verifying pda derivation for {to_verify} new entries");
+        let started = Instant::now();
+        let underived: Vec<PdaSqlite>;
+        (entries, underived) = entries.into_par_iter().partition(derives_pda);
+        let elapsed = started.elapsed();
+        timings.record("verify pda", elapsed);
+        if let Some(dead_letter) = dead_letter.as_mut() {
+            dead_letter.record(&underived, "seeds and program id do not derive the pda")?;
+        }
+        let rejected = underived.len();
+        info!(
+            "Verified {to_verify} entries in {:.2}s ({:.0} entries/s): {} derived, {rejected} rejected",
+            elapsed.as_secs_f64(),
+            to_verify as f64 / elapsed.as_secs_f64().max(f64::EPSILON),
+            entries.len()
+        );
+        if rejected > 0 {
+            warn!("Dropped {rejected} entries whose seeds do not derive their pda");
+        }
+    }
+
+    if options.sort_by == SortOrder::Program {
+        info!("Sorting new entries by program id");
+        timings.time("sort by program", || {
+            entries.par_sort_unstable_by_key(|entry| (entry.program_id, entry.pda))
+        });
+    }
+
+    if options.warn_on_large_seeds {
+        timings
+            .time("seed stats", || SeedStats::collect(&entries))
+            .log();
+    }
+
+    let stats = MergeStats {
+        blob_files: blob_files.len(),
+        sqlite_files: sqlite_files.len(),
+        csv_files: csv_files.len(),
+        remote_blobs: remote_blobs.len(),
+        initial_entries: initial_count + known_skipped + duplicates_skipped,
+        vec_deduped,
+        hashset_deduped,
+        new_entries: entries.len(),
+    };
     info!(
-        "Merge operation completed: returning {} new entries, {} blob files, and original dedup hashset (entries will be added after successful uploads)",
+        "Merge operation completed: returning {} new entries, {} blob and {} csv files, and original dedup hashset (entries will be added after successful uploads)",
         entries.len(),
-        blob_files.len()
+        blob_files.len(),
+        csv_files.len()
     );
-    Ok((entries, blob_files, dedup_hashset))
+    Ok(MergeOutput {
+        entries,
+        files: blob_files
+            .into_iter()
+            .chain(csv_files)
+            .chain(resumed_files)
+            .collect(),
+        dedup_hashset,
+        stats,
+    })
 }
 
-pub fn save_dedup_hashset(
-    dedup_hashset: &HashSet<Address>,
+/// Blob, sqlite and csv source files, as found by [`scan_sources`].
+type SourceFiles = (Vec<PathBuf>, Vec<PathBuf>, Vec<PathBuf>);
+
+/// Loads the dedup hashset [`merge`] checks entries against, with the pdas added within
+/// `--dedup-lookback-secs` that are uploaded again.
+fn load_merge_dedup(
     dedup_hashset_path: &Path,
-) -> Result<()> {
+    options: &MergeOptions,
+) -> Result<(HashSet<Address>, HashSet<Address>)> {
+    let mut recent = HashSet::new();
+    let dedup_hashset = if options.dedup_bloom_bits.is_some() {
+        info!("Dedup bloom filter enabled, not loading the dedup hashset into memory");
+        HashSet::new()
+    } else if let Some(lookback_secs) = options.dedup_lookback_secs
+        && dedup_hashset_path.exists()
+    {
+        let timestamps = load_dedup_timestamps(dedup_hashset_path)?;
+        let cutoff = unix_now().saturating_sub(lookback_secs);
+        recent = timestamps
+            .iter()
+            .filter(|&(_, &added)| added >= cutoff)
+            .map(|(&address, _)| address)
+            .collect();
+        info!(
+            "Loaded dedup hashset with {} entries, {} of them added in the last {lookback_secs}s and uploaded again",
+            timestamps.len(),
+            recent.len()
+        );
+        timestamps.into_keys().collect()
+    } else if dedup_hashset_path.exists() {
+        info!(
+            "Loading existing dedup hashset from {}",
+            dedup_hashset_path.display()
+        );
+        let loaded =
+            load_dedup_hashset_with_capacity(dedup_hashset_path, options.dedup_hashset_capacity)
+                .unwrap_or_else(|err| {
+                    warn!("{err:#}; starting with an empty dedup hashset");
+                    HashSet::new()
+                });
+        info!("Loaded dedup hashset with {} entries", loaded.len());
+        loaded
+    } else {
+        info!("No existing dedup hashset found, starting fresh");
+        HashSet::new()
+    };
+    Ok((dedup_hashset, recent))
+}
+
+/// Finds the blob, sqlite and csv files under `path` that [`merge`] parses.
+fn scan_sources(path: Option<&Path>, options: &MergeOptions) -> Result<SourceFiles> {
+    let Some(path) = path else {
+        return Ok((Vec::new(), Vec::new(), Vec::new()));
+    };
+    let max_file_size = options.max_file_size.unwrap_or(u64::MAX);
+    let sources = match options.input_format {
+        InputFormat::Auto => (
+            collect_blob_files(
+                path,
+                max_file_size,
+                options.strict_utf8,
+                options.skip_future_mtime,
+            )?,
+            collect_sqlite_files(path, max_file_size)?,
+            collect_csv_files(path, max_file_size, options.skip_future_mtime)?,
+        ),
+        InputFormat::Blob => (
+            collect_regular_files(path, max_file_size, options.skip_future_mtime)?,
+            Vec::new(),
+            Vec::new(),
+        ),
+        InputFormat::Sqlite => (
+            Vec::new(),
+            collect_regular_files(path, max_file_size, options.skip_future_mtime)?,
+            Vec::new(),
+        ),
+        InputFormat::Csv => (
+            Vec::new(),
+            Vec::new(),
+            collect_regular_files(path, max_file_size, options.skip_future_mtime)?,
+        ),
+    };
     info!(
-        "Serializing dedup hashset with {} entries to {}",
-        dedup_hashset.len(),
-        dedup_hashset_path.display()
+        "Discovered {} blob file(s), {} sqlite file(s) and {} csv file(s) in {}",
+        sources.0.len(),
+        sources.1.len(),
+        sources.2.len(),
+        path.display()
     );
-    let temp_path = dedup_hashset_path.with_extension("tmp");
-    let mut writer = BufWriter::new(File::create(&temp_path)?);
-    bincode::serialize_into(&mut writer, &dedup_hashset)?;
+    Ok(sources)
+}
+
+pub fn write_merge_stats(stats: &MergeStats, path: &Path) -> Result<()> {
+    let file = File::create(path)
+        .wrap_err_with(|| format!("failed to create dedup stats file {}", path.display()))?;
+    let mut writer = BufWriter::new(file);
+    serde_json::to_writer_pretty(&mut writer, stats)
+        .wrap_err_with(|| format!("failed to write dedup stats file {}", path.display()))?;
+    writeln!(writer)?;
     writer.flush()?;
-    writer.get_mut().sync_all()?;
+    info!("Wrote dedup stats to {}", path.display());
+    Ok(())
+}
 
-    match std::fs::rename(&temp_path, dedup_hashset_path) {
-        Ok(()) => {
-            info!("Successfully saved dedup hashset");
+/// First bytes of a [`DedupFormat::Timestamped`] dedup hashset. `0xFF` never appears in the
+/// text format, and a bincode set would need an impossible length to start with it.
+const TIMESTAMPED_DEDUP_MAGIC: [u8; 8] = *b"\xffPDA-TS1";
+
+/// Loads a persisted dedup hashset in any [`DedupFormat`], see [`detect_dedup_format`].
+pub fn load_dedup_hashset(dedup_hashset_path: &Path) -> Result<HashSet<Address>> {
+    load_dedup_hashset_with_capacity(dedup_hashset_path, None)
+}
+
+/// Like [`load_dedup_hashset`], but sizes the set up front so it never rehashes while loading:
+/// to the length prefix of the bincode formats, or to `capacity_hint` for the text format,
+/// which has none.
+pub fn load_dedup_hashset_with_capacity(
+    dedup_hashset_path: &Path,
+    capacity_hint: Option<usize>,
+) -> Result<HashSet<Address>> {
+    match detect_dedup_format(dedup_hashset_path)? {
+        DedupFormat::Bincode => {}
+        DedupFormat::Text => {
+            return parse_address_list(dedup_hashset_path, capacity_hint.unwrap_or(0));
         }
-        Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
-            info!("Dedup hashset already exists, replacing it");
-            std::fs::remove_file(dedup_hashset_path)?;
-            std::fs::rename(&temp_path, dedup_hashset_path)?;
-            info!("Successfully replaced dedup hashset");
+        DedupFormat::Timestamped => {
+            return Ok(load_dedup_timestamps(dedup_hashset_path)?
+                .into_keys()
+                .collect());
         }
-        Err(err) => {
-            std::fs::remove_file(&temp_path).ok();
-            return Err(eyre!(
-                "failed to replace dedup hashset at {}: {err}",
+    }
+    load_dedup_entries::<Address, _>(
+        dedup_hashset_path,
+        0,
+        size_of::<Address>(),
+        HashSet::with_capacity,
+    )
+}
+
+/// Entries read between extends of the collection being loaded.
+const DEDUP_LOAD_BATCH: usize = 1 << 16;
+
+/// Loads the bincode collection of `entry_size`-byte entries that starts `offset` bytes into a
+/// dedup hashset file. serde pre-sizes collections only up to a small cap and reads entries a
+/// byte at a time, so the collection is instead created by `with_capacity` from its `u64`
+/// length prefix and filled in batches of whole entries, which loads a large set several times
+/// faster. A length the rest of the file cannot hold is rejected before allocating.
+fn load_dedup_entries<T, C>(
+    dedup_hashset_path: &Path,
+    offset: usize,
+    entry_size: usize,
+    with_capacity: impl FnOnce(usize) -> C,
+) -> Result<C>
+where
+    T: DeserializeOwned,
+    C: Extend<T>,
+{
+    let file = File::open(dedup_hashset_path).wrap_err_with(|| {
+        format!(
+            "failed to open dedup hashset {}",
+            dedup_hashset_path.display()
+        )
+    })?;
+    let file_len = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+    reader.seek_relative(offset as i64)?;
+    let mut prefix = [0; size_of::<u64>()];
+    reader.read_exact(&mut prefix).wrap_err_with(|| {
+        format!(
+            "failed to read length of dedup hashset {}",
+            dedup_hashset_path.display()
+        )
+    })?;
+    let len = u64::from_le_bytes(prefix);
+    let available = file_len.saturating_sub((offset + prefix.len()) as u64);
+    if len.saturating_mul(entry_size as u64) > available {
+        return Err(eyre!(
+            "dedup hashset {} claims {len} entries but holds only {available} bytes of them",
+            dedup_hashset_path.display()
+        ));
+    }
+
+    let len = len as usize;
+    let mut collection = with_capacity(len);
+    let mut batch = Vec::with_capacity(len.min(DEDUP_LOAD_BATCH));
+    let mut entry = vec![0; entry_size];
+    for index in 0..len {
+        reader.read_exact(&mut entry)?;
+        batch.push(bincode::deserialize(&entry).map_err(|err| {
+            eyre!(
+                "failed to deserialize entry {index} of dedup hashset {}: {err}",
                 dedup_hashset_path.display()
-            ));
+            )
+        })?);
+        if batch.len() == DEDUP_LOAD_BATCH {
+            collection.extend(batch.drain(..));
         }
     }
-    Ok(())
+    collection.extend(batch);
+    Ok(collection)
 }
 
-fn process_paths(
-    label: &'static str,
-    paths: &[PathBuf],
-    entries: &Arc<RwLock<Vec<PdaSqlite>>>,
-    processed_count: &AtomicUsize,
-    total_sources: usize,
-    parser: fn(&Path) -> Result<Vec<PdaSqlite>>,
-) -> Result<()> {
+/// Loads a persisted dedup hashset with the unix second each address was added. Sets saved
+/// in a format without timestamps migrate with every address at 0, so none of them falls
+/// within a lookback window.
+pub fn load_dedup_timestamps(dedup_hashset_path: &Path) -> Result<HashMap<Address, u64>> {
+    if detect_dedup_format(dedup_hashset_path)? != DedupFormat::Timestamped {
+        return Ok(load_dedup_hashset(dedup_hashset_path)?
+            .into_iter()
+            .map(|address| (address, 0))
+            .collect());
+    }
+    load_dedup_entries::<(Address, u64), _>(
+        dedup_hashset_path,
+        TIMESTAMPED_DEDUP_MAGIC.len(),
+        size_of::<Address>() + size_of::<u64>(),
+        HashMap::with_capacity,
+    )
+}
+
+/// Tells the dedup hashset formats apart from the first bytes of the file. A timestamped set
+/// starts with [`TIMESTAMPED_DEDUP_MAGIC`]. Otherwise, a bincode set starts with its `u64`
+/// length, whose high bytes are zero for any real set, while a text set never contains a NUL
+/// byte. An empty file is an empty text set.
+pub fn detect_dedup_format(dedup_hashset_path: &Path) -> Result<DedupFormat> {
+    let file = File::open(dedup_hashset_path).wrap_err_with(|| {
+        format!(
+            "failed to open dedup hashset {}",
+            dedup_hashset_path.display()
+        )
+    })?;
+    let mut head = Vec::with_capacity(8);
+    file.take(8).read_to_end(&mut head)?;
+    Ok(if head == TIMESTAMPED_DEDUP_MAGIC {
+        DedupFormat::Timestamped
+    } else if head.contains(&0) {
+        DedupFormat::Bincode
+    } else {
+        DedupFormat::Text
+    })
+}
+
+/// Retains only the dedup entries listed in `keep_path` and rewrites the set in place.
+/// Returns the number of pruned entries.
+pub fn prune_dedup_hashset(dedup_hashset_path: &Path, keep_path: &Path) -> Result<usize> {
+    retain_dedup_hashset(dedup_hashset_path, &read_address_list(keep_path)?)
+}
+
+/// Retains only the dedup entries in `keep` and atomically rewrites the set in its current
+/// format. Returns the number of pruned entries.
+pub fn retain_dedup_hashset(dedup_hashset_path: &Path, keep: &HashSet<Address>) -> Result<usize> {
+    let mut dedup_hashset = load_dedup_hashset(dedup_hashset_path)?;
     info!(
-        "Starting parallel processing of {} {label} file(s)",
-        paths.len()
+        "Pruning dedup hashset with {} entries against {} addresses to keep",
+        dedup_hashset.len(),
+        keep.len()
     );
-    paths.par_iter().try_for_each(|path| -> Result<()> {
-        let parsed = parser(path.as_path())
-            .wrap_err_with(|| format!("failed to parse {label} file {}", path.display()))?;
 
-        let current_len = {
-            let mut guard = entries
-                .write()
-                .map_err(|err| eyre!("entries lock poisoned: {err}"))?;
-            guard.extend(parsed);
-            guard.len()
-        };
+    let before = dedup_hashset.len();
+    dedup_hashset.retain(|address| keep.contains(address));
+    let pruned = before - dedup_hashset.len();
 
-        let processed = processed_count.fetch_add(1, atomic::Ordering::Relaxed) + 1;
+    let format = detect_dedup_format(dedup_hashset_path)?;
+    save_dedup_hashset(&dedup_hashset, dedup_hashset_path, format)?;
+    Ok(pruned)
+}
+
+/// Unions the dedup hashsets at `from_paths` into the one at `into_path`, which starts empty
+/// if it does not exist yet, keeping its format. Returns the size of `into_path` before and
+/// after.
+pub fn merge_dedup_hashsets(into_path: &Path, from_paths: &[PathBuf]) -> Result<(usize, usize)> {
+    let (mut dedup_hashset, format) = if into_path.exists() {
+        (
+            load_dedup_hashset(into_path)?,
+            detect_dedup_format(into_path)?,
+        )
+    } else {
         info!(
-            "Finished processing {label} file ({processed}/{total_sources}) {current_len} entries so far from {}",
-            path.display()
+            "No dedup hashset at {}, starting from an empty set",
+            into_path.display()
         );
+        (HashSet::new(), DedupFormat::default())
+    };
+    let before = dedup_hashset.len();
 
-        Ok(())
-    })
+    for from_path in from_paths {
+        let other = load_dedup_hashset(from_path)?;
+        let len = dedup_hashset.len();
+        dedup_hashset.extend(other.iter().copied());
+        info!(
+            "Merged {} entries from {} ({} new)",
+            other.len(),
+            from_path.display(),
+            dedup_hashset.len() - len
+        );
+    }
+
+    save_dedup_hashset(&dedup_hashset, into_path, format)?;
+    Ok((before, dedup_hashset.len()))
 }
 
-fn collect_blob_files(root: &Path) -> Result<Vec<PathBuf>> {
-    info!("Scanning for blob files in {}", root.display());
-    let now = SystemTime::now();
-    let mut files = Vec::new();
+/// Reads a file of base58 addresses, one per line. Blank lines and `#` comments are ignored.
+pub fn read_address_list(path: &Path) -> Result<HashSet<Address>> {
+    parse_address_list(path, 0)
+}
 
-    for entry in std::fs::read_dir(root)? {
-        let entry = entry?;
-        let path = entry.path();
-        let Some(filename_os) = path.file_name() else {
-            warn!(
-                "Skipping path without filename while scanning {}",
-                path.display()
-            );
-            continue;
-        };
+fn parse_address_list(path: &Path, capacity: usize) -> Result<HashSet<Address>> {
+    let contents = std::fs::read_to_string(path)
+        .wrap_err_with(|| format!("failed to read address list {}", path.display()))?;
 
-        let Some(filename) = filename_os.to_str() else {
-            warn!(
-                "Skipping non-UTF-8 filename while scanning {}",
-                path.display()
-            );
+    let mut addresses = HashSet::with_capacity(capacity);
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
             continue;
-        };
-
-        if filename.starts_with("pda_collector_") && filename.ends_with(".blob") {
-            let metadata = entry.metadata()?;
-            let age = now.duration_since(metadata.modified()?).unwrap_or_default();
-            if age > Duration::from_secs(5) {
-                files.push(path);
-            } else {
-                info!("Skipping blob file {filename} (age: {age:?}, needs > 5s)");
-            }
         }
+
+        let address = parse_address(line)
+            .wrap_err_with(|| format!("on line {} of {}", index + 1, path.display()))?;
+        addresses.insert(address);
     }
 
-    info!("Found {} eligible blob file(s)", files.len());
-    Ok(files)
+    info!("Read {} addresses from {}", addresses.len(), path.display());
+    Ok(addresses)
 }
 
-fn collect_sqlite_files(root: &Path) -> Result<Vec<PathBuf>> {
-    info!("Scanning for sqlite files in {}", root.display());
-    let mut files = Vec::new();
+/// Reads [`PdaJson`] entries (base58 addresses, hex seeds) from a JSON array, or from JSON
+/// lines such as a `--dead-letter` file, whose extra fields are ignored.
+pub fn read_pda_list_json(path: &Path) -> Result<Vec<PdaSqlite>> {
+    let raw = std::fs::read(path)
+        .wrap_err_with(|| format!("failed to open pda list {}", path.display()))?;
+    let raw: Vec<PdaJson> = if raw.trim_ascii_start().starts_with(b"[") {
+        serde_json::from_slice(&raw)
+            .wrap_err_with(|| format!("failed to parse pda list {}", path.display()))?
+    } else {
+        serde_json::Deserializer::from_slice(&raw)
+            .into_iter::<PdaJson>()
+            .collect::<Result<_, _>>()
+            .wrap_err_with(|| format!("failed to parse pda list {}", path.display()))?
+    };
 
-    for entry in std::fs::read_dir(root)? {
-        let entry = entry?;
-        let path = entry.path();
-        let Some(extension) = path.extension().and_then(|ext| ext.to_str()) else {
-            continue;
-        };
+    let entries = raw
+        .into_iter()
+        .enumerate()
+        .map(|(index, entry)| {
+            PdaSqlite::try_from(entry)
+                .wrap_err_with(|| format!("invalid entry {index} in {}", path.display()))
+        })
+        .collect::<Result<Vec<_>>>()?;
 
-        if extension == "sqlite" {
-            files.push(path);
-        }
+    info!("Read {} entries from {}", entries.len(), path.display());
+    Ok(entries)
+}
+
+/// Streams the base58 pda of each entry to `path`, one per line.
+pub fn write_pda_list(entries: &[PdaSqlite], path: &Path) -> Result<()> {
+    write_address_list(entries.iter().map(|entry| &entry.pda), path)
+}
+
+/// Writes base58 addresses to `path`, one per line, in the format [`read_address_list`] reads.
+pub fn write_address_list<'a>(
+    addresses: impl IntoIterator<Item = &'a Address>,
+    path: &Path,
+) -> Result<()> {
+    let file = File::create(path)
+        .wrap_err_with(|| format!("failed to create pda list {}", path.display()))?;
+    let mut writer = BufWriter::new(file);
+    let mut written = 0usize;
+    for address in addresses {
+        writeln!(writer, "{address}")?;
+        written += 1;
     }
+    writer.flush()?;
 
-    info!("Found {} sqlite file(s)", files.len());
-    Ok(files)
+    info!("Wrote {written} pdas to {}", path.display());
+    Ok(())
 }
 
-fn from_blob(path: &Path) -> Result<Vec<PdaSqlite>> {
-    info!("Deserializing blob file: {}", path.display());
-    let file = File::open(path)
-        .wrap_err_with(|| format!("failed to open blob file {}", path.display()))?;
-    let reader = BufReader::new(file);
-    let entries: Vec<PdaSqlite> = bincode::deserialize_from(reader)
-        .map_err(|err| eyre!("failed to deserialize blob file {}: {err}", path.display()))?;
+/// Moves uploaded source files into `processed_dir`, returning how many were moved. Files are
+/// renamed after a hash of their full source path, see [`processed_destination`], so files
+/// sharing a basename in different directories never overwrite each other.
+pub fn move_to_processed(files: &[PathBuf], processed_dir: &Path) -> Result<usize> {
+    std::fs::create_dir_all(processed_dir)
+        .wrap_err_with(|| format!("failed to create {}", processed_dir.display()))?;
+    for file in files {
+        let destination = processed_destination(processed_dir, file)?;
+        // Rename fails across filesystems; fall back to copying and removing the source.
+        if std::fs::rename(file, &destination).is_err() {
+            std::fs::copy(file, &destination).wrap_err_with(|| {
+                format!(
+                    "failed to move {} to {}",
+                    file.display(),
+                    destination.display()
+                )
+            })?;
+            std::fs::remove_file(file)
+                .wrap_err_with(|| format!("failed to remove {}", file.display()))?;
+        }
+        debug!("Moved {} to {}", file.display(), destination.display());
+    }
     info!(
-        "Deserialized {} entries from blob file: {}",
-        entries.len(),
-        path.display()
+        "Moved {} processed file(s) to {}",
+        files.len(),
+        processed_dir.display()
     );
-    Ok(entries)
+    Ok(files.len())
 }
 
-fn from_sqlite(path: &Path) -> Result<Vec<PdaSqlite>> {
-    info!("Opening sqlite file: {}", path.display());
-    let conn = rusqlite::Connection::open(path)
-        .wrap_err_with(|| format!("failed to open sqlite file {}", path.display()))?;
-    info!("Preparing query for sqlite file: {}", path.display());
-    let mut stmt = conn
-        .prepare("SELECT pda, program_id, seed_bytes FROM pda_registry")
-        .wrap_err_with(|| format!("failed to prepare statement for {}", path.display()))?;
+/// Free path in `processed_dir` for `source`: its basename prefixed with a hash of its absolute
+/// path, plus a numeric suffix if the same source path was already moved by an earlier run.
+/// The prefix also keeps the moved files from matching the collector's filename pattern.
+fn processed_destination(processed_dir: &Path, source: &Path) -> Result<PathBuf> {
+    let absolute = std::path::absolute(source)
+        .wrap_err_with(|| format!("failed to resolve {}", source.display()))?;
+    let file_name = source
+        .file_name()
+        .ok_or_else(|| eyre!("{} has no file name", source.display()))?;
+    let hash = format!(
+        "{:x}",
+        md5::compute(absolute.as_os_str().as_encoded_bytes())
+    );
 
-    let mut rows = stmt
-        .query([])
+    let mut name = std::ffi::OsString::from(format!("{}-", &hash[..16]));
+    name.push(file_name);
+    let mut destination = processed_dir.join(&name);
+    let mut attempt = 1;
+    while destination.exists() {
+        let mut numbered = name.clone();
+        numbered.push(format!(".{attempt}"));
+        destination = processed_dir.join(numbered);
+        attempt += 1;
+    }
+    Ok(destination)
+}
+
+/// Reads the distinct pdas of every blob and sqlite file under `path` without touching the
+/// dedup hashset, for comparing collector outputs.
+pub fn read_source_pdas(path: &Path) -> Result<HashSet<Address>> {
+    Ok(read_source_entries(path)?
+        .into_iter()
+        .map(|entry| entry.pda)
+        .collect())
+}
+
+/// Reads every entry of every blob and sqlite file under `path` without touching the dedup
+/// hashset. Entries are not deduplicated.
+pub fn read_source_entries(path: &Path) -> Result<Vec<PdaSqlite>> {
+    validate_input_dir(path)?;
+    let blob_files = collect_blob_files(path, u64::MAX, false, false)?;
+    let sqlite_files = collect_sqlite_files(path, u64::MAX)?;
+    let total_sources = blob_files.len() + sqlite_files.len();
+    info!(
+        "Reading {} blob file(s) and {} sqlite file(s) from {}",
+        blob_files.len(),
+        sqlite_files.len(),
+        path.display()
+    );
+
+    let entries: Arc<RwLock<Vec<PdaSqlite>>> = Arc::new(RwLock::new(Vec::new()));
+    let processed = AtomicUsize::new(0);
+    process_paths(
+        "blob",
+        &blob_files,
+        &entries,
+        &processed,
+        total_sources,
+        None,
+        from_blob,
+    )?;
+    process_paths(
+        "sqlite",
+        &sqlite_files,
+        &entries,
+        &processed,
+        total_sources,
+        None,
+        |path| from_sqlite(path, DEFAULT_SQLITE_OPEN_RETRIES),
+    )?;
+
+    let entries = Arc::try_unwrap(entries)
+        .map_err(|_| eyre!("failed to unwrap entries lock"))?
+        .into_inner()
+        .map_err(|err| eyre!("entries lock poisoned: {err}"))?;
+    Ok(entries)
+}
+
+/// Rows of collector sqlite files whose stored integrity columns disagree with their
+/// `seed_bytes`, as reported by [`verify_sqlite_sources`]. Files without a column are not
+/// checked against it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct SqliteIntegrity {
+    pub rows: usize,
+    /// Rows whose `seed_checksum` is not the crc32 of their `seed_bytes`.
+    pub checksum_mismatches: usize,
+    /// Rows whose `seed_count` is not the number of seeds their `seed_bytes` decode to.
+    pub seed_count_mismatches: usize,
+}
+
+impl SqliteIntegrity {
+    pub fn is_clean(&self) -> bool {
+        self.checksum_mismatches == 0 && self.seed_count_mismatches == 0
+    }
+
+    fn add(mut self, other: Self) -> Self {
+        self.rows += other.rows;
+        self.checksum_mismatches += other.checksum_mismatches;
+        self.seed_count_mismatches += other.seed_count_mismatches;
+        self
+    }
+}
+
+/// Reads every sqlite file under `path` and totals the rows whose seed checksum or seed count
+/// disagrees with their seed bytes, without touching the dedup hashset.
+pub fn verify_sqlite_sources(path: &Path) -> Result<SqliteIntegrity> {
+    validate_input_dir(path)?;
+    let sqlite_files = collect_sqlite_files(path, u64::MAX)?;
+    info!(
+        "Verifying {} sqlite file(s) in {}",
+        sqlite_files.len(),
+        path.display()
+    );
+    sqlite_files
+        .par_iter()
+        .map(|file| {
+            read_sqlite(file, DEFAULT_SQLITE_OPEN_RETRIES)
+                .map(|(_, integrity)| integrity)
+                .wrap_err_with(|| format!("failed to verify sqlite file {}", file.display()))
+        })
+        .try_reduce(SqliteIntegrity::default, |a, b| Ok(a.add(b)))
+}
+
+/// Number of addresses in a persisted dedup hashset, read from its bincode length prefix.
+pub fn dedup_hashset_len(dedup_hashset_path: &Path) -> Result<u64> {
+    let file = File::open(dedup_hashset_path).wrap_err_with(|| {
+        format!(
+            "failed to open dedup hashset {}",
+            dedup_hashset_path.display()
+        )
+    })?;
+    bincode::deserialize_from(BufReader::new(file)).map_err(|err| {
+        eyre!(
+            "failed to read length of dedup hashset {}: {err}",
+            dedup_hashset_path.display()
+        )
+    })
+}
+
+/// Feeds every address of a persisted dedup hashset to `visit` one at a time, without
+/// materializing the set. Returns the number of addresses read.
+pub fn stream_dedup_hashset(
+    dedup_hashset_path: &Path,
+    mut visit: impl FnMut(Address) -> Result<()>,
+) -> Result<u64> {
+    let format = detect_dedup_format(dedup_hashset_path)?;
+    if format != DedupFormat::Bincode {
+        return Err(eyre!(
+            "dedup hashset {} is in the {format:?} format, which cannot be streamed; convert it with --dedup-hashset-format bincode first",
+            dedup_hashset_path.display()
+        ));
+    }
+    let file = File::open(dedup_hashset_path).wrap_err_with(|| {
+        format!(
+            "failed to open dedup hashset {}",
+            dedup_hashset_path.display()
+        )
+    })?;
+    let mut reader = BufReader::new(file);
+    let len: u64 = bincode::deserialize_from(&mut reader).map_err(|err| {
+        eyre!(
+            "failed to read length of dedup hashset {}: {err}",
+            dedup_hashset_path.display()
+        )
+    })?;
+    for index in 0..len {
+        let address: Address = bincode::deserialize_from(&mut reader).map_err(|err| {
+            eyre!(
+                "failed to read address {index} of dedup hashset {}: {err}",
+                dedup_hashset_path.display()
+            )
+        })?;
+        visit(address)?;
+    }
+    Ok(len)
+}
+
+/// Loads the persisted bloom filter when it was built from the current dedup hashset with the
+/// same size, otherwise rebuilds it by streaming the set and persists the result.
+fn load_or_build_bloom(dedup_hashset_path: &Path, num_bits: u64) -> Result<BloomFilter> {
+    let path = bloom_path(dedup_hashset_path);
+    let source_len = dedup_hashset_len(dedup_hashset_path)?;
+    if path.exists() {
+        match BloomFilter::load(&path) {
+            Ok(bloom) if bloom.num_bits() == num_bits && bloom.source_len() == source_len => {
+                info!("Reusing bloom filter {}", path.display());
+                return Ok(bloom);
+            }
+            Ok(_) => info!("Bloom filter {} is stale, rebuilding", path.display()),
+            Err(err) => warn!("{err:#}; rebuilding bloom filter"),
+        }
+    }
+
+    info!("Building {num_bits}-bit bloom filter from {source_len} dedup entries");
+    let mut bloom = BloomFilter::new(num_bits, source_len);
+    stream_dedup_hashset(dedup_hashset_path, |address| {
+        bloom.insert(&address);
+        Ok(())
+    })?;
+    bloom.set_source_len(source_len);
+    if let Err(err) = bloom.save(&path) {
+        warn!("{err:#}; the bloom filter will be rebuilt next run");
+    }
+    Ok(bloom)
+}
+
+/// Exact set of entry pdas already in the persisted dedup hashset. The bloom filter rules out
+/// definitely-new pdas, and only its positives are confirmed against a streamed pass over the
+/// set, so false positives never drop an entry.
+fn bloom_confirmed_duplicates(
+    dedup_hashset_path: &Path,
+    num_bits: u64,
+    entries: &[PdaSqlite],
+) -> Result<HashSet<Address>> {
+    if !dedup_hashset_path.exists() {
+        info!("No existing dedup hashset found, starting fresh");
+        return Ok(HashSet::new());
+    }
+
+    let bloom = load_or_build_bloom(dedup_hashset_path, num_bits)?;
+    let positives: HashSet<Address> = entries
+        .par_iter()
+        .map(|entry| entry.pda)
+        .filter(|pda| bloom.contains(pda))
+        .collect();
+    drop(bloom);
+    info!(
+        "Bloom filter flagged {} of {} entries as possible duplicates",
+        positives.len(),
+        entries.len()
+    );
+    if positives.is_empty() {
+        return Ok(HashSet::new());
+    }
+
+    let mut confirmed = HashSet::new();
+    stream_dedup_hashset(dedup_hashset_path, |address| {
+        if positives.contains(&address) {
+            confirmed.insert(address);
+        }
+        Ok(())
+    })?;
+    info!(
+        "Confirmed {} duplicates, {} bloom false positives",
+        confirmed.len(),
+        positives.len() - confirmed.len()
+    );
+    Ok(confirmed)
+}
+
+/// Sibling of `path` a save writes before renaming it into place. The suffix is appended to
+/// the whole file name, so the `.shard-*` and `.db-*` variants of a path never share one.
+fn temp_path(path: &Path) -> PathBuf {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    PathBuf::from(temp_path)
+}
+
+/// Rewrites the persisted dedup hashset as its current contents plus `additions`, streaming
+/// the existing set instead of loading it. Safe to call repeatedly with a growing `additions`
+/// set, as addresses already on disk are not written twice. A persisted bloom filter that
+/// matched the old set is updated in place.
+pub fn save_dedup_hashset_streaming(
+    additions: &HashSet<Address>,
+    dedup_hashset_path: &Path,
+    fsync: FsyncMode,
+) -> Result<()> {
+    info!(
+        "Streaming dedup hashset {} with {} additions",
+        dedup_hashset_path.display(),
+        additions.len()
+    );
+    let temp_path = temp_path(dedup_hashset_path);
+    let mut writer = BufWriter::new(File::create(&temp_path)?);
+    // Placeholder length, patched once the number of written addresses is known.
+    bincode::serialize_into(&mut writer, &0u64)?;
+
+    let mut written = 0u64;
+    let previous_len = if dedup_hashset_path.exists() {
+        Some(stream_dedup_hashset(dedup_hashset_path, |address| {
+            if !additions.contains(&address) {
+                bincode::serialize_into(&mut writer, &address)?;
+                written += 1;
+            }
+            Ok(())
+        })?)
+    } else {
+        None
+    };
+    for address in additions {
+        bincode::serialize_into(&mut writer, address)?;
+        written += 1;
+    }
+
+    writer.flush()?;
+    let mut file = writer
+        .into_inner()
+        .map_err(|err| eyre!("failed to flush dedup hashset: {err}"))?;
+    file.seek(SeekFrom::Start(0))?;
+    bincode::serialize_into(&mut file, &written)?;
+    commit_dedup_hashset(&file, &temp_path, dedup_hashset_path, fsync)?;
+    info!("Saved dedup hashset with {written} entries");
+
+    let bloom_path = bloom_path(dedup_hashset_path);
+    if let Some(previous_len) = previous_len
+        && let Ok(mut bloom) = BloomFilter::load(&bloom_path)
+        && bloom.source_len() == previous_len
+    {
+        for address in additions {
+            bloom.insert(address);
+        }
+        bloom.set_source_len(written);
+        if let Err(err) = bloom.save(&bloom_path) {
+            warn!("{err:#}; the bloom filter will be rebuilt next run");
+        }
+    }
+    Ok(())
+}
+
+/// Atomically replaces the dedup hashset at `dedup_hashset_path` with `dedup_hashset`. The text
+/// format is sorted, so saving an unchanged set rewrites the file byte for byte.
+pub fn save_dedup_hashset(
+    dedup_hashset: &HashSet<Address>,
+    dedup_hashset_path: &Path,
+    format: DedupFormat,
+) -> Result<()> {
+    save_dedup_hashset_with_fsync(dedup_hashset, dedup_hashset_path, format, FsyncMode::Full)
+}
+
+/// [`save_dedup_hashset`] with the durability of the write chosen by `fsync`.
+pub fn save_dedup_hashset_with_fsync(
+    dedup_hashset: &HashSet<Address>,
+    dedup_hashset_path: &Path,
+    format: DedupFormat,
+    fsync: FsyncMode,
+) -> Result<()> {
+    info!(
+        "Serializing dedup hashset with {} entries to {} as {format:?}",
+        dedup_hashset.len(),
+        dedup_hashset_path.display()
+    );
+    let temp_path = temp_path(dedup_hashset_path);
+    let mut writer = BufWriter::new(File::create(&temp_path)?);
+    match format {
+        DedupFormat::Bincode => bincode::serialize_into(&mut writer, &dedup_hashset)?,
+        DedupFormat::Timestamped => {
+            let timestamps = stamp_dedup_hashset(dedup_hashset, dedup_hashset_path)?;
+            writer.write_all(&TIMESTAMPED_DEDUP_MAGIC)?;
+            bincode::serialize_into(&mut writer, &timestamps)?;
+        }
+        DedupFormat::Text => {
+            let mut lines: Vec<String> = dedup_hashset.par_iter().map(Address::to_string).collect();
+            lines.par_sort_unstable();
+            for line in lines {
+                writeln!(writer, "{line}")?;
+            }
+        }
+    }
+    writer.flush()?;
+
+    commit_dedup_hashset(writer.get_ref(), &temp_path, dedup_hashset_path, fsync)
+}
+
+/// Moves the fully written `temp_file` at `temp_path` over the dedup hashset, syncing the file
+/// before the rename or its directory after it as `fsync` asks.
+fn commit_dedup_hashset(
+    temp_file: &File,
+    temp_path: &Path,
+    dedup_hashset_path: &Path,
+    fsync: FsyncMode,
+) -> Result<()> {
+    if fsync == FsyncMode::Full {
+        temp_file.sync_all()?;
+    }
+    replace_dedup_hashset(temp_path, dedup_hashset_path)?;
+    if fsync == FsyncMode::RenameOnly {
+        let dir = dedup_hashset_path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        File::open(dir)
+            .and_then(|dir| dir.sync_all())
+            .wrap_err_with(|| format!("failed to sync directory {}", dir.display()))?;
+    }
+    Ok(())
+}
+
+/// Pairs every address of `dedup_hashset` with the time it was added: its stamp in the set
+/// already at `dedup_hashset_path` (0 if that set has no timestamps), or now for addresses
+/// new to it.
+fn stamp_dedup_hashset(
+    dedup_hashset: &HashSet<Address>,
+    dedup_hashset_path: &Path,
+) -> Result<HashMap<Address, u64>> {
+    let saved = if dedup_hashset_path.exists() {
+        load_dedup_timestamps(dedup_hashset_path)?
+    } else {
+        HashMap::new()
+    };
+    let now = unix_now();
+    Ok(dedup_hashset
+        .iter()
+        .map(|&address| (address, saved.get(&address).copied().unwrap_or(now)))
+        .collect())
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Atomically moves a fully written temp file over the dedup hashset.
+fn replace_dedup_hashset(temp_path: &Path, dedup_hashset_path: &Path) -> Result<()> {
+    match std::fs::rename(temp_path, dedup_hashset_path) {
+        Ok(()) => {
+            info!("Successfully saved dedup hashset");
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+            info!("Dedup hashset already exists, replacing it");
+            std::fs::remove_file(dedup_hashset_path)?;
+            std::fs::rename(temp_path, dedup_hashset_path)?;
+            info!("Successfully replaced dedup hashset");
+        }
+        Err(err) => {
+            std::fs::remove_file(temp_path).ok();
+            return Err(eyre!(
+                "failed to replace dedup hashset at {}: {err}",
+                dedup_hashset_path.display()
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Pdas already accounted for while parsing, so duplicates across files are dropped as each
+/// file is parsed rather than held until the final sort and dedup, which then only has to
+/// confirm. The set is sharded by the first pda byte to keep parser threads from contending.
+struct SeenPdas<'a> {
+    /// The loaded dedup hashset, whose pdas are dropped outright unless also in `recent`.
+    known: &'a HashSet<Address>,
+    /// Pdas within `--dedup-lookback-secs`, uploaded again despite being known.
+    recent: &'a HashSet<Address>,
+    shards: Vec<Mutex<HashSet<Address>>>,
+    /// Entries parsed, before anything was dropped.
+    parsed: AtomicUsize,
+    known_skipped: AtomicUsize,
+    duplicates_skipped: AtomicUsize,
+}
+
+impl<'a> SeenPdas<'a> {
+    fn new(
+        known: &'a HashSet<Address>,
+        recent: &'a HashSet<Address>,
+        resumed: &[PdaSqlite],
+    ) -> Self {
+        let mut shards: Vec<HashSet<Address>> = vec![HashSet::new(); 256];
+        for entry in resumed {
+            shards[usize::from(entry.pda.as_ref()[0])].insert(entry.pda);
+        }
+        Self {
+            known,
+            recent,
+            shards: shards.into_iter().map(Mutex::new).collect(),
+            parsed: AtomicUsize::new(0),
+            known_skipped: AtomicUsize::new(0),
+            duplicates_skipped: AtomicUsize::new(0),
+        }
+    }
+
+    fn is_known(&self, pda: &Address) -> bool {
+        self.known.contains(pda) && !self.recent.contains(pda)
+    }
+
+    /// Drops the entries of one parsed file that are in the dedup hashset or were already seen,
+    /// in this file or another.
+    fn retain_new(&self, parsed: &mut Vec<PdaSqlite>) -> Result<()> {
+        let total = parsed.len();
+        let mut known_skipped = 0;
+        let mut poisoned = false;
+        parsed.retain(|entry| {
+            if self.is_known(&entry.pda) {
+                known_skipped += 1;
+                return false;
+            }
+            match self.shards[usize::from(entry.pda.as_ref()[0])].lock() {
+                Ok(mut shard) => shard.insert(entry.pda),
+                Err(_) => {
+                    poisoned = true;
+                    true
+                }
+            }
+        });
+        if poisoned {
+            return Err(eyre!("seen pdas lock poisoned"));
+        }
+
+        self.parsed.fetch_add(total, atomic::Ordering::Relaxed);
+        self.known_skipped
+            .fetch_add(known_skipped, atomic::Ordering::Relaxed);
+        self.duplicates_skipped.fetch_add(
+            total - known_skipped - parsed.len(),
+            atomic::Ordering::Relaxed,
+        );
+        Ok(())
+    }
+}
+
+/// Per-file bookkeeping of [`merge`] that [`read_source_entries`] goes without.
+#[derive(Clone, Copy)]
+struct ParseHooks<'a> {
+    merge_state: Option<&'a MergeState>,
+    seen: &'a SeenPdas<'a>,
+}
+
+fn process_paths(
+    label: &'static str,
+    paths: &[PathBuf],
+    entries: &Arc<RwLock<Vec<PdaSqlite>>>,
+    processed_count: &AtomicUsize,
+    total_sources: usize,
+    hooks: Option<ParseHooks>,
+    parser: impl Fn(&Path) -> Result<Vec<PdaSqlite>> + Sync,
+) -> Result<()> {
+    info!(
+        "Starting parallel processing of {} {label} file(s)",
+        paths.len()
+    );
+    paths.par_iter().try_for_each(|path| -> Result<()> {
+        let mut parsed = parser(path.as_path())
+            .wrap_err_with(|| format!("failed to parse {label} file {}", path.display()))?;
+        if let Some(hooks) = hooks {
+            if let Some(merge_state) = hooks.merge_state {
+                merge_state.record(path, &parsed).wrap_err_with(|| {
+                    format!("failed to record {} in merge state", path.display())
+                })?;
+            }
+            hooks.seen.retain_new(&mut parsed)?;
+        }
+
+        let current_len = {
+            let mut guard = entries
+                .write()
+                .map_err(|err| eyre!("entries lock poisoned: {err}"))?;
+            guard.extend(parsed);
+            guard.len()
+        };
+
+        let processed = processed_count.fetch_add(1, atomic::Ordering::Relaxed) + 1;
+        info!(
+            "Finished processing {label} file ({processed}/{total_sources}) {current_len} entries so far from {}",
+            path.display()
+        );
+
+        Ok(())
+    })
+}
+
+fn validate_input_dir(path: &Path) -> Result<()> {
+    let metadata = std::fs::metadata(path).map_err(|err| {
+        eyre!(
+            "input path {} is not accessible: {err} (check the --path flag)",
+            path.display()
+        )
+    })?;
+
+    if !metadata.is_dir() {
+        return Err(eyre!(
+            "input path {} is not a directory (--path must point at the directory holding collector output)",
+            path.display()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Collects the settled `pda_collector_*.blob[.zst]` files directly under `root`. Names are
+/// matched as raw bytes, so names that are not valid UTF-8 still match unless `strict_utf8`
+/// skips them. See [`is_settled`] for files modified in the future.
+fn collect_blob_files(
+    root: &Path,
+    max_file_size: u64,
+    strict_utf8: bool,
+    skip_future_mtime: bool,
+) -> Result<Vec<PathBuf>> {
+    info!("Scanning for blob files in {}", root.display());
+    let now = SystemTime::now();
+    let mut files = Vec::new();
+    let mut oversized = 0usize;
+
+    let dir = std::fs::read_dir(root)
+        .wrap_err_with(|| format!("failed to read input directory {}", root.display()))?;
+    for entry in dir {
+        let entry = entry?;
+        let path = entry.path();
+        let Some(filename_os) = path.file_name() else {
+            warn!(
+                "Skipping path without filename while scanning {}",
+                path.display()
+            );
+            continue;
+        };
+
+        if strict_utf8 && filename_os.to_str().is_none() {
+            warn!(
+                "Skipping non-UTF-8 filename while scanning {}",
+                path.display()
+            );
+            continue;
+        }
+        let filename = filename_os.to_string_lossy();
+
+        let name = filename_os.as_encoded_bytes();
+        if name.starts_with(b"pda_collector_")
+            && (name.ends_with(b".blob") || name.ends_with(b".blob.zst"))
+        {
+            match source_readiness(&entry, &filename, max_file_size, now, skip_future_mtime)? {
+                Readiness::Ready => files.push(path),
+                Readiness::Oversized => oversized += 1,
+                Readiness::NotReady => {}
+            }
+        }
+    }
+
+    info!("Found {} eligible blob file(s)", files.len());
+    if oversized > 0 {
+        warn!("Skipped {oversized} blob file(s) larger than {max_file_size} bytes");
+    }
+    Ok(files)
+}
+
+/// How far in the future a source file's mtime may be before it is blamed on clock skew rather
+/// than on a file still being written.
+pub const MTIME_SKEW_TOLERANCE: Duration = Duration::from_secs(5);
+
+/// Whether a source file modified at `modified` has been left alone long enough (over 5s) to be
+/// parsed. An mtime up to [`MTIME_SKEW_TOLERANCE`] in the future counts as just written; one
+/// further ahead comes from a file server whose clock runs ahead, and would otherwise keep
+/// the file "too new" until our clock caught up, so it is warned about and the file treated as
+/// settled, or skipped with `skip_future_mtime`.
+fn is_settled(
+    filename: &str,
+    modified: SystemTime,
+    now: SystemTime,
+    skip_future_mtime: bool,
+) -> bool {
+    let age = match now.duration_since(modified) {
+        Ok(age) => age,
+        Err(err) if err.duration() <= MTIME_SKEW_TOLERANCE => Duration::ZERO,
+        Err(err) => {
+            let skew = err.duration();
+            if skip_future_mtime {
+                warn!(
+                    "Skipping file {filename}: modified {skew:?} in the future, check the clock of its file server"
+                );
+                return false;
+            }
+            warn!(
+                "File {filename} was modified {skew:?} in the future, probably clock skew on its file server; treating it as settled"
+            );
+            return true;
+        }
+    };
+    if age > Duration::from_secs(5) {
+        true
+    } else {
+        info!("Skipping file {filename} (age: {age:?}, needs > 5s)");
+        false
+    }
+}
+
+/// Whether a source file is too large to parse safely, warning when it is. Checked before
+/// parsing so a corrupt or runaway file cannot exhaust memory.
+fn exceeds_max_file_size(path: &Path, size: u64, max_file_size: u64) -> bool {
+    if size <= max_file_size {
+        return false;
+    }
+    warn!(
+        "Skipping {}: {size} bytes exceeds --max-file-size of {max_file_size} bytes",
+        path.display()
+    );
+    true
+}
+
+/// Whether the collector still holds `path` open, signalled by a sibling `<name>.lock` or
+/// `<name>.partial` file. The age check in [`source_readiness`] remains a secondary guard.
+fn has_writer_marker(path: &Path) -> bool {
+    WRITER_MARKER_SUFFIXES.iter().any(|suffix| {
+        let mut marker = path.as_os_str().to_owned();
+        marker.push(suffix);
+        Path::new(&marker).exists()
+    })
+}
+
+/// Suffixes of the files a collector keeps next to a source it is still writing.
+const WRITER_MARKER_SUFFIXES: [&str; 2] = [".lock", ".partial"];
+
+/// Whether a scanned source file can be parsed now, see [`source_readiness`].
+enum Readiness {
+    Ready,
+    /// Skipped for exceeding `--max-file-size`, counted for the scan's summary.
+    Oversized,
+    NotReady,
+}
+
+/// Checks a source file the scan matched: it must be a regular file within `max_file_size`,
+/// without a writer marker (see [`has_writer_marker`]) and settled (see [`is_settled`]).
+fn source_readiness(
+    entry: &std::fs::DirEntry,
+    filename: &str,
+    max_file_size: u64,
+    now: SystemTime,
+    skip_future_mtime: bool,
+) -> Result<Readiness> {
+    let path = entry.path();
+    let metadata = entry.metadata()?;
+    if !metadata.is_file() {
+        return Ok(Readiness::NotReady);
+    }
+    if has_writer_marker(&path) {
+        info!("Skipping file {filename}: collector lock/partial marker present");
+        return Ok(Readiness::NotReady);
+    }
+    if exceeds_max_file_size(&path, metadata.len(), max_file_size) {
+        return Ok(Readiness::Oversized);
+    }
+    Ok(
+        if is_settled(filename, metadata.modified()?, now, skip_future_mtime) {
+            Readiness::Ready
+        } else {
+            Readiness::NotReady
+        },
+    )
+}
+
+/// Every regular file directly under `root`, regardless of name, for a forced `--input-format`.
+/// The collector's own marker files are never sources, and the rest pass the same readiness
+/// checks as name-detected blobs.
+fn collect_regular_files(
+    root: &Path,
+    max_file_size: u64,
+    skip_future_mtime: bool,
+) -> Result<Vec<PathBuf>> {
+    info!("Scanning for files in {}", root.display());
+    let now = SystemTime::now();
+    let mut files = Vec::new();
+    let mut oversized = 0usize;
+
+    let dir = std::fs::read_dir(root)
+        .wrap_err_with(|| format!("failed to read input directory {}", root.display()))?;
+    for entry in dir {
+        let entry = entry?;
+        let filename = entry.file_name();
+        if WRITER_MARKER_SUFFIXES
+            .iter()
+            .any(|suffix| filename.as_encoded_bytes().ends_with(suffix.as_bytes()))
+        {
+            continue;
+        }
+        match source_readiness(
+            &entry,
+            &filename.to_string_lossy(),
+            max_file_size,
+            now,
+            skip_future_mtime,
+        )? {
+            Readiness::Ready => files.push(entry.path()),
+            Readiness::Oversized => oversized += 1,
+            Readiness::NotReady => {}
+        }
+    }
+
+    info!("Found {} file(s)", files.len());
+    if oversized > 0 {
+        warn!("Skipped {oversized} file(s) larger than {max_file_size} bytes");
+    }
+    Ok(files)
+}
+
+pub(crate) fn collect_sqlite_files(root: &Path, max_file_size: u64) -> Result<Vec<PathBuf>> {
+    info!("Scanning for sqlite files in {}", root.display());
+    let mut files = Vec::new();
+    let mut oversized = 0usize;
+
+    let dir = std::fs::read_dir(root)
+        .wrap_err_with(|| format!("failed to read input directory {}", root.display()))?;
+    for entry in dir {
+        let entry = entry?;
+        let path = entry.path();
+        let Some(extension) = path.extension().and_then(|ext| ext.to_str()) else {
+            continue;
+        };
+
+        if extension == "sqlite" {
+            if exceeds_max_file_size(&path, entry.metadata()?.len(), max_file_size) {
+                oversized += 1;
+                continue;
+            }
+            files.push(path);
+        }
+    }
+
+    info!("Found {} sqlite file(s)", files.len());
+    if oversized > 0 {
+        warn!("Skipped {oversized} sqlite file(s) larger than {max_file_size} bytes");
+    }
+    Ok(files)
+}
+
+/// Collects the `*.csv` and gzipped `*.csv.gz` exports directly under `root` that pass the
+/// same readiness checks as blobs.
+fn collect_csv_files(
+    root: &Path,
+    max_file_size: u64,
+    skip_future_mtime: bool,
+) -> Result<Vec<PathBuf>> {
+    info!("Scanning for csv files in {}", root.display());
+    let now = SystemTime::now();
+    let mut files = Vec::new();
+    let mut oversized = 0usize;
+
+    let dir = std::fs::read_dir(root)
+        .wrap_err_with(|| format!("failed to read input directory {}", root.display()))?;
+    for entry in dir {
+        let entry = entry?;
+        let filename = entry.file_name();
+        let name = filename.as_encoded_bytes();
+        if name.ends_with(b".csv") || name.ends_with(b".csv.gz") {
+            match source_readiness(
+                &entry,
+                &filename.to_string_lossy(),
+                max_file_size,
+                now,
+                skip_future_mtime,
+            )? {
+                Readiness::Ready => files.push(entry.path()),
+                Readiness::Oversized => oversized += 1,
+                Readiness::NotReady => {}
+            }
+        }
+    }
+
+    info!("Found {} csv file(s)", files.len());
+    if oversized > 0 {
+        warn!("Skipped {oversized} csv file(s) larger than {max_file_size} bytes");
+    }
+    Ok(files)
+}
+
+/// Magic bytes opening every versioned collector blob, followed by a little-endian `u16` version.
+pub const BLOB_MAGIC: [u8; 8] = *b"PDACBLOB";
+/// Latest blob layout: the header followed by a bincode `Vec<PdaSqlite>`.
+pub const BLOB_VERSION: u16 = 1;
+/// Bytes of magic and version before the entries of a blob written by [`write_blob`].
+pub const BLOB_HEADER_LEN: usize = BLOB_MAGIC.len() + std::mem::size_of::<u16>();
+
+/// Writes `entries` as a single [`BLOB_VERSION`] blob at `path`, parsed like any collector
+/// output, zstd-compressed when `path` ends in `.zst` as [`from_blob`] expects. The blob is
+/// written to a temp file and renamed into place, so a concurrent scan never picks it up half
+/// written.
+pub fn write_blob(entries: &[PdaSqlite], path: &Path) -> Result<()> {
+    write_blob_with_index(entries, path, None)
+}
+
+/// Like [`write_blob`], recording each entry in `index` with the uncompressed bytes it was
+/// written to.
+pub fn write_indexed_blob(
+    entries: &[PdaSqlite],
+    path: &Path,
+    index: &mut ProgramIndex,
+) -> Result<()> {
+    write_blob_with_index(entries, path, Some(index))
+}
+
+fn write_blob_with_index(
+    entries: &[PdaSqlite],
+    path: &Path,
+    index: Option<&mut ProgramIndex>,
+) -> Result<()> {
+    let temp_path = path.with_extension("tmp");
+    let file = File::create(&temp_path)
+        .wrap_err_with(|| format!("failed to create blob file {}", temp_path.display()))?;
+    let is_zstd = path.extension().is_some_and(|ext| ext == "zst");
+    let file = if is_zstd {
+        let mut encoder = zstd::stream::write::Encoder::new(
+            BufWriter::new(file),
+            zstd::DEFAULT_COMPRESSION_LEVEL,
+        )
+        .wrap_err_with(|| format!("failed to initialize zstd encoder for {}", path.display()))?;
+        write_blob_entries(&mut encoder, entries, path, index)?;
+        encoder
+            .finish()?
+            .into_inner()
+            .map_err(|err| err.into_error())?
+    } else {
+        let mut writer = BufWriter::new(file);
+        write_blob_entries(&mut writer, entries, path, index)?;
+        writer.into_inner().map_err(|err| err.into_error())?
+    };
+    file.sync_all()?;
+    std::fs::rename(&temp_path, path).wrap_err_with(|| {
+        format!(
+            "failed to move {} into place at {}",
+            temp_path.display(),
+            path.display()
+        )
+    })?;
+    info!("Wrote {} entries to blob {}", entries.len(), path.display());
+    Ok(())
+}
+
+fn write_blob_entries(
+    writer: &mut impl Write,
+    entries: &[PdaSqlite],
+    path: &Path,
+    mut index: Option<&mut ProgramIndex>,
+) -> Result<()> {
+    let mut writer = CountingWriter { writer, written: 0 };
+    writer.write_all(&BLOB_MAGIC)?;
+    writer.write_all(&BLOB_VERSION.to_le_bytes())?;
+    // Serialized the way bincode writes the `Vec` it is read back as: the u64 length, then
+    // each entry, which lets the index record where every entry landed.
+    let serialize_err = |err| eyre!("failed to serialize blob {}: {err}", path.display());
+    bincode::serialize_into(&mut writer, &(entries.len() as u64)).map_err(serialize_err)?;
+    for entry in entries {
+        let offset = writer.written;
+        bincode::serialize_into(&mut writer, entry).map_err(serialize_err)?;
+        if let Some(index) = index.as_deref_mut() {
+            index.push(entry, Some((offset, writer.written - offset)))?;
+        }
+    }
+    Ok(())
+}
+
+/// Counts the bytes passed through to `writer`.
+struct CountingWriter<W> {
+    writer: W,
+    written: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.writer.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+pub(crate) fn from_blob(path: &Path) -> Result<Vec<PdaSqlite>> {
+    info!("Deserializing blob file: {}", path.display());
+    let file = File::open(path)
+        .wrap_err_with(|| format!("failed to open blob file {}", path.display()))?;
+    let reader = BufReader::new(file);
+    let is_zstd = path.extension().is_some_and(|ext| ext == "zst");
+    let entries = if is_zstd {
+        let decoder = zstd::stream::read::Decoder::with_buffer(reader).wrap_err_with(|| {
+            format!("failed to initialize zstd decoder for {}", path.display())
+        })?;
+        read_blob_entries(decoder, path)
+    } else {
+        read_blob_entries(reader, path)
+    }?;
+    info!(
+        "Deserialized {} entries from blob file: {}",
+        entries.len(),
+        path.display()
+    );
+    Ok(entries)
+}
+
+/// Parses a downloaded blob exactly like [`from_blob`] parses one on disk, header check
+/// included.
+fn from_remote_blob(blob: &RemoteBlob) -> Result<Vec<PdaSqlite>> {
+    let name = Path::new(&blob.url);
+    let path = blob.url.split(['?', '#']).next().unwrap_or_default();
+    let entries = if path.ends_with(".zst") {
+        let decoder = zstd::stream::read::Decoder::new(blob.bytes.as_slice())
+            .wrap_err_with(|| format!("failed to initialize zstd decoder for {}", blob.url))?;
+        read_blob_entries(decoder, name)
+    } else {
+        read_blob_entries(blob.bytes.as_slice(), name)
+    }?;
+    info!(
+        "Deserialized {} entries from remote blob: {}",
+        entries.len(),
+        blob.url
+    );
+    Ok(entries)
+}
+
+/// Checks the blob header and dispatches on its version. Blobs written before the header was
+/// introduced start straight with the bincode vector length, so they are read by the legacy
+/// path instead of being rejected.
+fn read_blob_entries(mut reader: impl Read, path: &Path) -> Result<Vec<PdaSqlite>> {
+    let mut header = [0u8; BLOB_HEADER_LEN];
+    let mut header_len = 0;
+    while header_len < BLOB_HEADER_LEN {
+        let read = reader
+            .read(&mut header[header_len..])
+            .wrap_err_with(|| format!("failed to read blob header of {}", path.display()))?;
+        if read == 0 {
+            break;
+        }
+        header_len += read;
+    }
+
+    if header_len == BLOB_HEADER_LEN && header[..BLOB_MAGIC.len()] == BLOB_MAGIC {
+        let version = u16::from_le_bytes([header[BLOB_MAGIC.len()], header[BLOB_MAGIC.len() + 1]]);
+        return match version {
+            1 => bincode::deserialize_from(reader).map_err(|err| {
+                eyre!(
+                    "failed to deserialize v{version} blob file {}: {err}",
+                    path.display()
+                )
+            }),
+            _ => Err(eyre!(
+                "blob file {} has unsupported version {version} (latest supported is {BLOB_VERSION})",
+                path.display()
+            )),
+        };
+    }
+
+    let legacy = std::io::Cursor::new(&header[..header_len]).chain(reader);
+    let entries: Vec<PdaSqlite> = bincode::deserialize_from(legacy).map_err(|err| {
+        eyre!(
+            "this doesn't look like a pda_collector blob: {} has no {:?} magic header and does not parse as a legacy headerless blob: {err}",
+            path.display(),
+            String::from_utf8_lossy(&BLOB_MAGIC)
+        )
+    })?;
+    warn!(
+        "Read legacy headerless blob file {}; rewrite it with a v{BLOB_VERSION} header",
+        path.display()
+    );
+    Ok(entries)
+}
+
+/// Reads a CSV export with a `pda,program_id,seeds` header: base58 addresses and
+/// `;`-separated hex seeds. Files ending in `.gz` are decompressed as they are read.
+fn from_csv(path: &Path) -> Result<Vec<PdaSqlite>> {
+    info!("Reading csv file: {}", path.display());
+    let file =
+        File::open(path).wrap_err_with(|| format!("failed to open csv file {}", path.display()))?;
+    let reader = BufReader::new(file);
+    if path.extension().is_some_and(|ext| ext == "gz") {
+        read_csv_entries(flate2::read::MultiGzDecoder::new(reader), path)
+    } else {
+        read_csv_entries(reader, path)
+    }
+}
+
+fn read_csv_entries(reader: impl Read, path: &Path) -> Result<Vec<PdaSqlite>> {
+    let mut reader = csv::Reader::from_reader(reader);
+    let entries = reader
+        .deserialize::<PdaCsvRow>()
+        .enumerate()
+        .map(|(index, row)| {
+            let row = row.wrap_err_with(|| {
+                format!("failed to read row {} of {}", index + 1, path.display())
+            })?;
+            PdaSqlite::try_from(PdaJson::from(row))
+                .wrap_err_with(|| format!("invalid row {} in {}", index + 1, path.display()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    info!(
+        "Read {} entries from csv file: {}",
+        entries.len(),
+        path.display()
+    );
+    Ok(entries)
+}
+
+fn from_sqlite(path: &Path, open_retries: u32) -> Result<Vec<PdaSqlite>> {
+    read_sqlite(path, open_retries).map(|(entries, _integrity)| entries)
+}
+
+/// Runs `op` up to `retries` more times, with exponential backoff starting at 100ms, while
+/// sqlite reports the database busy or locked. Other errors, such as a missing file or one
+/// that is not a database, are returned immediately.
+fn retry_sqlite_busy<T>(
+    path: &Path,
+    retries: u32,
+    mut op: impl FnMut() -> rusqlite::Result<T>,
+) -> rusqlite::Result<T> {
+    let mut attempt = 0;
+    loop {
+        match op() {
+            Err(err) if attempt < retries && is_sqlite_busy(&err) => {
+                let backoff = Duration::from_millis(100 << attempt.min(6));
+                attempt += 1;
+                warn!(
+                    "sqlite file {} is busy ({err}), retrying in {backoff:?} ({attempt}/{retries})",
+                    path.display()
+                );
+                std::thread::sleep(backoff);
+            }
+            result => return result,
+        }
+    }
+}
+
+fn is_sqlite_busy(err: &rusqlite::Error) -> bool {
+    matches!(
+        err.sqlite_error_code(),
+        Some(rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked)
+    )
+}
+
+/// Reads every entry of a sqlite file, returning them with the number of rows whose
+/// `seed_checksum` does not match their `seed_bytes`. Mismatched rows are kept but warned
+/// about; files without a `seed_checksum` column, and rows where it is NULL, are not checked.
+fn read_sqlite(path: &Path, open_retries: u32) -> Result<(Vec<PdaSqlite>, SqliteIntegrity)> {
+    info!("Opening sqlite file: {}", path.display());
+    let (conn, has_checksums, has_seed_counts) = retry_sqlite_busy(path, open_retries, || {
+        let conn = rusqlite::Connection::open(path)?;
+        let has_column = |column: &str| {
+            conn.prepare("SELECT 1 FROM pragma_table_info('pda_registry') WHERE name = ?1")
+                .and_then(|mut stmt| stmt.exists([column]))
+        };
+        let has_checksums = has_column("seed_checksum")?;
+        let has_seed_counts = has_column("seed_count")?;
+        Ok((conn, has_checksums, has_seed_counts))
+    })
+    .wrap_err_with(|| format!("failed to open sqlite file {}", path.display()))?;
+    info!("Preparing query for sqlite file: {}", path.display());
+    let query = format!(
+        "SELECT pda, program_id, seed_bytes, {}, {} FROM pda_registry",
+        if has_checksums {
+            "seed_checksum"
+        } else {
+            "NULL"
+        },
+        if has_seed_counts {
+            "seed_count"
+        } else {
+            "NULL"
+        },
+    );
+    let mut stmt = retry_sqlite_busy(path, open_retries, || conn.prepare(&query))
+        .wrap_err_with(|| format!("failed to prepare statement for {}", path.display()))?;
+
+    let mut rows = stmt
+        .query([])
         .wrap_err_with(|| format!("failed to query sqlite file {}", path.display()))?;
 
     let mut entries = Vec::new();
+    let mut encoding_counts = HashMap::new();
+    let mut integrity = SqliteIntegrity::default();
     while let Some(row) = rows
         .next()
         .wrap_err_with(|| format!("failed to read row in {}", path.display()))?
@@ -273,54 +2033,36 @@ fn from_sqlite(path: &Path) -> Result<Vec<PdaSqlite>> {
         let pda_bytes: Vec<u8> = row.get(0)?;
         let program_id_bytes: Vec<u8> = row.get(1)?;
         let seed_bytes: Vec<u8> = row.get(2)?;
-        let seeds: Vec<Vec<u8>> = decode_seeds_from_storage(seed_bytes);
-
-        fn decode_seeds_from_storage(seeds_raw: Vec<u8>) -> Vec<Vec<u8>> {
-            let mut cursor = 0;
-            let mut seeds = Vec::new();
-
-            // Read the number of seeds
-            if seeds_raw.len() < 4 {
-                return seeds; // Empty or invalid data
-            }
-
-            let num_seeds = u32::from_le_bytes([
-                seeds_raw[cursor],
-                seeds_raw[cursor + 1],
-                seeds_raw[cursor + 2],
-                seeds_raw[cursor + 3],
-            ]) as usize;
-            cursor += 4;
-
-            // Read each seed
-            for _ in 0..num_seeds {
-                if cursor + 4 > seeds_raw.len() {
-                    break; // Not enough data for seed length
-                }
-
-                let seed_len = u32::from_le_bytes([
-                    seeds_raw[cursor],
-                    seeds_raw[cursor + 1],
-                    seeds_raw[cursor + 2],
-                    seeds_raw[cursor + 3],
-                ]) as usize;
-                cursor += 4;
-
-                if cursor + seed_len > seeds_raw.len() {
-                    break; // Not enough data for seed content
-                }
+        let seed_checksum: Option<i64> = row.get(3)?;
+        let seed_count: Option<i64> = row.get(4)?;
+        let (seeds, encoding) = decode_seeds(&seed_bytes);
+        *encoding_counts.entry(encoding).or_insert(0usize) += 1;
 
-                let seed = seeds_raw[cursor..cursor + seed_len].to_vec();
-                seeds.push(seed);
-                cursor += seed_len;
+        let pda = decode_address(&pda_bytes, "pda", path.display())?;
+        if let Some(stored) = seed_checksum {
+            let actual = schema::seed_checksum(&seed_bytes);
+            if stored != i64::from(actual) {
+                integrity.checksum_mismatches += 1;
+                warn!(
+                    "Seed checksum mismatch for pda {pda} in {}: stored {stored}, computed {actual}",
+                    path.display()
+                );
             }
-
-            seeds
+        }
+        if let Some(stored) = seed_count
+            && usize::try_from(stored).ok() != Some(seeds.len())
+        {
+            integrity.seed_count_mismatches += 1;
+            warn!(
+                "Seed count mismatch for pda {pda} in {}: stored {stored}, seed bytes decode to {} seed(s)",
+                path.display(),
+                seeds.len()
+            );
         }
 
         entries.push(PdaSqlite {
-            pda: decode_address(pda_bytes, "pda", path)?,
-            program_id: decode_address(program_id_bytes, "program_id", path)?,
+            pda,
+            program_id: decode_address(&program_id_bytes, "program_id", path.display())?,
             seeds,
         });
     }
@@ -330,17 +2072,1160 @@ fn from_sqlite(path: &Path) -> Result<Vec<PdaSqlite>> {
         entries.len(),
         path.display()
     );
-    Ok(entries)
+    if !encoding_counts.is_empty() {
+        let mut distribution = encoding_counts
+            .iter()
+            .map(|(encoding, count)| format!("{encoding:?}={count}"))
+            .collect::<Vec<_>>();
+        distribution.sort();
+        info!(
+            "Seed encodings in {}: {}",
+            path.display(),
+            distribution.join(", ")
+        );
+    }
+    integrity.rows = entries.len();
+    if integrity.checksum_mismatches > 0 {
+        warn!(
+            "{} of {} rows in {} failed seed checksum verification",
+            integrity.checksum_mismatches,
+            entries.len(),
+            path.display()
+        );
+    }
+    if integrity.seed_count_mismatches > 0 {
+        warn!(
+            "{} of {} rows in {} store a seed_count that disagrees with their seed bytes",
+            integrity.seed_count_mismatches,
+            entries.len(),
+            path.display()
+        );
+    }
+    Ok((entries, integrity))
+}
+
+/// Whether the entry's seeds derive its `pda` under `program_id`. Collectors usually record the
+/// bump as the last seed, so the stored seeds are tried as-is first; otherwise the canonical bump
+/// search (255 down to 0) runs over them, exactly as `find_program_address` does on chain.
+pub(crate) fn derives_pda(entry: &PdaSqlite) -> bool {
+    let seeds: Vec<&[u8]> = entry.seeds.iter().map(Vec::as_slice).collect();
+    if Address::create_program_address(&seeds, &entry.program_id)
+        .is_ok_and(|derived| derived == entry.pda)
+    {
+        return true;
+    }
+    Address::try_find_program_address(&seeds, &entry.program_id)
+        .is_some_and(|(derived, _bump)| derived == entry.pda)
+}
+
+/// Rewrites the program id of every entry whose program is a key of `remap`, returning how
+/// many entries changed.
+fn remap_programs(entries: &mut [PdaSqlite], remap: &HashMap<Address, Address>) -> usize {
+    entries
+        .par_iter_mut()
+        .filter_map(|entry| {
+            let new_program_id = remap.get(&entry.program_id)?;
+            entry.program_id = *new_program_id;
+            Some(())
+        })
+        .count()
+}
+
+/// Strips trailing zero bytes from every seed that is exactly `pad_len` bytes long, returning
+/// how many seeds changed.
+///
+/// This is inherently ambiguous: a seed that legitimately ends in zero (a little-endian
+/// counter, a pubkey whose last byte is 0) is indistinguishable from a padded one and gets
+/// trimmed too, which then no longer derives its pda. Seeds of any other length, such as the
+/// one-byte bump, are left alone.
+pub(crate) fn trim_seed_padding(entries: &mut [PdaSqlite], pad_len: usize) -> usize {
+    entries
+        .par_iter_mut()
+        .map(|entry| {
+            let mut trimmed = 0;
+            for seed in entry.seeds.iter_mut().filter(|seed| seed.len() == pad_len) {
+                let len = seed
+                    .iter()
+                    .rposition(|&byte| byte != 0)
+                    .map_or(0, |i| i + 1);
+                if len < seed.len() {
+                    seed.truncate(len);
+                    trimmed += 1;
+                }
+            }
+            trimmed
+        })
+        .sum()
+}
+
+/// On-disk encodings of the `seed_bytes` column seen across collector databases.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum SeedEncoding {
+    /// `u32` seed count followed by `u32`-length-prefixed seeds, all little endian.
+    LengthPrefixed,
+    /// `bincode` serialization of `Vec<Vec<u8>>`.
+    Bincode,
+    /// JSON array of hex strings.
+    JsonHex,
+    /// None of the above matched exactly; decoded leniently as length-prefixed.
+    Unknown,
+}
+
+/// Sniffs the encoding of a `seed_bytes` value and normalizes it into seeds. Each decoder has
+/// to consume the whole buffer to be accepted, tried in the order of [`SeedEncoding`].
+pub(crate) fn decode_seeds(seeds_raw: &[u8]) -> (Vec<Vec<u8>>, SeedEncoding) {
+    let (seeds, complete) = decode_length_prefixed_seeds(seeds_raw);
+    if complete {
+        return (seeds, SeedEncoding::LengthPrefixed);
+    }
+
+    let mut reader = seeds_raw;
+    if let Ok(bincode_seeds) = bincode::deserialize_from::<_, Vec<Vec<u8>>>(&mut reader)
+        && reader.is_empty()
+    {
+        return (bincode_seeds, SeedEncoding::Bincode);
+    }
+
+    if seeds_raw.first() == Some(&b'[')
+        && let Ok(hex_seeds) = serde_json::from_slice::<Vec<String>>(seeds_raw)
+        && let Ok(json_seeds) = hex_seeds.iter().map(hex::decode).collect()
+    {
+        return (json_seeds, SeedEncoding::JsonHex);
+    }
+
+    (seeds, SeedEncoding::Unknown)
+}
+
+/// Decodes the length-prefixed layout written by `build_insert_script`, stopping at the first
+/// truncated seed. The flag reports whether the buffer was consumed exactly. The counts come
+/// from untrusted files, so every offset is computed with checked arithmetic and the seed
+/// count is checked against the bytes left before anything is allocated for it.
+fn decode_length_prefixed_seeds(seeds_raw: &[u8]) -> (Vec<Vec<u8>>, bool) {
+    let Some(num_seeds) = read_u32_le(seeds_raw, 0) else {
+        return (Vec::new(), false); // Empty or invalid data
+    };
+    let mut cursor = 4;
+
+    // Every seed takes at least its 4-byte length.
+    let max_seeds = (seeds_raw.len() - cursor) / 4;
+    let mut seeds = Vec::with_capacity(num_seeds.min(max_seeds));
+
+    for _ in 0..num_seeds {
+        let Some(seed_len) = read_u32_le(seeds_raw, cursor) else {
+            return (seeds, false); // Not enough data for seed length
+        };
+        cursor += 4;
+
+        let Some(seed_end) = cursor
+            .checked_add(seed_len)
+            .filter(|&end| end <= seeds_raw.len())
+        else {
+            return (seeds, false); // Not enough data for seed content
+        };
+
+        seeds.push(seeds_raw[cursor..seed_end].to_vec());
+        cursor = seed_end;
+    }
+
+    (seeds, cursor == seeds_raw.len())
 }
 
-fn decode_address(bytes: Vec<u8>, field: &str, path: &Path) -> Result<Address> {
-    let array: [u8; 32] = bytes.try_into().map_err(|bytes: Vec<u8>| {
+/// The little-endian `u32` at `offset`, as a `usize`, or `None` past the end of `bytes`.
+fn read_u32_le(bytes: &[u8], offset: usize) -> Option<usize> {
+    let end = offset.checked_add(4)?;
+    let value: [u8; 4] = bytes.get(offset..end)?.try_into().ok()?;
+    usize::try_from(u32::from_le_bytes(value)).ok()
+}
+
+/// Length in bytes of a raw address column.
+pub(crate) const ADDRESS_LEN: usize = 32;
+
+/// Leading bytes hex-dumped in [`decode_address`] errors.
+const ADDRESS_DUMP_LEN: usize = 8;
+
+/// Decodes a raw address column read from `source`, a file or a database. Every reader of
+/// binary addresses goes through here so a malformed value always reports the field, where it
+/// came from, the expected and actual lengths, and the leading bytes.
+pub(crate) fn decode_address(
+    bytes: &[u8],
+    field: &str,
+    source: impl std::fmt::Display,
+) -> Result<Address> {
+    let array: [u8; ADDRESS_LEN] = bytes.try_into().map_err(|_| {
+        let dump = match bytes.len() {
+            0 => String::new(),
+            len if len > ADDRESS_DUMP_LEN => {
+                format!(
+                    " (starts with {}...)",
+                    hex::encode(&bytes[..ADDRESS_DUMP_LEN])
+                )
+            }
+            _ => format!(" (starts with {})", hex::encode(bytes)),
+        };
         eyre!(
-            "expected 32 bytes for {field} in sqlite file {}, got {}",
-            path.display(),
+            "invalid {field} in {source}: expected {ADDRESS_LEN} bytes, got {}{dump}",
             bytes.len()
         )
     })?;
 
     Ok(Address::new_from_array(array))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(index: u8) -> PdaSqlite {
+        PdaSqlite {
+            pda: Address::from([index; 32]),
+            seeds: vec![vec![index]],
+            program_id: Address::from([0xAA; 32]),
+        }
+    }
+
+    /// Backdates `path` so the scan treats it as settled rather than still being written.
+    fn backdate(path: &Path) {
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(60))
+            .unwrap();
+    }
+
+    fn sample_seeds() -> Vec<Vec<u8>> {
+        vec![b"vault".to_vec(), vec![7; 32], vec![254]]
+    }
+
+    fn length_prefixed(seeds: &[Vec<u8>]) -> Vec<u8> {
+        let mut raw = (seeds.len() as u32).to_le_bytes().to_vec();
+        for seed in seeds {
+            raw.extend_from_slice(&(seed.len() as u32).to_le_bytes());
+            raw.extend_from_slice(seed);
+        }
+        raw
+    }
+
+    #[test]
+    fn decodes_length_prefixed_seeds() {
+        let raw = length_prefixed(&sample_seeds());
+        assert_eq!(
+            decode_seeds(&raw),
+            (sample_seeds(), SeedEncoding::LengthPrefixed)
+        );
+    }
+
+    #[test]
+    fn decodes_empty_length_prefixed_seeds() {
+        assert_eq!(
+            decode_seeds(&0u32.to_le_bytes()),
+            (Vec::new(), SeedEncoding::LengthPrefixed)
+        );
+    }
+
+    #[test]
+    fn decodes_bincode_seeds() {
+        let raw = bincode::serialize(&sample_seeds()).unwrap();
+        assert_eq!(decode_seeds(&raw), (sample_seeds(), SeedEncoding::Bincode));
+    }
+
+    #[test]
+    fn decodes_json_hex_seeds() {
+        let hex_seeds: Vec<String> = sample_seeds().iter().map(hex::encode).collect();
+        let raw = serde_json::to_vec(&hex_seeds).unwrap();
+        assert_eq!(decode_seeds(&raw), (sample_seeds(), SeedEncoding::JsonHex));
+    }
+
+    #[test]
+    fn truncated_seeds_fall_back_to_lenient_decoding() {
+        let mut raw = length_prefixed(&sample_seeds());
+        raw.truncate(raw.len() - 1);
+        assert_eq!(
+            decode_seeds(&raw),
+            (sample_seeds()[..2].to_vec(), SeedEncoding::Unknown)
+        );
+    }
+
+    #[test]
+    fn huge_seed_counts_and_lengths_are_rejected_without_allocating() {
+        let mut raw = u32::MAX.to_le_bytes().to_vec();
+        raw.extend_from_slice(&u32::MAX.to_le_bytes());
+        raw.extend_from_slice(b"seed");
+        assert_eq!(
+            decode_length_prefixed_seeds(&raw),
+            (Vec::<Vec<u8>>::new(), false)
+        );
+
+        let mut raw = 1u32.to_le_bytes().to_vec();
+        raw.extend_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(decode_seeds(&raw).1, SeedEncoding::Unknown);
+    }
+
+    fn padded_and_unpadded() -> Vec<PdaSqlite> {
+        let mut padded = b"vault".to_vec();
+        padded.resize(32, 0);
+        [padded, b"vault".to_vec()]
+            .into_iter()
+            .map(|seed| PdaSqlite {
+                pda: Address::new_from_array([1; 32]),
+                seeds: vec![seed, vec![0]],
+                program_id: Address::new_from_array([2; 32]),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn padded_seeds_survive_the_merge_unless_trimmed() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut padded = padded_and_unpadded().swap_remove(0);
+        padded.pda = Address::new_from_array([3; 32]);
+        let path = dir.path().join("pda_collector_padded.blob");
+        write_blob(std::slice::from_ref(&padded), &path).unwrap();
+        backdate(&path);
+
+        let merged_seeds = |trim_seed_padding| {
+            let merged = merge(
+                Some(dir.path().to_owned()),
+                dir.path().join("dedup"),
+                &MergeOptions {
+                    trim_seed_padding,
+                    ..MergeOptions::default()
+                },
+                &mut PhaseTimings::default(),
+            )
+            .unwrap();
+            // The merged entries are written and parsed once more, as --merge-only output is.
+            let rewritten = dir.path().join("merged.blob");
+            write_blob(&merged.entries, &rewritten).unwrap();
+            from_blob(&rewritten).unwrap().swap_remove(0).seeds
+        };
+        assert_eq!(merged_seeds(None), padded.seeds);
+        assert_eq!(merged_seeds(Some(32)), vec![b"vault".to_vec(), vec![0]]);
+    }
+
+    #[test]
+    fn trimming_normalizes_padded_seeds() {
+        let mut entries = padded_and_unpadded();
+        assert_eq!(trim_seed_padding(&mut entries, 32), 1);
+        assert_eq!(entries[0].seeds, entries[1].seeds);
+        assert_eq!(entries[0].seeds, vec![b"vault".to_vec(), vec![0]]);
+    }
+
+    #[test]
+    fn trimming_only_touches_seeds_of_the_pad_length() {
+        let mut entries = padded_and_unpadded();
+        assert_eq!(trim_seed_padding(&mut entries, 16), 0);
+        assert_eq!(entries[0].seeds[0].len(), 32);
+    }
+
+    #[test]
+    fn derives_pda_accepts_only_entries_their_seeds_derive() {
+        let program_id = Address::from([7; 32]);
+        let (pda, bump) =
+            Address::try_find_program_address(&[b"vault", &[1, 2, 3]], &program_id).unwrap();
+        let derived = PdaSqlite {
+            pda,
+            seeds: vec![b"vault".to_vec(), vec![1, 2, 3]],
+            program_id,
+        };
+        assert!(derives_pda(&derived));
+
+        let mut with_bump = derived.clone();
+        with_bump.seeds.push(vec![bump]);
+        assert!(derives_pda(&with_bump));
+
+        let mut other_seeds = derived.clone();
+        other_seeds.seeds[1] = vec![1, 2, 4];
+        assert!(!derives_pda(&other_seeds));
+
+        let mut other_program = derived.clone();
+        other_program.program_id = Address::from([8; 32]);
+        assert!(!derives_pda(&other_program));
+    }
+
+    #[test]
+    fn decodes_full_length_address() {
+        let address = decode_address(&[3; ADDRESS_LEN], "pda", "a.sqlite").unwrap();
+        assert_eq!(address, Address::new_from_array([3; ADDRESS_LEN]));
+    }
+
+    #[test]
+    fn short_address_error_has_context() {
+        let err = decode_address(&[0xAB, 0xCD], "program_id", "short.sqlite")
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            err,
+            "invalid program_id in short.sqlite: expected 32 bytes, got 2 (starts with abcd)"
+        );
+    }
+
+    #[test]
+    fn long_address_error_truncates_dump() {
+        let err = decode_address(&[0x11; 40], "pda", "long.sqlite")
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            err,
+            "invalid pda in long.sqlite: expected 32 bytes, got 40 (starts with 1111111111111111...)"
+        );
+    }
+
+    #[test]
+    fn empty_address_is_rejected() {
+        let err = decode_address(&[], "pda", "empty.sqlite")
+            .unwrap_err()
+            .to_string();
+        assert_eq!(err, "invalid pda in empty.sqlite: expected 32 bytes, got 0");
+    }
+
+    #[test]
+    fn from_sqlite_normalizes_mixed_encodings() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mixed.sqlite");
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE pda_registry (pda BLOB, program_id BLOB, seed_count INTEGER, seed_bytes BLOB);",
+        )
+        .unwrap();
+
+        let hex_seeds: Vec<String> = sample_seeds().iter().map(hex::encode).collect();
+        let encodings = [
+            length_prefixed(&sample_seeds()),
+            bincode::serialize(&sample_seeds()).unwrap(),
+            serde_json::to_vec(&hex_seeds).unwrap(),
+        ];
+        for (index, seed_bytes) in encodings.iter().enumerate() {
+            conn.execute(
+                "INSERT INTO pda_registry VALUES (?1, ?2, 3, ?3)",
+                rusqlite::params![vec![index as u8; 32], vec![9u8; 32], seed_bytes],
+            )
+            .unwrap();
+        }
+        drop(conn);
+
+        let entries = from_sqlite(&path, 0).unwrap();
+        assert_eq!(entries.len(), 3);
+        for entry in entries {
+            assert_eq!(entry.seeds, sample_seeds());
+        }
+    }
+
+    #[test]
+    fn seed_checksums_round_trip_and_catch_corruption() {
+        use crate::cloudflare::{UploadOptions, build_insert_script};
+
+        let entries: Vec<PdaSqlite> = (1..=3u8)
+            .map(|index| PdaSqlite {
+                pda: Address::from([index; 32]),
+                seeds: vec![b"vault".to_vec(), vec![index; 32]],
+                program_id: Address::from([9; 32]),
+            })
+            .collect();
+        let options = UploadOptions {
+            with_checksums: true,
+            ..UploadOptions::default()
+        };
+        let script = build_insert_script(&entries, &options).unwrap().unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("checksums.sqlite");
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE pda_registry (pda BLOB, program_id BLOB, seed_count INTEGER, seed_bytes BLOB, seed_checksum INTEGER);",
+        )
+        .unwrap();
+        conn.execute_batch(&script).unwrap();
+
+        let (read, integrity) = read_sqlite(&path, 0).unwrap();
+        assert_eq!(integrity.checksum_mismatches, 0);
+        assert_eq!(read.len(), entries.len());
+        for (read, entry) in read.iter().zip(&entries) {
+            assert_eq!(read.pda, entry.pda);
+            assert_eq!(read.seeds, entry.seeds);
+        }
+
+        // Flip one bit of one row's stored seeds, as a disk error would.
+        conn.execute(
+            "UPDATE pda_registry SET seed_bytes = ?1 WHERE pda = ?2",
+            rusqlite::params![
+                {
+                    let mut corrupted = length_prefixed(&entries[1].seeds);
+                    corrupted[10] ^= 0x01;
+                    corrupted
+                },
+                entries[1].pda.as_ref(),
+            ],
+        )
+        .unwrap();
+        drop(conn);
+
+        let (_, integrity) = read_sqlite(&path, 0).unwrap();
+        assert_eq!(integrity.checksum_mismatches, 1);
+    }
+
+    #[test]
+    fn seed_count_disagreeing_with_seed_bytes_is_reported() {
+        use crate::cloudflare::{UploadOptions, build_insert_script};
+
+        let entries: Vec<PdaSqlite> = (1..=3u8)
+            .map(|index| PdaSqlite {
+                pda: Address::from([index; 32]),
+                seeds: vec![b"vault".to_vec(); usize::from(index)],
+                program_id: Address::from([9; 32]),
+            })
+            .collect();
+        let script = build_insert_script(&entries, &UploadOptions::default())
+            .unwrap()
+            .unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pda_collector.sqlite");
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE pda_registry (pda BLOB, program_id BLOB, seed_count INTEGER, seed_bytes BLOB);",
+        )
+        .unwrap();
+        conn.execute_batch(&script).unwrap();
+        let clean = verify_sqlite_sources(dir.path()).unwrap();
+        assert_eq!(clean.rows, 3);
+        assert!(clean.is_clean());
+
+        conn.execute(
+            "UPDATE pda_registry SET seed_count = 7 WHERE pda = ?1",
+            [entries[1].pda.as_ref()],
+        )
+        .unwrap();
+        drop(conn);
+        let integrity = verify_sqlite_sources(dir.path()).unwrap();
+        assert_eq!(integrity.seed_count_mismatches, 1);
+        assert_eq!(integrity.checksum_mismatches, 0);
+        assert!(!integrity.is_clean());
+    }
+
+    #[test]
+    fn processed_files_with_colliding_basenames_are_kept_apart() {
+        let inputs = [tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap()];
+        let processed_dir = tempfile::tempdir().unwrap();
+        let files: Vec<PathBuf> = inputs
+            .iter()
+            .enumerate()
+            .map(|(index, dir)| {
+                let path = dir.path().join("pda_collector_1.blob");
+                std::fs::write(&path, [index as u8]).unwrap();
+                path
+            })
+            .collect();
+
+        assert_eq!(move_to_processed(&files, processed_dir.path()).unwrap(), 2);
+
+        let mut moved: Vec<Vec<u8>> = std::fs::read_dir(processed_dir.path())
+            .unwrap()
+            .map(|entry| {
+                let path = entry.unwrap().path();
+                let name = path.file_name().unwrap().to_str().unwrap().to_owned();
+                assert!(name.ends_with("-pda_collector_1.blob"), "{name}");
+                std::fs::read(path).unwrap()
+            })
+            .collect();
+        moved.sort();
+        assert_eq!(moved, vec![vec![0], vec![1]]);
+        assert!(files.iter().all(|file| !file.exists()));
+
+        // The same source path processed again on a later run must not replace the first copy.
+        std::fs::write(&files[0], [2]).unwrap();
+        move_to_processed(&files[..1], processed_dir.path()).unwrap();
+        assert_eq!(std::fs::read_dir(processed_dir.path()).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn dedup_hashset_loads_pre_sized_from_its_length_prefix() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dedup");
+        let dedup_hashset: HashSet<Address> =
+            (0..50u8).map(|index| Address::from([index; 32])).collect();
+
+        save_dedup_hashset(&dedup_hashset, &path, DedupFormat::Bincode).unwrap();
+        let loaded = load_dedup_hashset(&path).unwrap();
+        assert_eq!(loaded, dedup_hashset);
+        assert!(loaded.capacity() >= dedup_hashset.len());
+
+        save_dedup_hashset(&dedup_hashset, &path, DedupFormat::Text).unwrap();
+        let loaded = load_dedup_hashset_with_capacity(&path, Some(1000)).unwrap();
+        assert_eq!(loaded, dedup_hashset);
+        assert!(loaded.capacity() >= 1000);
+
+        // A corrupt length prefix fails instead of reserving room for it.
+        let mut corrupt = (u64::MAX >> 8).to_le_bytes().to_vec();
+        corrupt.extend_from_slice(&[1; 32]);
+        std::fs::write(&path, corrupt).unwrap();
+        let err = load_dedup_hashset(&path).unwrap_err();
+        assert!(err.to_string().contains("claims"), "{err}");
+    }
+
+    #[test]
+    fn address_lists_accept_base58_and_hex() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("addresses");
+        let addresses = [[1; 32], [2; 32], [3; 32]].map(Address::from);
+        std::fs::write(
+            &path,
+            format!(
+                "# one per format\n{}\n0x{}\n{}\n",
+                addresses[0],
+                hex::encode(addresses[1]),
+                hex::encode_upper(addresses[2]),
+            ),
+        )
+        .unwrap();
+        assert_eq!(read_address_list(&path).unwrap(), HashSet::from(addresses));
+
+        std::fs::write(&path, format!("0x{}\n", &hex::encode(addresses[0])[2..])).unwrap();
+        let err = read_address_list(&path).unwrap_err();
+        assert!(format!("{err:#}").contains("line 1"), "{err:#}");
+    }
+
+    #[test]
+    fn text_dedup_hashset_round_trips_byte_for_byte() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dedup");
+        let dedup_hashset: HashSet<Address> =
+            (0..50u8).map(|index| Address::from([index; 32])).collect();
+
+        save_dedup_hashset(&dedup_hashset, &path, DedupFormat::Text).unwrap();
+        let saved = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = saved.lines().collect();
+        assert!(lines.is_sorted());
+        assert_eq!(lines.len(), dedup_hashset.len());
+
+        assert_eq!(detect_dedup_format(&path).unwrap(), DedupFormat::Text);
+        let loaded = load_dedup_hashset(&path).unwrap();
+        assert_eq!(loaded, dedup_hashset);
+        save_dedup_hashset(&loaded, &path, DedupFormat::Text).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), saved);
+
+        save_dedup_hashset(&loaded, &path, DedupFormat::Bincode).unwrap();
+        assert_eq!(detect_dedup_format(&path).unwrap(), DedupFormat::Bincode);
+        assert_eq!(load_dedup_hashset(&path).unwrap(), dedup_hashset);
+    }
+
+    #[test]
+    fn dedup_hashset_saves_under_every_fsync_mode() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dedup");
+        let dedup_hashset: HashSet<Address> =
+            (0..50u8).map(|index| Address::from([index; 32])).collect();
+
+        for fsync in [FsyncMode::Full, FsyncMode::None, FsyncMode::RenameOnly] {
+            save_dedup_hashset_with_fsync(&dedup_hashset, &path, DedupFormat::Bincode, fsync)
+                .unwrap();
+            assert_eq!(load_dedup_hashset(&path).unwrap(), dedup_hashset);
+
+            let additions = HashSet::from([Address::from([99; 32])]);
+            save_dedup_hashset_streaming(&additions, &path, fsync).unwrap();
+            assert_eq!(load_dedup_hashset(&path).unwrap().len(), 51);
+            assert!(!temp_path(&path).exists());
+        }
+    }
+
+    #[test]
+    fn suffixed_dedup_hashsets_save_through_their_own_temp_files() {
+        let dir = tempfile::tempdir().unwrap();
+        // Another instance's save in flight, at the temp path its unsuffixed file uses.
+        let other_temp = temp_path(&dir.path().join("dedup"));
+        std::fs::write(&other_temp, b"in flight").unwrap();
+
+        let dedup_hashset = HashSet::from([Address::from([1; 32])]);
+        for name in ["dedup.shard-0-of-2", "dedup.shard-1-of-2", "dedup.db-abc"] {
+            let path = dir.path().join(name);
+            save_dedup_hashset(&dedup_hashset, &path, DedupFormat::Bincode).unwrap();
+            save_dedup_hashset_streaming(
+                &HashSet::from([Address::from([2; 32])]),
+                &path,
+                FsyncMode::default(),
+            )
+            .unwrap();
+            assert_eq!(load_dedup_hashset(&path).unwrap().len(), 2);
+        }
+        assert_eq!(std::fs::read(&other_temp).unwrap(), b"in flight");
+    }
+
+    #[test]
+    fn merge_stats_break_down_the_dedup() {
+        let dir = tempfile::tempdir().unwrap();
+        // Pdas 1..=4 in one file and 3..=6 in the other, with 1 and 2 already uploaded.
+        for (name, range) in [("a", 1..=4u8), ("b", 3..=6u8)] {
+            let entries: Vec<PdaSqlite> = range.map(entry).collect();
+            let path = dir.path().join(format!("pda_collector_{name}.blob"));
+            std::fs::write(&path, bincode::serialize(&entries).unwrap()).unwrap();
+            backdate(&path);
+        }
+        let dedup_path = dir.path().join("dedup");
+        let known: HashSet<Address> = [entry(1).pda, entry(2).pda].into_iter().collect();
+        save_dedup_hashset(&known, &dedup_path, DedupFormat::Bincode).unwrap();
+
+        // Loading the dedup hashset during the scan must not change what it filters.
+        for dedup_readahead in [false, true] {
+            let merged = merge(
+                Some(dir.path().to_owned()),
+                dedup_path.clone(),
+                &MergeOptions {
+                    dedup_readahead,
+                    ..MergeOptions::default()
+                },
+                &mut PhaseTimings::default(),
+            )
+            .unwrap();
+
+            assert_eq!(merged.entries.len(), 4);
+            assert_eq!(merged.files.len(), 2);
+            assert_eq!(merged.dedup_hashset, known);
+            assert_eq!(
+                merged.stats,
+                MergeStats {
+                    blob_files: 2,
+                    sqlite_files: 0,
+                    csv_files: 0,
+                    remote_blobs: 0,
+                    initial_entries: 8,
+                    vec_deduped: 2,
+                    hashset_deduped: 2,
+                    new_entries: 4,
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn bloom_dedup_drops_exactly_what_the_dedup_hashset_drops() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pda_collector_a.blob");
+        std::fs::write(
+            &path,
+            bincode::serialize(&(1..=60).map(entry).collect::<Vec<_>>()).unwrap(),
+        )
+        .unwrap();
+        backdate(&path);
+        let dedup_path = dir.path().join("dedup");
+        let known: HashSet<Address> = (0..=120).step_by(3).map(|index| entry(index).pda).collect();
+        save_dedup_hashset(&known, &dedup_path, DedupFormat::Bincode).unwrap();
+
+        let merge_pdas = |dedup_bloom_bits| {
+            let merged = merge(
+                Some(dir.path().to_owned()),
+                dedup_path.clone(),
+                &MergeOptions {
+                    dedup_bloom_bits,
+                    ..MergeOptions::default()
+                },
+                &mut PhaseTimings::default(),
+            )
+            .unwrap();
+            merged
+                .entries
+                .iter()
+                .map(|entry| entry.pda)
+                .collect::<Vec<_>>()
+        };
+        let exact = merge_pdas(None);
+        assert_eq!(exact.len(), 40);
+        // A 64-bit filter over 41 addresses is mostly ones, so most candidates are false
+        // positives the streamed pass has to clear; the persisted filter is reused the second
+        // time.
+        assert_eq!(merge_pdas(Some(64)), exact);
+        assert!(bloom_path(&dedup_path).exists());
+        assert_eq!(merge_pdas(Some(64)), exact);
+        assert_eq!(merge_pdas(Some(1 << 20)), exact);
+    }
+
+    #[test]
+    fn remote_blobs_merge_alongside_the_scanned_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let blob_path = dir.path().join("remote.blob");
+        write_blob(&(1..=3).map(entry).collect::<Vec<_>>(), &blob_path).unwrap();
+        let bytes = std::fs::read(&blob_path).unwrap();
+        std::fs::remove_file(&blob_path).unwrap();
+        let remote_blobs = vec![
+            RemoteBlob {
+                url: "https://collector.example/pda_collector_1.blob".to_owned(),
+                bytes: bytes.clone(),
+            },
+            RemoteBlob {
+                url: "https://collector.example/pda_collector_2.blob.zst?sig=1".to_owned(),
+                bytes: zstd::encode_all(
+                    bincode::serialize(&(3..=5).map(entry).collect::<Vec<_>>())
+                        .unwrap()
+                        .as_slice(),
+                    0,
+                )
+                .unwrap(),
+            },
+        ];
+
+        let merged = merge(
+            Some(dir.path().to_owned()),
+            dir.path().join("dedup"),
+            &MergeOptions {
+                remote_blobs,
+                ..MergeOptions::default()
+            },
+            &mut PhaseTimings::default(),
+        )
+        .unwrap();
+        let pdas: Vec<Address> = merged.entries.iter().map(|entry| entry.pda).collect();
+        assert_eq!(
+            pdas,
+            (1..=5).map(|index| entry(index).pda).collect::<Vec<_>>()
+        );
+        assert_eq!(merged.stats.remote_blobs, 2);
+        assert!(merged.files.is_empty());
+
+        // The header check applies to downloads too.
+        let mut unsupported = bytes;
+        unsupported[BLOB_MAGIC.len()] = 0xFF;
+        let err = merge(
+            None,
+            dir.path().join("dedup"),
+            &MergeOptions {
+                remote_blobs: vec![RemoteBlob {
+                    url: "https://collector.example/future.blob".to_owned(),
+                    bytes: unsupported,
+                }],
+                ..MergeOptions::default()
+            },
+            &mut PhaseTimings::default(),
+        )
+        .unwrap_err();
+        assert!(
+            format!("{err:#}").contains("unsupported version"),
+            "{err:#}"
+        );
+    }
+
+    #[test]
+    fn forced_input_format_applies_the_readiness_checks() {
+        let dir = tempfile::tempdir().unwrap();
+        let settled_export = |name: &str| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, b"export").unwrap();
+            backdate(&path);
+            path
+        };
+        let settled = settled_export("settled.dat");
+        settled_export("locked.dat");
+        settled_export("locked.dat.lock");
+        settled_export("partial.dat.partial");
+        std::fs::write(dir.path().join("fresh.dat"), b"export").unwrap();
+        std::fs::create_dir(dir.path().join("nested.dat")).unwrap();
+
+        let files = collect_regular_files(dir.path(), u64::MAX, false).unwrap();
+        assert_eq!(files, vec![settled]);
+        assert!(
+            collect_regular_files(dir.path(), 1, false)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn future_mtimes_beyond_the_tolerance_count_as_settled() {
+        let now = SystemTime::now();
+        let secs = Duration::from_secs;
+        assert!(is_settled("old", now - secs(60), now, false));
+        assert!(!is_settled("fresh", now - secs(1), now, false));
+        assert!(!is_settled("slight skew", now + secs(2), now, false));
+        assert!(is_settled("skewed", now + secs(3600), now, false));
+        assert!(!is_settled("skewed", now + secs(3600), now, true));
+    }
+
+    #[test]
+    fn timestamped_dedup_hashset_migrates_and_looks_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dedup");
+        let address = |index: u8| Address::from([index; 32]);
+        let old: HashSet<Address> = [address(1), address(2)].into_iter().collect();
+        save_dedup_hashset(&old, &path, DedupFormat::Bincode).unwrap();
+
+        // Migrating stamps the addresses already saved with 0, and only new ones with now.
+        let mut grown = old.clone();
+        grown.insert(address(3));
+        save_dedup_hashset(&grown, &path, DedupFormat::Timestamped).unwrap();
+        assert_eq!(
+            detect_dedup_format(&path).unwrap(),
+            DedupFormat::Timestamped
+        );
+        let timestamps = load_dedup_timestamps(&path).unwrap();
+        assert_eq!(timestamps[&address(1)], 0);
+        assert_eq!(timestamps[&address(2)], 0);
+        assert!(timestamps[&address(3)] > 0);
+        assert_eq!(load_dedup_hashset(&path).unwrap(), grown);
+
+        // Saving again keeps the stamps.
+        save_dedup_hashset(&grown, &path, DedupFormat::Timestamped).unwrap();
+        assert_eq!(load_dedup_timestamps(&path).unwrap(), timestamps);
+
+        let source = dir.path().join("pda_collector_1.blob");
+        let entries: Vec<PdaSqlite> = (1..=4u8).map(entry).collect();
+        std::fs::write(&source, bincode::serialize(&entries).unwrap()).unwrap();
+        backdate(&source);
+
+        let merged = merge(
+            Some(dir.path().to_owned()),
+            path,
+            &MergeOptions {
+                dedup_lookback_secs: Some(3600),
+                ..MergeOptions::default()
+            },
+            &mut PhaseTimings::default(),
+        )
+        .unwrap();
+        let pdas: Vec<Address> = merged.entries.iter().map(|entry| entry.pda).collect();
+        assert_eq!(pdas, vec![address(3), address(4)]);
+        assert_eq!(merged.dedup_hashset, grown);
+    }
+
+    #[test]
+    fn dead_letter_records_read_back_as_a_pda_list() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dead-letter.jsonl");
+        let entries: Vec<PdaSqlite> = (1..=3u8)
+            .map(|index| PdaSqlite {
+                pda: Address::from([index; 32]),
+                seeds: vec![b"vault".to_vec(), vec![index]],
+                program_id: Address::from([9; 32]),
+            })
+            .collect();
+
+        DeadLetter::open(&path)
+            .unwrap()
+            .record(&entries[..2], "first")
+            .unwrap();
+        DeadLetter::open(&path)
+            .unwrap()
+            .record(&entries[2..], "second")
+            .unwrap();
+
+        let read = read_pda_list_json(&path).unwrap();
+        assert_eq!(read.len(), entries.len());
+        for (read, entry) in read.iter().zip(&entries) {
+            assert_eq!(read.pda, entry.pda);
+            assert_eq!(read.program_id, entry.program_id);
+            assert_eq!(read.seeds, entry.seeds);
+        }
+    }
+
+    #[test]
+    fn dead_lettered_pdas_are_uploaded_again_despite_the_dedup_hashset() {
+        let dir = tempfile::tempdir().unwrap();
+        // A run uploaded 1 to 3 and recorded them in its dedup hashset, but the import of
+        // the chunk holding 1 and 2 reported failed rows.
+        let dedup_path = dir.path().join("dedup");
+        let uploaded: HashSet<Address> = (1..=3).map(|index| entry(index).pda).collect();
+        save_dedup_hashset(&uploaded, &dedup_path, DedupFormat::Bincode).unwrap();
+        let dead_letter_path = dir.path().join("dead-letter.jsonl");
+        DeadLetter::open(&dead_letter_path)
+            .unwrap()
+            .record(
+                &[entry(1), entry(2)],
+                "1 row(s) of chunk 1 failed to import",
+            )
+            .unwrap();
+
+        for dedup_bloom_bits in [None, Some(1 << 12)] {
+            let merged = merge(
+                None,
+                dedup_path.clone(),
+                &MergeOptions {
+                    pda_list_in: Some(dead_letter_path.clone()),
+                    dedup_bloom_bits,
+                    ..MergeOptions::default()
+                },
+                &mut PhaseTimings::default(),
+            )
+            .unwrap();
+            let pdas: Vec<Address> = merged.entries.iter().map(|entry| entry.pda).collect();
+            assert_eq!(pdas, [entry(1).pda, entry(2).pda]);
+            assert_eq!(merged.entries[1].seeds, entry(2).seeds);
+        }
+    }
+
+    #[test]
+    fn gzipped_csv_reads_like_plain_csv() {
+        use flate2::{Compression, write::GzEncoder};
+
+        let dir = tempfile::tempdir().unwrap();
+        let csv = format!(
+            "pda,program_id,seeds\n{},{},{};{}\n",
+            Address::from([1; 32]),
+            Address::from([9; 32]),
+            hex::encode(b"vault"),
+            hex::encode([7]),
+        );
+        let plain = dir.path().join("export.csv");
+        std::fs::write(&plain, &csv).unwrap();
+        let gzipped = dir.path().join("export.csv.gz");
+        let mut encoder = GzEncoder::new(File::create(&gzipped).unwrap(), Compression::default());
+        encoder.write_all(csv.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+        let marked = dir.path().join("marked.csv");
+        std::fs::copy(&plain, &marked).unwrap();
+        for path in [&plain, &gzipped, &marked] {
+            backdate(path);
+        }
+        // Neither a directory, a file its exporter is still writing, nor a fresh file counts.
+        std::fs::create_dir(dir.path().join("nested.csv")).unwrap();
+        std::fs::write(dir.path().join("marked.csv.lock"), b"").unwrap();
+        std::fs::write(dir.path().join("fresh.csv.gz"), b"").unwrap();
+
+        let mut found = collect_csv_files(dir.path(), u64::MAX, false).unwrap();
+        found.sort();
+        assert_eq!(found, vec![plain.clone(), gzipped.clone()]);
+        // Merged csv files are returned to be moved with the blobs.
+        let mut merged = merge(
+            Some(dir.path().to_owned()),
+            dir.path().join("dedup"),
+            &MergeOptions::default(),
+            &mut PhaseTimings::default(),
+        )
+        .unwrap()
+        .files;
+        merged.sort();
+        assert_eq!(merged, found);
+
+        let entries = from_csv(&gzipped).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].seeds, vec![b"vault".to_vec(), vec![7]]);
+        assert_eq!(entries[0].pda, from_csv(&plain).unwrap()[0].pda);
+    }
+
+    #[test]
+    fn written_blob_reads_back_with_its_header() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pda_collector_merged.blob");
+        let entries: Vec<PdaSqlite> = (1..=3u8).map(entry).collect();
+
+        write_blob(&entries, &path).unwrap();
+        assert!(std::fs::read(&path).unwrap().starts_with(&BLOB_MAGIC));
+        let read = from_blob(&path).unwrap();
+        assert_eq!(read.len(), entries.len());
+        for (read, entry) in read.iter().zip(&entries) {
+            assert_eq!(read.pda, entry.pda);
+            assert_eq!(read.seeds, entry.seeds);
+        }
+    }
+
+    #[test]
+    fn zstd_blob_is_written_compressed_and_reads_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pda_collector_merged.blob.zst");
+        let entries: Vec<PdaSqlite> = (1..=3u8)
+            .map(|index| PdaSqlite {
+                pda: Address::from([index; 32]),
+                seeds: vec![vec![index; 64]],
+                program_id: Address::from([9; 32]),
+            })
+            .collect();
+
+        write_blob(&entries, &path).unwrap();
+        let compressed = std::fs::read(&path).unwrap();
+        assert!(compressed.starts_with(&[0x28, 0xB5, 0x2F, 0xFD]));
+        assert!(
+            zstd::decode_all(compressed.as_slice())
+                .unwrap()
+                .starts_with(&BLOB_MAGIC)
+        );
+        let read = from_blob(&path).unwrap();
+        assert_eq!(read.len(), entries.len());
+        for (read, entry) in read.iter().zip(&entries) {
+            assert_eq!((read.pda, &read.seeds), (entry.pda, &entry.seeds));
+        }
+    }
+
+    #[test]
+    fn program_index_points_into_the_written_blob() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pda_collector_merged.blob");
+        let entries: Vec<PdaSqlite> = [(1u8, 7u8, 1usize), (2, 7, 3), (3, 8, 2)]
+            .into_iter()
+            .map(|(pda, program_id, seed_len)| PdaSqlite {
+                pda: Address::from([pda; 32]),
+                seeds: vec![vec![pda; seed_len]],
+                program_id: Address::from([program_id; 32]),
+            })
+            .collect();
+
+        let mut index = ProgramIndex::default();
+        write_indexed_blob(&entries, &path, &mut index).unwrap();
+        let blob = std::fs::read(&path).unwrap();
+        let rows = crate::export::program_index(&entries).unwrap();
+        let index = index.programs();
+        assert_eq!(index.len(), 2);
+        assert_eq!((index[0].entries, index[0].first_row), (2, 0));
+        assert_eq!((index[1].entries, index[1].first_row), (1, 2));
+        assert_eq!(index[1].program_id, Address::from([8; 32]).to_string());
+        for (program, rows) in index.iter().zip(rows.programs()) {
+            assert_eq!((rows.blob_offset, rows.blob_len), (None, None));
+            assert_eq!(
+                (rows.entries, rows.first_row),
+                (program.entries, program.first_row)
+            );
+            let start = program.blob_offset.unwrap() as usize;
+            let mut bytes = &blob[start..start + program.blob_len.unwrap() as usize];
+            for entry in &entries[program.first_row..program.first_row + program.entries] {
+                let read: PdaSqlite = bincode::deserialize_from(&mut bytes).unwrap();
+                assert_eq!((read.pda, &read.seeds), (entry.pda, &entry.seeds));
+            }
+            assert!(bytes.is_empty());
+        }
+        assert_eq!(
+            index[1].blob_offset.unwrap() + index[1].blob_len.unwrap(),
+            blob.len() as u64
+        );
+        assert_eq!(from_blob(&path).unwrap().len(), entries.len());
+
+        let mut unsorted = entries.clone();
+        unsorted.swap(0, 2);
+        assert!(crate::export::program_index(&unsorted).is_err());
+    }
+
+    #[test]
+    fn resumed_merge_returns_the_files_it_does_not_parse_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input");
+        std::fs::create_dir(&input).unwrap();
+        for index in 1..=2u8 {
+            let path = input.join(format!("pda_collector_{index}.blob"));
+            write_blob(&[entry(index)], &path).unwrap();
+            backdate(&path);
+        }
+        let options = MergeOptions {
+            merge_state: Some(dir.path().join("merge-state")),
+            ..MergeOptions::default()
+        };
+        let merge_input = || {
+            merge(
+                Some(input.clone()),
+                dir.path().join("dedup"),
+                &options,
+                &mut PhaseTimings::default(),
+            )
+            .unwrap()
+        };
+
+        // The first run is interrupted after parsing, leaving its merge state behind.
+        let first = merge_input();
+        assert_eq!(first.stats.blob_files, 2);
+        let resumed = merge_input();
+        assert_eq!(resumed.stats.blob_files, 0);
+        assert_eq!(resumed.entries.len(), 2);
+        let mut files = resumed.files.clone();
+        files.sort();
+        let mut expected = first.files.clone();
+        expected.sort();
+        assert_eq!(files, expected);
+
+        let processed = dir.path().join("processed");
+        assert_eq!(move_to_processed(&resumed.files, &processed).unwrap(), 2);
+        assert_eq!(std::fs::read_dir(&input).unwrap().count(), 0);
+    }
+}
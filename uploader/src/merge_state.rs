@@ -0,0 +1,250 @@
+use std::{
+    collections::HashSet,
+    ffi::OsString,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use eyre::{Result, WrapErr, eyre};
+use log::info;
+
+use crate::types::PdaSqlite;
+
+/// Progress of an interrupted-and-resumable parse phase, kept as two append-only files:
+///
+/// - the manifest at `--merge-state`, one parsed source path per line;
+/// - the spill at `<manifest>.spill`, one record per manifest line in the same order, each a
+///   little-endian `u64` length followed by the bincode-encoded entries of that file.
+///
+/// A record is synced before its manifest line is written, so every listed file has its
+/// entries on disk. Anything past the last listed file is a torn write and is truncated on
+/// resume; that file is simply parsed again. Reparsing a file twice is harmless, since the
+/// merge dedups the combined entries exactly.
+pub struct MergeState {
+    done: HashSet<PathBuf>,
+    writer: Mutex<StateWriter>,
+}
+
+struct StateWriter {
+    manifest: File,
+    spill: File,
+}
+
+impl MergeState {
+    /// Opens the state at `manifest_path`, creating it if missing, and returns it together
+    /// with the entries of every file a previous run finished parsing.
+    pub fn open(manifest_path: &Path) -> Result<(Self, Vec<PdaSqlite>)> {
+        let spill_path = spill_path(manifest_path);
+        let mut manifest = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(manifest_path)
+            .wrap_err_with(|| format!("failed to open merge state {}", manifest_path.display()))?;
+        let mut spill = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&spill_path)
+            .wrap_err_with(|| format!("failed to open merge spill {}", spill_path.display()))?;
+
+        let (done_paths, manifest_len) = read_manifest(&mut manifest)
+            .wrap_err_with(|| format!("failed to read merge state {}", manifest_path.display()))?;
+        let (entries, spill_len) = read_spill(&mut spill, done_paths.len())
+            .wrap_err_with(|| format!("failed to read merge spill {}", spill_path.display()))?;
+
+        // Drop any torn trailing line or record so new writes append to a consistent state.
+        manifest.set_len(manifest_len)?;
+        manifest.seek(SeekFrom::End(0))?;
+        spill.set_len(spill_len)?;
+        spill.seek(SeekFrom::End(0))?;
+
+        if !done_paths.is_empty() {
+            info!(
+                "Resuming merge state {}: {} file(s) already parsed, {} entries reloaded",
+                manifest_path.display(),
+                done_paths.len(),
+                entries.len()
+            );
+        }
+        let state = Self {
+            done: done_paths.into_iter().collect(),
+            writer: Mutex::new(StateWriter { manifest, spill }),
+        };
+        Ok((state, entries))
+    }
+
+    /// Whether a previous run already parsed `path`.
+    pub fn is_done(&self, path: &Path) -> bool {
+        self.done.contains(path)
+    }
+
+    /// Records `path` as fully parsed into `entries`.
+    pub fn record(&self, path: &Path, entries: &[PdaSqlite]) -> Result<()> {
+        let record = bincode::serialize(entries)
+            .map_err(|err| eyre!("failed to serialize entries of {}: {err}", path.display()))?;
+        let mut writer = self
+            .writer
+            .lock()
+            .map_err(|err| eyre!("merge state lock poisoned: {err}"))?;
+
+        writer
+            .spill
+            .write_all(&(record.len() as u64).to_le_bytes())?;
+        writer.spill.write_all(&record)?;
+        writer.spill.sync_data()?;
+
+        let mut line = path.as_os_str().to_string_lossy().into_owned();
+        line.push('\n');
+        writer.manifest.write_all(line.as_bytes())?;
+        writer.manifest.sync_data()?;
+        Ok(())
+    }
+}
+
+/// Removes the merge state at `manifest_path` and its spill, once their entries are no longer
+/// needed.
+pub fn clear_merge_state(manifest_path: &Path) -> Result<()> {
+    for path in [manifest_path.to_owned(), spill_path(manifest_path)] {
+        match std::fs::remove_file(&path) {
+            Ok(()) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => {
+                return Err(err).wrap_err_with(|| format!("failed to remove {}", path.display()));
+            }
+        }
+    }
+    info!("Cleared merge state {}", manifest_path.display());
+    Ok(())
+}
+
+fn spill_path(manifest_path: &Path) -> PathBuf {
+    let mut path = OsString::from(manifest_path.as_os_str());
+    path.push(".spill");
+    PathBuf::from(path)
+}
+
+/// Returns the complete lines of the manifest and the byte length they span.
+fn read_manifest(manifest: &mut File) -> Result<(Vec<PathBuf>, u64)> {
+    let mut reader = BufReader::new(manifest);
+    let mut paths = Vec::new();
+    let mut len = 0;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let read = reader.read_line(&mut line)?;
+        if read == 0 || !line.ends_with('\n') {
+            break;
+        }
+        len += read as u64;
+        paths.push(PathBuf::from(line.trim_end_matches('\n')));
+    }
+    Ok((paths, len))
+}
+
+/// Reads the first `records` records of the spill, returning their entries and the byte
+/// length they span.
+fn read_spill(spill: &mut File, records: usize) -> Result<(Vec<PdaSqlite>, u64)> {
+    let mut reader = BufReader::new(spill);
+    let mut entries = Vec::new();
+    let mut len = 0;
+    for index in 0..records {
+        let mut len_bytes = [0; 8];
+        reader.read_exact(&mut len_bytes).wrap_err_with(|| {
+            format!("spill ends at record {index} but the manifest lists {records} files")
+        })?;
+        let mut record = vec![0; u64::from_le_bytes(len_bytes) as usize];
+        reader
+            .read_exact(&mut record)
+            .wrap_err_with(|| format!("spill record {index} is truncated"))?;
+        let parsed: Vec<PdaSqlite> = bincode::deserialize(&record)
+            .map_err(|err| eyre!("failed to deserialize spill record {index}: {err}"))?;
+        entries.extend(parsed);
+        len += 8 + record.len() as u64;
+    }
+    Ok((entries, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_address::Address;
+
+    use super::*;
+
+    fn entry(index: u8) -> PdaSqlite {
+        PdaSqlite {
+            pda: Address::from([index; 32]),
+            seeds: vec![vec![index]],
+            program_id: Address::from([0xAA; 32]),
+        }
+    }
+
+    fn pdas(entries: &[PdaSqlite]) -> Vec<Address> {
+        entries.iter().map(|entry| entry.pda).collect()
+    }
+
+    #[test]
+    fn reopening_resumes_the_recorded_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = dir.path().join("state");
+        let (state, resumed) = MergeState::open(&manifest).unwrap();
+        assert!(resumed.is_empty());
+        state.record(Path::new("/in/a.blob"), &[entry(1)]).unwrap();
+        state
+            .record(Path::new("/in/b.blob"), &[entry(2), entry(3)])
+            .unwrap();
+        drop(state);
+
+        let (state, resumed) = MergeState::open(&manifest).unwrap();
+        assert!(state.is_done(Path::new("/in/a.blob")));
+        assert!(state.is_done(Path::new("/in/b.blob")));
+        assert!(!state.is_done(Path::new("/in/c.blob")));
+        assert_eq!(pdas(&resumed), pdas(&[entry(1), entry(2), entry(3)]));
+    }
+
+    #[test]
+    fn torn_writes_are_truncated_and_appended_after() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = dir.path().join("state");
+        let (state, _) = MergeState::open(&manifest).unwrap();
+        state.record(Path::new("/in/a.blob"), &[entry(1)]).unwrap();
+        drop(state);
+        // A record and a manifest line cut short by a crash.
+        let mut spill = OpenOptions::new()
+            .append(true)
+            .open(spill_path(&manifest))
+            .unwrap();
+        spill.write_all(&64u64.to_le_bytes()).unwrap();
+        let mut lines = OpenOptions::new().append(true).open(&manifest).unwrap();
+        lines.write_all(b"/in/b.bl").unwrap();
+
+        let (state, resumed) = MergeState::open(&manifest).unwrap();
+        assert!(!state.is_done(Path::new("/in/b.blob")));
+        assert_eq!(pdas(&resumed), pdas(&[entry(1)]));
+        state.record(Path::new("/in/b.blob"), &[entry(2)]).unwrap();
+        drop(state);
+
+        let (state, resumed) = MergeState::open(&manifest).unwrap();
+        assert!(state.is_done(Path::new("/in/b.blob")));
+        assert_eq!(pdas(&resumed), pdas(&[entry(1), entry(2)]));
+    }
+
+    #[test]
+    fn clearing_removes_the_manifest_and_spill() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = dir.path().join("state");
+        let (state, _) = MergeState::open(&manifest).unwrap();
+        state.record(Path::new("/in/a.blob"), &[entry(1)]).unwrap();
+        drop(state);
+
+        clear_merge_state(&manifest).unwrap();
+        assert!(!manifest.exists());
+        assert!(!spill_path(&manifest).exists());
+        // Clearing an already cleared state is not an error.
+        clear_merge_state(&manifest).unwrap();
+    }
+}
@@ -0,0 +1,69 @@
+use std::path::{Path, PathBuf};
+
+use eyre::{Result, WrapErr};
+use log::info;
+use rusqlite::Connection;
+
+use crate::{
+    cloudflare::{UploadOptions, build_insert_script},
+    schema,
+    types::PdaSqlite,
+};
+
+/// A local sqlite copy of every chunk uploaded to the inactive database, for offline queries
+/// and backups. Chunks are inserted only after their upload succeeds, each in its own
+/// transaction, so after a crash the mirror holds exactly the chunks that reached D1.
+pub struct LocalMirror {
+    conn: Connection,
+    path: PathBuf,
+    options: UploadOptions,
+}
+
+impl LocalMirror {
+    /// Opens the mirror at `path`, creating `options.table_name` with the D1 schema if it does
+    /// not exist yet and failing if an existing table lacks a column the upload writes.
+    pub fn open(path: &Path, options: &UploadOptions) -> Result<Self> {
+        let conn = Connection::open(path)
+            .wrap_err_with(|| format!("failed to open local mirror {}", path.display()))?;
+        let expected = schema::expected_d1_columns(options.with_seeds_json, options.with_checksums);
+        conn.execute_batch(&schema::create_table_statement(
+            &options.table_name,
+            &expected,
+        ))
+        .wrap_err_with(|| format!("failed to create table in {}", path.display()))?;
+        let columns = schema::sqlite_columns(path, &options.table_name)?;
+        schema::ensure_no_drift(
+            &format!("local mirror {}", path.display()),
+            &expected,
+            &columns,
+        )?;
+
+        info!(
+            "Mirroring uploaded entries to {} table {}",
+            path.display(),
+            options.table_name
+        );
+        Ok(Self {
+            conn,
+            path: path.to_owned(),
+            // The mirror runs its own transaction around each chunk.
+            options: UploadOptions {
+                transactional: false,
+                ..options.clone()
+            },
+        })
+    }
+
+    /// Inserts one uploaded chunk with the same rows [`build_insert_script`] sends to D1.
+    pub fn insert(&mut self, entries: &[PdaSqlite]) -> Result<()> {
+        let Some(script) = build_insert_script(entries, &self.options)? else {
+            return Ok(());
+        };
+        let tx = self.conn.transaction()?;
+        tx.execute_batch(&script)
+            .wrap_err_with(|| format!("failed to insert into {}", self.path.display()))?;
+        tx.commit()
+            .wrap_err_with(|| format!("failed to commit to {}", self.path.display()))?;
+        Ok(())
+    }
+}
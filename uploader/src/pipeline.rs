@@ -0,0 +1,2637 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::{IsTerminal, Write},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use cloudflare::framework::auth::Credentials;
+use eyre::{WrapErr, eyre};
+use log::{debug, error, info, warn};
+use rand::{SeedableRng, rngs::StdRng};
+use rayon::prelude::*;
+use solana_address::Address;
+use tokio::{sync::mpsc, time::sleep};
+
+use crate::{
+    analyze::{self, ProgramProfile},
+    backend::{Backend, CloudflareD1Backend, S3Backend, UploadBackend},
+    cloudflare::{
+        ImportReport, SendWithRetry, UploadOptions, build_insert_script, check_renderable,
+        continue_d1_import, d1_pdas, d1_table_columns, http_client, is_fatal_api_error,
+        lookup_d1_pda, new_client, query_d1, split_unrenderable,
+    },
+    dead_letter::DeadLetter,
+    dedup_store::{AnyDedupStore, DedupStore},
+    exit::{Failure, ResultExt, RunError, TimedOut},
+    export,
+    kv::{CloudflareKv, KvStore},
+    merge, merge_state,
+    mirror::LocalMirror,
+    s3, schema,
+    summary::{BookmarkRecord, PhaseTimings, RentEstimate, RunSummary, append_bookmark},
+    types::{Args, BackendKind, Command, DedupFormat, PdaSqlite, S3PayloadFormat, SortOrder},
+};
+
+/// Workers KV namespace holding the `ACTIVE_DB` pointer.
+pub const NAMESPACE_ID: &str = "05dc24c1e32e433ba403340ffcb21fb2";
+/// Key whose value (`blue` or `green`) names the database serving production reads.
+pub const ACTIVE_DB_KEY: &str = "ACTIVE_DB";
+/// Entries uploaded per chunk; each chunk is retried on its own.
+pub const CHUNK_SIZE: usize = 100_000;
+
+/// Runs one uploader invocation: merges collector output, uploads it blue/green, toggles
+/// `ACTIVE_DB`, and persists the dedup hashset. Fills `summary` and `timings` as it goes, so
+/// they are meaningful even when an error is returned.
+pub async fn run(
+    mut args: Args,
+    summary: &mut RunSummary,
+    timings: &mut PhaseTimings,
+) -> Result<(), RunError> {
+    match args.command.clone() {
+        Some(Command::ContinueImport {
+            database_id,
+            bookmark,
+        }) => return continue_import(&args, &database_id, bookmark, summary).await,
+        Some(Command::Lookup { database_id, pdas }) => {
+            return lookup(&args, &database_id, &pdas).await;
+        }
+        Some(Command::CompactDedup {
+            dedup_hashset_file,
+            database_id,
+            page_size,
+        }) => {
+            return compact_dedup(&args, &dedup_hashset_file, &database_id, page_size).await;
+        }
+        Some(command) => return run_command(command),
+        None => {}
+    }
+
+    if args.dedup_per_database
+        && let Some(target_db_id) = args.target_db_id.as_deref()
+    {
+        let suffix = format!(".db-{target_db_id}");
+        args.dedup_hashset_file = with_suffix(&args.dedup_hashset_file, &suffix);
+        args.merge_state = args
+            .merge_state
+            .as_deref()
+            .map(|path| with_suffix(path, &suffix));
+        info!(
+            "Uploading to {target_db_id} with its own dedup hashset {}",
+            args.dedup_hashset_file.display()
+        );
+    }
+
+    if let Some(shard) = args.pda_shard {
+        args.dedup_hashset_file = shard.suffixed(&args.dedup_hashset_file);
+        args.merge_state = args.merge_state.as_deref().map(|path| shard.suffixed(path));
+        info!(
+            "Uploading pda shard {shard} with dedup hashset {}",
+            args.dedup_hashset_file.display()
+        );
+    }
+
+    if let Some(output) = args.merge_only.clone() {
+        return merge_only(&args, &output, summary, timings).await;
+    }
+
+    if args.dedup_readonly {
+        warn!(
+            "--dedup-readonly is set: the dedup hashset {} will NOT be updated by this run",
+            args.dedup_hashset_file.display()
+        );
+    }
+    validate_db_ids(&args)
+        .wrap_err("invalid database configuration")
+        .or_exit(Failure::Config)?;
+    let table_overrides = resolve_db_tables(&args)
+        .wrap_err("invalid --db-table")
+        .or_exit(Failure::Config)?;
+    let merge_options = merge_options(&args)?;
+
+    let dedup_store = AnyDedupStore::open(
+        &args.dedup_hashset_file,
+        args.s3_endpoint.as_deref(),
+        args.run_id.as_deref(),
+    )
+    .await;
+    let pending_secondary = pending_secondary_path(&args.dedup_hashset_file);
+    if (args.stop_after_inactive || args.secondary_only)
+        && matches!(dedup_store, AnyDedupStore::S3(_))
+    {
+        return Err(RunError::new(
+            Failure::Config,
+            eyre!(
+                "--stop-after-inactive and --secondary-only keep their pending dedup state on local disk and need a local --dedup-hashset-file"
+            ),
+        ));
+    }
+    if args.secondary_only {
+        if !pending_secondary.exists() {
+            return Err(RunError::new(
+                Failure::Config,
+                eyre!(
+                    "--secondary-only found no pending secondary backfill at {}; it resumes a --stop-after-inactive run",
+                    pending_secondary.display()
+                ),
+            ));
+        }
+    } else if !args.skip_kv
+        && args.blue_db_id.is_some()
+        && args.green_db_id.is_some()
+        && pending_secondary.exists()
+    {
+        return Err(RunError::new(
+            Failure::Config,
+            eyre!(
+                "a --stop-after-inactive run is waiting for its secondary backfill ({} exists); uploading now would toggle production to the database missing its entries, so run --secondary-only first",
+                pending_secondary.display()
+            ),
+        ));
+    }
+    let started = Instant::now();
+    dedup_store
+        .fetch()
+        .await
+        .wrap_err("failed to fetch dedup hashset")
+        .or_exit(Failure::Merge)?;
+    timings.record("fetch dedup hashset", started.elapsed());
+    let dedup_format = resolve_dedup_format(&args, dedup_store.local_path())
+        .wrap_err("failed to detect the dedup hashset format")
+        .or_exit(Failure::Merge)?;
+    args.dedup_hashset_format = Some(dedup_format);
+    if args.dedup_bloom && dedup_format != DedupFormat::Bincode {
+        return Err(RunError::new(
+            Failure::Config,
+            eyre!(
+                "--dedup-bloom streams the bincode dedup hashset and cannot save the {dedup_format:?} format"
+            ),
+        ));
+    }
+    if args.dedup_lookback_secs.is_some() && dedup_format != DedupFormat::Timestamped {
+        return Err(RunError::new(
+            Failure::Config,
+            eyre!("--dedup-lookback-secs requires --dedup-hashset-format timestamped"),
+        ));
+    }
+
+    // merge, against the dedup state of the secondary database when backfilling it
+    let merge_dedup_path = if args.secondary_only {
+        pending_secondary.clone()
+    } else {
+        dedup_store.local_path().to_owned()
+    };
+    let merge::MergeOutput {
+        entries,
+        files,
+        mut dedup_hashset,
+        stats: merge_stats,
+    } = merge_off_runtime(&args, merge_dedup_path, merge_options, timings)
+        .await
+        .or_exit(Failure::Merge)?;
+    if let Some(dedup_stats_json) = args.dedup_stats_json.as_deref() {
+        merge::write_merge_stats(&merge_stats, dedup_stats_json)
+            .wrap_err("failed to write dedup stats")
+            .or_exit(Failure::Merge)?;
+    }
+    info!(
+        "Merged {} files into {} new entries",
+        files.len(),
+        entries.len()
+    );
+    let entries = if args.secondary_only {
+        backfill_entries(dedup_store.local_path(), entries)
+            .wrap_err("failed to select the entries to backfill")
+            .or_exit(Failure::Merge)?
+    } else {
+        entries
+    };
+    // Sampling happens right after the merge (and the --secondary-only backfill selection), so
+    // the exports, the uploads and the dedup hashset only ever see the sampled pdas.
+    let entries = match args.sample {
+        Some(sample_size) => sample_entries(entries, sample_size, args.sample_seed),
+        None => entries,
+    };
+    // Unserializable entries are dropped before anything is exported, so the exports and the
+    // program index describe exactly the uploaded rows.
+    let entries = if args.skip_unserializable {
+        skip_unserializable(&args, entries).or_exit(Failure::Config)?
+    } else {
+        entries
+    };
+    summary.source_files = files.len();
+    summary.new_entries = entries.len();
+    summary.rent = rent_estimate(&args, &entries);
+
+    if let Some(new_entries_out) = args.new_entries_out.as_deref() {
+        merge::write_pda_list(&entries, new_entries_out)
+            .wrap_err("failed to write new entries list")
+            .or_exit(Failure::Merge)?;
+    }
+
+    if let Some(export_parquet) = args.export_parquet.as_deref() {
+        timings
+            .time("export parquet", || {
+                export::export_parquet(&entries, export_parquet)
+            })
+            .wrap_err("failed to export parquet")
+            .or_exit(Failure::Merge)?;
+    }
+
+    if let Some(program_index_out) = args.program_index_out.as_deref() {
+        export::program_index(&entries)
+            .and_then(|index| index.write(program_index_out))
+            .wrap_err("failed to write program index")
+            .or_exit(Failure::Merge)?;
+    }
+
+    if args.fail_on_empty && entries.is_empty() {
+        return Err(RunError::new(
+            Failure::Empty,
+            eyre!("merge produced no new entries and --fail-on-empty is set"),
+        ));
+    }
+
+    if args.validate_sql {
+        timings
+            .time("validate sql", || validate_sql(&args, &entries))
+            .wrap_err("generated SQL failed validation")
+            .or_exit(Failure::Config)?;
+    }
+
+    if args.estimate {
+        timings
+            .time("estimate", || estimate_upload(&args, &entries))
+            .wrap_err("failed to estimate upload")
+            .or_exit(Failure::Merge)?;
+        return Ok(());
+    }
+
+    // Only the uploads talk to Cloudflare, so --estimate runs without credentials.
+    let api_token = args
+        .token
+        .clone()
+        .ok_or_else(|| eyre!("--token is required"))
+        .or_exit(Failure::Config)?;
+    let account_id = args
+        .account_id
+        .clone()
+        .ok_or_else(|| eyre!("--account-id is required"))
+        .or_exit(Failure::Config)?;
+    let api_base = args.cloudflare_api_base.trim_end_matches('/');
+    let client = new_client(
+        Credentials::UserAuthToken {
+            token: api_token.clone(),
+        },
+        api_base,
+    )
+    .wrap_err("failed to create client")
+    .or_exit(Failure::Config)?;
+    let kv = CloudflareKv {
+        client,
+        account_id: account_id.clone(),
+        namespace_id: NAMESPACE_ID.to_owned(),
+    };
+    let active_db = if args.skip_kv {
+        info!("--skip-kv: not reading or writing {ACTIVE_DB_KEY}");
+        None
+    } else {
+        let started = Instant::now();
+        let active_db = match args.force_active_db {
+            Some(forced) => {
+                warn!(
+                    "!!! --force-active-db is set: treating {} as the active database WITHOUT reading {ACTIVE_DB_KEY} from KV !!!",
+                    forced.as_str()
+                );
+                forced.as_str().to_owned()
+            }
+            None => kv
+                .get(ACTIVE_DB_KEY)
+                .await
+                .wrap_err("failed to get current db")
+                .or_exit(Failure::Config)?
+                .ok_or_else(|| eyre!("no current db"))
+                .or_exit(Failure::Config)?,
+        };
+        timings.record("read active db", started.elapsed());
+        info!("Current production db: {active_db}");
+        summary.active_db_before = Some(active_db.clone());
+        summary.active_db_after = Some(active_db.clone());
+        Some(active_db)
+    };
+
+    if let Some(target_db_id) = args.target_db_id.as_deref() {
+        let backend =
+            build_backend(&args, api_base, &api_token, &account_id, &table_overrides).await?;
+        let create_indexes = args.create_indexes && args.backend == BackendKind::CloudflareD1;
+        warn_unapplied_schema_options(&args, true);
+        if !args.replica_db_ids.is_empty() {
+            warn!(
+                "--skip-kv uploads to {target_db_id} alone, ignoring --replica-db-id {}",
+                args.replica_db_ids.join(", ")
+            );
+        }
+        if args.check_schema {
+            check_schema(
+                &args,
+                api_base,
+                &api_token,
+                &account_id,
+                &[target_db_id],
+                &table_overrides,
+            )
+            .await
+            .wrap_err("schema check failed")
+            .or_exit(Failure::Config)?;
+        }
+        if args.require_all_replicas_healthy {
+            check_databases_healthy(
+                &args,
+                api_base,
+                &api_token,
+                &account_id,
+                &[target_db_id],
+                &table_overrides,
+            )
+            .await
+            .wrap_err("health preflight failed, nothing was uploaded")
+            .or_exit(Failure::Config)?;
+        }
+        let mut mirror = args
+            .output_db_path
+            .as_deref()
+            .map(|path| {
+                LocalMirror::open(
+                    path,
+                    &UploadOptions {
+                        table_name: table_for(&args, &table_overrides, target_db_id).to_owned(),
+                        ..upload_options(&args)
+                    },
+                )
+            })
+            .transpose()
+            .wrap_err("failed to open local mirror")
+            .or_exit(Failure::Config)?;
+        let mut dead_letter = args
+            .dead_letter
+            .as_deref()
+            .map(DeadLetter::open)
+            .transpose()
+            .or_exit(Failure::Config)?;
+
+        let total_entries = entries.len();
+        info!(
+            "Uploading {total_entries} entries to target database {target_db_id} in {} chunk(s), leaving {ACTIVE_DB_KEY} untouched",
+            total_entries.div_ceil(CHUNK_SIZE)
+        );
+        let started = Instant::now();
+        let report = upload_chunks(
+            &backend,
+            target_db_id,
+            "target",
+            &entries,
+            &args,
+            dead_letter.as_mut(),
+            async |_, chunk| match mirror.as_mut() {
+                Some(mirror) => mirror.insert(chunk),
+                None => Ok(()),
+            },
+        )
+        .await
+        .wrap_err("failed to upload chunk to target D1 database")
+        .or_exit(Failure::Upload)?;
+        summary.record_import(&report);
+        record_bookmark(&args, "target", target_db_id, total_entries, &report);
+        if create_indexes {
+            index_database(
+                api_base,
+                &api_token,
+                &account_id,
+                target_db_id,
+                table_for(&args, &table_overrides, target_db_id),
+            )
+            .await
+            .wrap_err("failed to create indexes on target D1 database")
+            .or_exit(Failure::Upload)?;
+        }
+        timings.record("upload target", started.elapsed());
+
+        let started = Instant::now();
+        dedup_hashset.extend(entries.iter().map(|entry| entry.pda));
+        save_dedup_hashset(&args, &dedup_store, &dedup_hashset)
+            .await
+            .wrap_err("failed to save dedup hashset")
+            .or_exit(Failure::Upload)?;
+        timings.record("save dedup hashset", started.elapsed());
+        info!("Uploaded {total_entries} entries to target database {target_db_id}");
+    } else if let (Some(blue_db_id), Some(green_db_id), Some(active_db)) = (
+        args.blue_db_id.as_deref(),
+        args.green_db_id.as_deref(),
+        active_db,
+    ) {
+        let (inactive_db_id, new_active_label, secondary_db_id) = match active_db.as_str() {
+            "blue" => (green_db_id, "green", blue_db_id),
+            "green" => (blue_db_id, "blue", green_db_id),
+            other => {
+                return Err(RunError::new(
+                    Failure::Config,
+                    eyre!("unexpected active db: {other}"),
+                ));
+            }
+        };
+        // The database a paused run left behind is the one ACTIVE_DB now calls inactive.
+        let secondary_db_id = if args.secondary_only {
+            inactive_db_id
+        } else {
+            secondary_db_id
+        };
+
+        let upload_options = upload_options(&args);
+        let mut mirror = args
+            .output_db_path
+            .as_deref()
+            .map(|path| {
+                LocalMirror::open(
+                    path,
+                    &UploadOptions {
+                        table_name: table_for(&args, &table_overrides, inactive_db_id).to_owned(),
+                        ..upload_options.clone()
+                    },
+                )
+            })
+            .transpose()
+            .wrap_err("failed to open local mirror")
+            .or_exit(Failure::Config)?;
+        let mut dead_letter = args
+            .dead_letter
+            .as_deref()
+            .map(DeadLetter::open)
+            .transpose()
+            .or_exit(Failure::Config)?;
+
+        let backend =
+            build_backend(&args, api_base, &api_token, &account_id, &table_overrides).await?;
+
+        let create_indexes = args.create_indexes && args.backend == BackendKind::CloudflareD1;
+        warn_unapplied_schema_options(&args, true);
+
+        if args.check_schema {
+            let database_ids: Vec<&str> = [inactive_db_id, secondary_db_id]
+                .into_iter()
+                .chain(args.replica_db_ids.iter().map(String::as_str))
+                .collect();
+            check_schema(
+                &args,
+                api_base,
+                &api_token,
+                &account_id,
+                &database_ids,
+                &table_overrides,
+            )
+            .await
+            .wrap_err("schema check failed")
+            .or_exit(Failure::Config)?;
+        }
+
+        if args.require_all_replicas_healthy {
+            let database_ids: Vec<&str> = [inactive_db_id, secondary_db_id]
+                .into_iter()
+                .chain(args.replica_db_ids.iter().map(String::as_str))
+                .collect();
+            check_databases_healthy(
+                &args,
+                api_base,
+                &api_token,
+                &account_id,
+                &database_ids,
+                &table_overrides,
+            )
+            .await
+            .wrap_err("health preflight failed, nothing was uploaded or toggled")
+            .or_exit(Failure::Config)?;
+        }
+
+        // Asked before Step 1, so a declined toggle leaves every database untouched. The read
+        // blocks, so it runs off the runtime's worker threads.
+        let toggles =
+            !args.secondary_only && args.pda_shard.is_none_or(|shard| shard.toggles_active_db());
+        if toggles && !args.yes && std::io::stdin().is_terminal() {
+            let (active, target) = (active_db.clone(), new_active_label.to_owned());
+            let confirmed = tokio::task::spawn_blocking(move || confirm_toggle(&active, &target))
+                .await
+                .wrap_err("confirmation prompt panicked")
+                .and_then(|answer| answer.wrap_err("failed to read confirmation"))
+                .or_exit(Failure::Config)?;
+            if !confirmed {
+                return Err(RunError::new(
+                    Failure::Config,
+                    eyre!(
+                        "toggle to {new_active_label} declined; nothing was uploaded and {ACTIVE_DB_KEY} still points at {active_db}"
+                    ),
+                ));
+            }
+        }
+
+        let total_entries = entries.len();
+        let num_chunks = total_entries.div_ceil(CHUNK_SIZE);
+
+        // With --secondary-only, production already serves the entries and only Step 3 runs.
+        if args.secondary_only {
+            info!("--secondary-only: skipping Steps 1 and 2, {ACTIVE_DB_KEY} stays {active_db}");
+        } else {
+            // Step 1: Upload to inactive database in chunks
+            info!(
+                "Step 1: Uploading {total_entries} entries to inactive database {inactive_db_id} in {num_chunks} chunk(s) of up to {CHUNK_SIZE} entries"
+            );
+            let started = Instant::now();
+            let report = upload_chunks(
+                &backend,
+                inactive_db_id,
+                "inactive",
+                &entries,
+                &args,
+                dead_letter.as_mut(),
+                async |_, chunk| match mirror.as_mut() {
+                    Some(mirror) => mirror.insert(chunk),
+                    None => Ok(()),
+                },
+            )
+            .await
+            .wrap_err("failed to upload chunk to inactive D1 database")
+            .or_exit(Failure::Upload)?;
+            summary.record_import(&report);
+            record_bookmark(&args, "inactive", inactive_db_id, total_entries, &report);
+            if create_indexes {
+                index_database(
+                    api_base,
+                    &api_token,
+                    &account_id,
+                    inactive_db_id,
+                    table_for(&args, &table_overrides, inactive_db_id),
+                )
+                .await
+                .wrap_err("failed to create indexes on inactive D1 database")
+                .or_exit(Failure::Upload)?;
+            }
+            timings.record("step 1 upload inactive", started.elapsed());
+
+            // Step 2: Toggle the active database, from a single instance when sharded
+            if let Some(shard) = args.pda_shard.filter(|shard| !shard.toggles_active_db()) {
+                info!(
+                    "Step 2: Skipped for pda shard {shard}, only shard 0 toggles {ACTIVE_DB_KEY}"
+                );
+            } else {
+                info!("Step 2: Toggling active database to {new_active_label}");
+                let started = Instant::now();
+                kv.put(ACTIVE_DB_KEY, new_active_label)
+                    .await
+                    .wrap_err("failed to put kv")
+                    .or_exit(Failure::Upload)?;
+                info!("Database toggle complete");
+                summary.active_db_after = Some(new_active_label.to_owned());
+
+                if args.verify_after_toggle
+                    && let Err(err) = wait_for_active_db(
+                        &kv,
+                        new_active_label,
+                        Duration::from_secs(args.verify_toggle_timeout_secs),
+                    )
+                    .await
+                {
+                    error!(
+                        "Toggle verification failed, rolling back active database to {active_db}: {err:#}"
+                    );
+                    rollback_active_db(&kv, &active_db).await;
+                    summary.active_db_after = Some(active_db.clone());
+                    return Err(err)
+                        .wrap_err("active database toggle did not propagate")
+                        .or_exit(Failure::Upload);
+                }
+
+                timings.record("step 2 toggle", started.elapsed());
+            }
+        }
+
+        // Step 3: Upload to the secondary database and any replicas in chunks
+        let step3_targets: Vec<(&str, &str)> = if args.trim_to_active_only {
+            warn!(
+                "--trim-to-active-only: skipping Step 3, so secondary database {secondary_db_id} and any replicas stay behind until the next full run"
+            );
+            Vec::new()
+        } else if args.stop_after_inactive {
+            info!(
+                "--stop-after-inactive: pausing before Step 3, so secondary database {secondary_db_id} and any replicas wait for the backfill"
+            );
+            Vec::new()
+        } else {
+            std::iter::once(("secondary", secondary_db_id))
+                .chain(
+                    args.replica_db_ids
+                        .iter()
+                        .map(|id| ("replica", id.as_str())),
+                )
+                .collect()
+        };
+        let mut failed_targets = Vec::new();
+        let checkpoint_pending = args.secondary_only.then_some(pending_secondary.as_path());
+
+        for (index, &(label, database_id)) in step3_targets.iter().enumerate() {
+            // Checkpoints only run on the last Step 3 target: by then every earlier target
+            // holds the chunk as well, so recording it as deduplicated is safe.
+            let checkpoint = index + 1 == step3_targets.len();
+            info!(
+                "Step 3: Uploading {total_entries} entries to {label} database {database_id} in {num_chunks} chunk(s)"
+            );
+            let started = Instant::now();
+            let result = upload_chunks(
+                &backend,
+                database_id,
+                label,
+                &entries,
+                &args,
+                dead_letter.as_mut(),
+                async |chunk_num, chunk| {
+                    let Some(every) = args.checkpoint_every.filter(|_| checkpoint) else {
+                        return Ok(());
+                    };
+                    dedup_hashset.extend(chunk.iter().map(|entry| entry.pda));
+                    if chunk_num % every.get() == 0 {
+                        info!(
+                            "Checkpointing dedup hashset after chunk {chunk_num}/{num_chunks} ({} entries)",
+                            dedup_hashset.len()
+                        );
+                        checkpoint_dedup_hashset(
+                            &args,
+                            &dedup_store,
+                            checkpoint_pending,
+                            &dedup_hashset,
+                        )
+                        .await?;
+                    }
+                    Ok(())
+                },
+            )
+            .await;
+            let result = match result {
+                Ok(report) if create_indexes => index_database(
+                    api_base,
+                    &api_token,
+                    &account_id,
+                    database_id,
+                    table_for(&args, &table_overrides, database_id),
+                )
+                .await
+                .map(|()| report),
+                result => result,
+            };
+            timings.record(
+                format!("step 3 upload {label} {database_id}"),
+                started.elapsed(),
+            );
+
+            let err = match result {
+                Ok(report) => {
+                    summary.record_import(&report);
+                    record_bookmark(&args, label, database_id, total_entries, &report);
+                    continue;
+                }
+                Err(err) => err,
+            };
+
+            if args.continue_on_replica_error {
+                error!("Step 3 failed for {label} database {database_id}, continuing: {err:#}");
+                failed_targets.push(database_id);
+                summary.failed_targets.push(database_id.to_owned());
+                continue;
+            }
+
+            // Only the instance that toggled may roll back; a shard that skipped Step 2 read
+            // ACTIVE_DB before shard 0 toggled it and would restore a stale value.
+            if toggles {
+                error!("Step 3 failed, rolling back active database to {active_db}: {err:#}");
+                rollback_active_db(&kv, &active_db).await;
+                summary.active_db_after = Some(active_db.clone());
+            }
+            return Err(err)
+                .wrap_err(format!("failed to upload chunk to {label} D1 database"))
+                .or_exit(Failure::Upload);
+        }
+
+        // Step 4: Update and save dedup hashset to disk only after all uploads succeed
+        if args.secondary_only {
+            // The main dedup hashset already holds the backfilled entries, the pending state
+            // is what trailed it.
+            if failed_targets.is_empty() {
+                info!(
+                    "Step 4: Secondary database {secondary_db_id} caught up, clearing its pending dedup state"
+                );
+                std::fs::remove_file(&pending_secondary)
+                    .wrap_err_with(|| format!("failed to remove {}", pending_secondary.display()))
+                    .or_exit(Failure::Upload)?;
+            } else {
+                warn!(
+                    "Keeping {} so --secondary-only can retry the failed database(s)",
+                    pending_secondary.display()
+                );
+            }
+        } else if args.trim_to_active_only {
+            warn!(
+                "--trim-to-active-only: leaving the dedup hashset unchanged so the next run uploads these {total_entries} entries again"
+            );
+        } else {
+            // The secondary has to be backfilled from the dedup state it still matches, which
+            // Step 4 is about to advance.
+            if args.stop_after_inactive {
+                snapshot_secondary_dedup(&args, dedup_store.local_path(), &pending_secondary)
+                    .wrap_err("failed to save the pending secondary dedup state")
+                    .or_exit(Failure::Upload)?;
+            }
+            info!("Step 4: Updating and saving dedup hashset to disk");
+            let started = Instant::now();
+            let before = dedup_hashset.len();
+            dedup_hashset.extend(entries.iter().map(|entry| entry.pda));
+            info!(
+                "Extended dedup hashset with {} new entries (now contains {} total)",
+                dedup_hashset.len() - before,
+                dedup_hashset.len()
+            );
+            save_dedup_hashset(&args, &dedup_store, &dedup_hashset)
+                .await
+                .wrap_err("failed to save dedup hashset")
+                .or_exit(Failure::Upload)?;
+            timings.record("step 4 save dedup hashset", started.elapsed());
+        }
+
+        if args.stop_after_inactive {
+            info!(
+                "Paused after Step 2 with {total_entries} entries on {new_active_label}; secondary database {secondary_db_id} is pending its backfill"
+            );
+            println!(
+                "{}",
+                serde_json::json!({
+                    "resume": "--secondary-only",
+                    "active_db": new_active_label,
+                    "secondary_db_id": secondary_db_id,
+                    "pending_dedup_hashset": pending_secondary,
+                    "entries": total_entries,
+                })
+            );
+        } else if failed_targets.is_empty() {
+            info!("All operations completed successfully!");
+        } else {
+            error!(
+                "Completed with {} failed Step 3 database(s) that need a backfill: {}",
+                failed_targets.len(),
+                failed_targets.join(", ")
+            );
+        }
+    } else {
+        info!("Skipping D1 uploads because --blue-db-id and --green-db-id were not provided");
+        warn_unapplied_schema_options(&args, false);
+        if args.force_active_db.is_some() {
+            warn!(
+                "--force-active-db was used without uploads, so {ACTIVE_DB_KEY} in KV was not rewritten"
+            );
+        }
+        // Still save the hashset even when skipping uploads (for testing)
+        save_dedup_hashset(&args, &dedup_store, &dedup_hashset)
+            .await
+            .wrap_err("failed to save dedup hashset")
+            .or_exit(Failure::Merge)?;
+    }
+
+    settle_source_files(&args, &files);
+
+    // todo: update telegram bot
+
+    // remove old files
+    // for file in files {
+    //     if let Err(err) = std::fs::remove_file(&file) {
+    //         warn!("Failed to remove source blob {}: {err}", file.display());
+    //     }
+    // }
+
+    Ok(())
+}
+
+/// Moves the parsed source `files` into `--processed-dir` unless a later run still needs them,
+/// then clears `--merge-state`. A `--skip-kv` run leaves them for the runs uploading to the
+/// other databases, and a `--pda-shard` instance for the other shards.
+fn settle_source_files(args: &Args, files: &[PathBuf]) {
+    // Without a dedup update the next run has to read these files again. A paused run leaves
+    // them for its backfill, which merges them against the pending dedup state and leaves them
+    // in place too, since a retried backfill needs them again.
+    if args.trim_to_active_only && args.processed_dir.is_some() {
+        warn!("--trim-to-active-only: leaving source files in place instead of moving them");
+    } else if args.stop_after_inactive && args.processed_dir.is_some() {
+        info!("Leaving source files in place for the secondary backfill");
+    } else if args.secondary_only && args.processed_dir.is_some() {
+        info!("--secondary-only: leaving source files in place");
+    } else if args.skip_kv && args.processed_dir.is_some() {
+        info!("--skip-kv: leaving source files in place for the other databases");
+    } else if let Some(shard) = args.pda_shard
+        && args.processed_dir.is_some()
+    {
+        info!("Leaving source files in place for the pda shards other than {shard}");
+    } else if let Some(processed_dir) = args.processed_dir.as_deref()
+        && let Err(err) = merge::move_to_processed(files, processed_dir)
+    {
+        warn!("Failed to move processed files: {err:#}");
+    }
+    // Cleared only after the move, so files an interrupted merge parsed stay listed until then.
+    if let Some(merge_state) = args.merge_state.as_deref()
+        && let Err(err) = merge_state::clear_merge_state(merge_state)
+    {
+        warn!("{err:#}");
+    }
+}
+
+/// Warns about the schema options this run leaves unapplied: neither reaches a database
+/// without uploads, indexes are only created on D1, and bincode S3 payloads have no columns.
+fn warn_unapplied_schema_options(args: &Args, uploading: bool) {
+    if !uploading {
+        for (set, flag) in [
+            (args.create_indexes, "--create-indexes"),
+            (args.with_checksums, "--with-checksums"),
+        ] {
+            if set {
+                warn!("Ignoring {flag}: no database is uploaded to");
+            }
+        }
+        return;
+    }
+    if args.create_indexes && args.backend != BackendKind::CloudflareD1 {
+        warn!("Ignoring --create-indexes: only supported for --backend cloudflare-d1");
+    }
+    if args.with_checksums
+        && args.backend == BackendKind::S3
+        && args.s3_format == S3PayloadFormat::Bincode
+    {
+        warn!(
+            "Ignoring --with-checksums: --s3-format bincode payloads have no seed_checksum column"
+        );
+    }
+}
+
+/// Builds the `--backend` the chunks are uploaded through.
+async fn build_backend(
+    args: &Args,
+    api_base: &str,
+    api_token: &str,
+    account_id: &str,
+    table_overrides: &HashMap<String, String>,
+) -> Result<Backend, RunError> {
+    let upload_options = upload_options(args);
+    if args.transactional && args.backend == BackendKind::CloudflareD1 {
+        warn!("Ignoring --transactional: D1's importer rejects BEGIN/COMMIT in import scripts");
+    }
+    Ok(match args.backend {
+        BackendKind::CloudflareD1 => Backend::CloudflareD1(CloudflareD1Backend {
+            api_base: api_base.to_owned(),
+            api_token: api_token.to_owned(),
+            account_id: account_id.to_owned(),
+            options: UploadOptions {
+                transactional: false,
+                ..upload_options
+            },
+            table_overrides: table_overrides.clone(),
+            chunk_timeout: args.chunk_timeout_secs.map(Duration::from_secs),
+            upload_mode: args.upload_mode,
+            query_mode_max_entries: args.query_mode_max_entries,
+        }),
+        BackendKind::S3 => Backend::S3(S3Backend {
+            client: s3::new_s3_client(args.s3_endpoint.as_deref()).await,
+            bucket: args
+                .s3_bucket
+                .clone()
+                .ok_or_else(|| eyre!("--s3-bucket is required"))
+                .or_exit(Failure::Config)?,
+            prefix: args.s3_prefix.clone(),
+            format: args.s3_format,
+            options: upload_options,
+            table_overrides: table_overrides.clone(),
+        }),
+    })
+}
+
+/// The `--rent-lamports-per-byte-year` estimate for `entries`, if asked for.
+fn rent_estimate(args: &Args, entries: &[PdaSqlite]) -> Option<RentEstimate> {
+    args.rent_lamports_per_byte_year
+        .map(|lamports_per_byte_year| {
+            RentEstimate::collect(
+                entries,
+                args.rent_account_base_bytes,
+                lamports_per_byte_year,
+            )
+        })
+}
+
+fn upload_options(args: &Args) -> UploadOptions {
+    UploadOptions {
+        with_seeds_json: args.with_seeds_json,
+        with_checksums: args.with_checksums,
+        transactional: args.transactional,
+        table_name: args.table_name.clone(),
+        force_reupload: args.force_reupload,
+        entries_per_statement: args.entries_per_statement.get(),
+        adaptive_entries_per_statement: args.entries_per_statement_auto,
+        max_rows_per_file: args.max_insert_rows_per_file,
+        max_file_bytes: args.max_file_bytes,
+        r2_content_type: args.r2_content_type.clone(),
+        // A `run-id` passed to --r2-metadata replaces the run's own, so the header is sent once.
+        r2_metadata: args
+            .run_id
+            .as_deref()
+            .filter(|_| !args.r2_metadata.iter().any(|(key, _)| key == "run-id"))
+            .map(|run_id| ("run-id".to_owned(), run_id.to_owned()))
+            .into_iter()
+            .chain(args.r2_metadata.iter().cloned())
+            .collect(),
+    }
+}
+
+/// Drops the entries the upload could not render for `--skip-unserializable`, logging each and
+/// recording them in `--dead-letter`, instead of letting one fail its whole chunk.
+fn skip_unserializable(args: &Args, entries: Vec<PdaSqlite>) -> eyre::Result<Vec<PdaSqlite>> {
+    let options = upload_options(args);
+    let (entries, failed) = split_unrenderable(entries, |entry| check_renderable(entry, &options));
+    if failed.is_empty() {
+        return Ok(entries);
+    }
+
+    let mut dead_letter = args
+        .dead_letter
+        .as_deref()
+        .map(DeadLetter::open)
+        .transpose()?;
+    for (entry, err) in &failed {
+        warn!(
+            "Skipping unserializable entry for pda {}: {err:#}",
+            entry.pda
+        );
+        record_dead_letter(
+            dead_letter.as_mut(),
+            std::slice::from_ref(entry),
+            &format!("unserializable: {err:#}"),
+        );
+    }
+    warn!(
+        "Skipped {} unserializable entries, uploading the other {}",
+        failed.len(),
+        entries.len()
+    );
+    Ok(entries)
+}
+
+/// Executes the script of every upload chunk in an in-memory sqlite database for
+/// `--validate-sql`, checking that each entry lands as exactly one row.
+fn validate_sql(args: &Args, entries: &[PdaSqlite]) -> eyre::Result<()> {
+    let options = UploadOptions {
+        transactional: args.transactional && args.backend != BackendKind::CloudflareD1,
+        ..upload_options(args)
+    };
+    let columns = schema::expected_d1_columns(options.with_seeds_json, options.with_checksums);
+    let scripts = entries
+        .chunks(CHUNK_SIZE)
+        .map(|chunk| build_insert_script(chunk, &options))
+        .collect::<eyre::Result<Vec<_>>>()?;
+    let num_scripts = scripts.len();
+    let rows =
+        schema::execute_in_memory(&options.table_name, &columns, scripts.into_iter().flatten())?;
+    if rows != entries.len() as u64 {
+        return Err(eyre!(
+            "{} entries produced {rows} rows in the in-memory table",
+            entries.len()
+        ));
+    }
+    info!("Validated the SQL of {num_scripts} chunk(s): {rows} rows inserted in memory");
+    Ok(())
+}
+
+/// Prints the `--estimate` prediction: the SQL each chunk would upload, timed at
+/// `--estimate-mbps` plus `--estimate-ingest-secs-per-chunk` of D1 ingest per chunk, for Step 1
+/// and every Step 3 database. Retries, polling jitter and rate limits are not modelled.
+fn estimate_upload(args: &Args, entries: &[PdaSqlite]) -> eyre::Result<()> {
+    let options = UploadOptions {
+        transactional: false,
+        ..upload_options(args)
+    };
+    let script_bytes = entries
+        .par_chunks(CHUNK_SIZE)
+        .map(|chunk| {
+            let script = build_insert_script(chunk, &options)?;
+            Ok(script.map_or(0, |script| script.len() as u64))
+        })
+        .collect::<eyre::Result<Vec<u64>>>()?;
+    let num_chunks = script_bytes.len();
+    let total_bytes: u64 = script_bytes.iter().sum();
+
+    let databases = 2 + args.replica_db_ids.len();
+    let upload_secs = total_bytes as f64 * 8.0 / (args.estimate_mbps * 1_000_000.0);
+    let ingest_secs = num_chunks as f64 * args.estimate_ingest_secs_per_chunk;
+    let per_database = Duration::from_secs_f64(upload_secs + ingest_secs);
+
+    println!("new entries: {}", entries.len());
+    println!("chunks per database: {num_chunks} of up to {CHUNK_SIZE} entries");
+    println!("sql per database: {total_bytes} bytes");
+    println!(
+        "per database: {per_database:.0?} ({upload_secs:.0}s upload at {} Mbps, {ingest_secs:.0}s ingest at {}s per chunk)",
+        args.estimate_mbps, args.estimate_ingest_secs_per_chunk
+    );
+    println!(
+        "predicted total for {databases} database(s): {:.0?}",
+        per_database * databases as u32
+    );
+    Ok(())
+}
+
+/// Uniformly samples `sample_size` entries without replacement, keeping their merge order.
+/// A fixed `seed` makes the sample reproducible.
+fn sample_entries(
+    entries: Vec<PdaSqlite>,
+    sample_size: usize,
+    seed: Option<u64>,
+) -> Vec<PdaSqlite> {
+    if sample_size >= entries.len() {
+        info!(
+            "Sample size {sample_size} covers all {} new entries, uploading everything",
+            entries.len()
+        );
+        return entries;
+    }
+
+    let mut rng = match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_os_rng(),
+    };
+    let mut keep = vec![false; entries.len()];
+    for index in rand::seq::index::sample(&mut rng, entries.len(), sample_size) {
+        keep[index] = true;
+    }
+    info!(
+        "Sampled {sample_size} of {} new entries (seed: {seed:?})",
+        entries.len()
+    );
+    entries
+        .into_iter()
+        .zip(keep)
+        .filter_map(|(entry, keep)| keep.then_some(entry))
+        .collect()
+}
+
+/// Persists the dedup hashset unless `--dedup-readonly` is set, in which case every save,
+/// checkpoints included, is skipped with a warning. With `--dedup-bloom` the in-memory set only
+/// holds this run's additions, so they are merged into the set on disk instead. The saved file
+/// is then published to the dedup store.
+async fn save_dedup_hashset(
+    args: &Args,
+    dedup_store: &impl DedupStore,
+    dedup_hashset: &HashSet<Address>,
+) -> eyre::Result<()> {
+    if write_dedup_hashset(args, dedup_store.local_path(), dedup_hashset)? {
+        dedup_store.publish().await?;
+    }
+    Ok(())
+}
+
+/// Saves a Step 3 checkpoint of the dedup hashset and publishes it like the final save, so a
+/// run that dies on an ephemeral host keeps its progress. A `--secondary-only` run advances its
+/// `pending` dedup state instead, which stays on local disk.
+async fn checkpoint_dedup_hashset(
+    args: &Args,
+    dedup_store: &impl DedupStore,
+    pending: Option<&Path>,
+    dedup_hashset: &HashSet<Address>,
+) -> eyre::Result<()> {
+    match pending {
+        Some(pending) => write_dedup_hashset(args, pending, dedup_hashset).map(drop),
+        None => save_dedup_hashset(args, dedup_store, dedup_hashset).await,
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// Where a `--stop-after-inactive` run keeps the dedup hashset the secondary database still
+/// matches until it is backfilled.
+fn pending_secondary_path(dedup_hashset_file: &Path) -> PathBuf {
+    with_suffix(dedup_hashset_file, ".pending-secondary")
+}
+
+/// Keeps the `--secondary-only` entries the main dedup hashset at `dedup_hashset_path` already
+/// holds: those production serves but the secondary database lacks. Entries new to both
+/// databases wait for the next full run, which uploads them to production first.
+fn backfill_entries(
+    dedup_hashset_path: &Path,
+    mut entries: Vec<PdaSqlite>,
+) -> eyre::Result<Vec<PdaSqlite>> {
+    let uploaded = if dedup_hashset_path.exists() {
+        merge::load_dedup_hashset(dedup_hashset_path)?
+    } else {
+        HashSet::new()
+    };
+    let before = entries.len();
+    entries.retain(|entry| uploaded.contains(&entry.pda));
+    info!(
+        "Backfilling {} entries production already serves; {} entries new to both databases wait for the next full run",
+        entries.len(),
+        before - entries.len()
+    );
+    Ok(entries)
+}
+
+/// Copies the dedup hashset at `dedup_hashset_path`, not yet advanced by this run, to
+/// `pending`, or saves an empty set there if there is none yet.
+fn snapshot_secondary_dedup(
+    args: &Args,
+    dedup_hashset_path: &Path,
+    pending: &Path,
+) -> eyre::Result<()> {
+    info!(
+        "Keeping the dedup hashset the secondary database matches at {}",
+        pending.display()
+    );
+    if dedup_hashset_path.exists() {
+        std::fs::copy(dedup_hashset_path, pending).wrap_err_with(|| {
+            format!(
+                "failed to copy {} to {}",
+                dedup_hashset_path.display(),
+                pending.display()
+            )
+        })?;
+    } else {
+        merge::save_dedup_hashset_with_fsync(
+            &HashSet::new(),
+            pending,
+            args.dedup_hashset_format.unwrap_or_default(),
+            args.fsync_mode,
+        )?;
+    }
+    Ok(())
+}
+
+/// The format the run saves the dedup hashset at `path` in: `--dedup-hashset-format` if given,
+/// else the format the set is already in, else bincode.
+fn resolve_dedup_format(args: &Args, path: &Path) -> eyre::Result<DedupFormat> {
+    match args.dedup_hashset_format {
+        Some(format) => Ok(format),
+        None if path.exists() => merge::detect_dedup_format(path),
+        None => Ok(DedupFormat::default()),
+    }
+}
+
+/// Writes the dedup hashset to `path` without publishing it, returning whether anything was
+/// written. State that never leaves local disk, like the `--secondary-only` pending state, is
+/// saved through this directly.
+fn write_dedup_hashset(
+    args: &Args,
+    path: &Path,
+    dedup_hashset: &HashSet<Address>,
+) -> eyre::Result<bool> {
+    if args.dedup_readonly {
+        warn!(
+            "--dedup-readonly: skipping save of {} entries to {}",
+            dedup_hashset.len(),
+            args.dedup_hashset_file.display()
+        );
+        return Ok(false);
+    }
+    if args.dedup_bloom {
+        merge::save_dedup_hashset_streaming(dedup_hashset, path, args.fsync_mode)?;
+    } else {
+        merge::save_dedup_hashset_with_fsync(
+            dedup_hashset,
+            path,
+            args.dedup_hashset_format.unwrap_or_default(),
+            args.fsync_mode,
+        )?;
+    }
+    Ok(true)
+}
+
+/// Asks on the terminal whether to toggle production from `active` to `target`, defaulting to
+/// no.
+fn confirm_toggle(active: &str, target: &str) -> std::io::Result<bool> {
+    let mut stderr = std::io::stderr();
+    write!(
+        stderr,
+        "About to toggle production {ACTIVE_DB_KEY} from {active} to {target} (namespace {NAMESPACE_ID}). Continue? [y/N] "
+    )?;
+    stderr.flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(
+        answer.trim().to_ascii_lowercase().as_str(),
+        "y" | "yes"
+    ))
+}
+
+/// Polls `ACTIVE_DB` until it reads back `expected`, since Workers KV is eventually consistent.
+async fn wait_for_active_db(
+    kv: &impl KvStore,
+    expected: &str,
+    timeout: Duration,
+) -> eyre::Result<()> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        match kv.get(ACTIVE_DB_KEY).await {
+            Ok(Some(value)) if value == expected => {
+                info!("Verified active database reads back as {expected}");
+                return Ok(());
+            }
+            Ok(value) => debug!("Active database still reads {value:?}, waiting for {expected}"),
+            Err(err) => warn!("Failed to read back active database: {err:#}"),
+        }
+
+        if Instant::now() >= deadline {
+            return Err(TimedOut(format!(
+                "active database did not read back as {expected} within {timeout:?}"
+            ))
+            .into());
+        }
+        sleep(Duration::from_secs(2)).await;
+    }
+}
+
+/// Restores `ACTIVE_DB` to `previous`. A failed rollback is logged rather than returned so the
+/// error that triggered it stays the one reported.
+async fn rollback_active_db(kv: &impl KvStore, previous: &str) {
+    match kv.put(ACTIVE_DB_KEY, previous).await {
+        Ok(()) => info!("Rolled back active database to {previous}"),
+        Err(err) => error!("Failed to roll back active database to {previous}: {err:#}"),
+    }
+}
+
+fn validate_db_ids(args: &Args) -> eyre::Result<()> {
+    if let (Some(blue_db_id), Some(green_db_id)) =
+        (args.blue_db_id.as_deref(), args.green_db_id.as_deref())
+        && blue_db_id == green_db_id
+    {
+        return Err(eyre::eyre!(
+            "--blue-db-id and --green-db-id must differ, both are {blue_db_id}"
+        ));
+    }
+
+    let mut seen = HashSet::new();
+    let all_ids = [args.blue_db_id.as_deref(), args.green_db_id.as_deref()]
+        .into_iter()
+        .flatten()
+        .chain(args.replica_db_ids.iter().map(String::as_str));
+    for database_id in all_ids {
+        if !seen.insert(database_id) {
+            return Err(eyre::eyre!(
+                "database id {database_id} is configured more than once"
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Keys the `--db-table` overrides by database id, resolving the `blue`/`green` labels.
+fn resolve_db_tables(args: &Args) -> eyre::Result<HashMap<String, String>> {
+    let configured: HashSet<&str> = [args.blue_db_id.as_deref(), args.green_db_id.as_deref()]
+        .into_iter()
+        .flatten()
+        .chain(args.replica_db_ids.iter().map(String::as_str))
+        .collect();
+
+    let mut table_overrides = HashMap::new();
+    for (database, table) in &args.db_tables {
+        let database_id = match database.as_str() {
+            "blue" => args.blue_db_id.as_deref(),
+            "green" => args.green_db_id.as_deref(),
+            database_id => Some(database_id).filter(|id| configured.contains(id)),
+        }
+        .ok_or_else(|| eyre!("{database}={table} does not name a configured database"))?;
+        if let Some(previous) = table_overrides.insert(database_id.to_owned(), table.clone()) {
+            return Err(eyre!(
+                "database {database_id} is given two tables: {previous} and {table}"
+            ));
+        }
+    }
+    Ok(table_overrides)
+}
+
+/// Table the uploader writes to in `database_id`.
+fn table_for<'a>(
+    args: &'a Args,
+    table_overrides: &'a HashMap<String, String>,
+    database_id: &str,
+) -> &'a str {
+    table_overrides
+        .get(database_id)
+        .map_or(args.table_name.as_str(), String::as_str)
+}
+
+/// Creates the `--create-indexes` indexes on `table` once its import has finished.
+async fn index_database(
+    api_base: &str,
+    api_token: &str,
+    account_id: &str,
+    database_id: &str,
+    table: &str,
+) -> eyre::Result<()> {
+    for statement in schema::index_statements(table) {
+        info!("Creating index on D1 database {database_id}: {statement}");
+        query_d1(api_base, api_token, account_id, database_id, &statement).await?;
+    }
+    Ok(())
+}
+
+/// The `--require-all-replicas-healthy` preflight: every database must answer a trivial query
+/// and hold the expected table schema. Every database is checked and all unhealthy ones are
+/// reported together.
+async fn check_databases_healthy(
+    args: &Args,
+    api_base: &str,
+    api_token: &str,
+    account_id: &str,
+    database_ids: &[&str],
+    table_overrides: &HashMap<String, String>,
+) -> eyre::Result<()> {
+    if args.backend != BackendKind::CloudflareD1 {
+        warn!("Skipping health preflight: only supported for --backend cloudflare-d1");
+        return Ok(());
+    }
+
+    let expected = schema::expected_d1_columns(args.with_seeds_json, args.with_checksums);
+    let mut unhealthy = Vec::new();
+    for &database_id in database_ids {
+        let table = table_for(args, table_overrides, database_id);
+        let health = async {
+            query_d1(api_base, api_token, account_id, database_id, "SELECT 1")
+                .await
+                .wrap_err("trivial query failed")?;
+            let columns =
+                d1_table_columns(api_base, api_token, account_id, database_id, table).await?;
+            schema::ensure_no_drift(&format!("table {table}"), &expected, &columns)
+        };
+        match health.await {
+            Ok(()) => info!("D1 database {database_id} is healthy"),
+            Err(err) => {
+                error!("D1 database {database_id} is unhealthy: {err:#}");
+                unhealthy.push(database_id);
+            }
+        }
+    }
+
+    if unhealthy.is_empty() {
+        return Ok(());
+    }
+    Err(eyre!(
+        "{} of {} database(s) are unhealthy: {}",
+        unhealthy.len(),
+        database_ids.len(),
+        unhealthy.join(", ")
+    ))
+}
+
+/// Compares a representative source sqlite file and each target D1 database against the
+/// columns the uploader reads and writes, failing on missing columns or type mismatches.
+async fn check_schema(
+    args: &Args,
+    api_base: &str,
+    api_token: &str,
+    account_id: &str,
+    database_ids: &[&str],
+    table_overrides: &HashMap<String, String>,
+) -> eyre::Result<()> {
+    if args.backend != BackendKind::CloudflareD1 {
+        warn!("Skipping schema check: only supported for --backend cloudflare-d1");
+        return Ok(());
+    }
+
+    let sqlite_files = match args.path.as_deref() {
+        Some(path) => merge::collect_sqlite_files(path, args.max_file_size)?,
+        None => Vec::new(),
+    };
+    let source_columns = match sqlite_files.first() {
+        Some(source) => {
+            info!("Checking schema of source sqlite file {}", source.display());
+            let columns = schema::sqlite_columns(source, "pda_registry")?;
+            schema::ensure_no_drift(
+                &format!("source {}", source.display()),
+                &schema::expected_source_columns(),
+                &columns,
+            )?;
+            Some(columns)
+        }
+        None => {
+            info!("No source sqlite files, checking D1 schemas only");
+            None
+        }
+    };
+
+    let expected = schema::expected_d1_columns(args.with_seeds_json, args.with_checksums);
+    for database_id in database_ids {
+        let table = table_for(args, table_overrides, database_id);
+        info!("Checking schema of table {table} in D1 database {database_id}");
+        let columns = d1_table_columns(api_base, api_token, account_id, database_id, table).await?;
+        schema::ensure_no_drift(&format!("D1 database {database_id}"), &expected, &columns)?;
+
+        if let Some(source_columns) = source_columns.as_deref() {
+            let extra = schema::extra_columns(source_columns, &columns);
+            if !extra.is_empty() {
+                warn!(
+                    "Source sqlite has columns not present in D1 database {database_id}: {}",
+                    extra.join(", ")
+                );
+            }
+        }
+    }
+
+    info!("Schema check passed");
+    Ok(())
+}
+
+/// Merges the sources into the single blob `output`, see `--merge-only`. The dedup hashset is
+/// the one `run` suffixed for `--dedup-per-database` and `--pda-shard`, so the blob holds what
+/// that upload would send; it is fetched and read but never saved.
+async fn merge_only(
+    args: &Args,
+    output: &Path,
+    summary: &mut RunSummary,
+    timings: &mut PhaseTimings,
+) -> Result<(), RunError> {
+    let merge_options = merge_options(args)?;
+    let dedup_store = AnyDedupStore::open(
+        &args.dedup_hashset_file,
+        args.s3_endpoint.as_deref(),
+        args.run_id.as_deref(),
+    )
+    .await;
+    let started = Instant::now();
+    dedup_store
+        .fetch()
+        .await
+        .wrap_err("failed to fetch dedup hashset")
+        .or_exit(Failure::Merge)?;
+    timings.record("fetch dedup hashset", started.elapsed());
+
+    let merged = merge_off_runtime(
+        args,
+        dedup_store.local_path().to_owned(),
+        merge_options,
+        timings,
+    )
+    .await
+    .or_exit(Failure::Merge)?;
+    summary.source_files = merged.files.len();
+    summary.new_entries = merged.entries.len();
+    summary.rent = rent_estimate(args, &merged.entries);
+
+    let mut program_index = export::ProgramIndex::default();
+    timings
+        .time("write blob", || match args.program_index_out {
+            Some(_) => merge::write_indexed_blob(&merged.entries, output, &mut program_index),
+            None => merge::write_blob(&merged.entries, output),
+        })
+        .wrap_err("failed to write merged blob")
+        .or_exit(Failure::Merge)?;
+    if let Some(program_index_out) = args.program_index_out.as_deref() {
+        program_index
+            .write(program_index_out)
+            .wrap_err("failed to write program index")
+            .or_exit(Failure::Merge)?;
+    }
+    info!(
+        "Merged {} files into {} entries in {}",
+        merged.files.len(),
+        merged.entries.len(),
+        output.display()
+    );
+
+    settle_source_files(args, &merged.files);
+    Ok(())
+}
+
+/// Runs [`merge::merge`] on a blocking thread, and in a dedicated rayon pool of
+/// `--merge-threads` threads when set, so its CPU-bound parsing and sorting never occupy a
+/// tokio worker and can overlap with network I/O. The `--url` blobs are downloaded first.
+async fn merge_off_runtime(
+    args: &Args,
+    dedup_hashset_path: PathBuf,
+    mut options: merge::MergeOptions,
+    timings: &mut PhaseTimings,
+) -> eyre::Result<merge::MergeOutput> {
+    if !args.urls.is_empty() {
+        let started = Instant::now();
+        options.remote_blobs = download_remote_blobs(&args.urls, args.max_file_size).await?;
+        timings.record("download remote blobs", started.elapsed());
+    }
+    let path = args.path.clone();
+    let threads = args.merge_threads;
+    let (result, merge_timings) = tokio::task::spawn_blocking(move || {
+        let mut timings = PhaseTimings::default();
+        let merge = || merge::merge(path, dedup_hashset_path, &options, &mut timings);
+        let result = match threads {
+            Some(threads) => rayon::ThreadPoolBuilder::new()
+                .num_threads(threads.get())
+                .thread_name(|index| format!("merge-{index}"))
+                .build()
+                .wrap_err("failed to build the merge thread pool")
+                .and_then(|pool| pool.install(merge)),
+            None => merge(),
+        };
+        (result, timings)
+    })
+    .await
+    .wrap_err("merge task panicked")?;
+    timings.extend(merge_timings);
+    result
+}
+
+/// Downloads every `--url` blob into memory, failing on the first that cannot be fetched.
+/// Like local files, blobs larger than `max_file_size` are skipped with a warning.
+async fn download_remote_blobs(
+    urls: &[reqwest::Url],
+    max_file_size: u64,
+) -> eyre::Result<Vec<merge::RemoteBlob>> {
+    let http = http_client()?;
+    let mut blobs = Vec::with_capacity(urls.len());
+    for url in urls {
+        info!("Downloading blob from {url}");
+        match download_remote_blob(&http, url, max_file_size).await? {
+            Some(bytes) => {
+                info!("Downloaded {} bytes from {url}", bytes.len());
+                blobs.push(merge::RemoteBlob {
+                    url: url.to_string(),
+                    bytes,
+                });
+            }
+            None => warn!("Skipping {url}: it exceeds --max-file-size of {max_file_size} bytes"),
+        }
+    }
+    Ok(blobs)
+}
+
+/// Downloads the body of `url`, or `None` once it turns out larger than `max_file_size`,
+/// from its `Content-Length` or while streaming it.
+async fn download_remote_blob(
+    http: &reqwest::Client,
+    url: &reqwest::Url,
+    max_file_size: u64,
+) -> eyre::Result<Option<Vec<u8>>> {
+    let mut response = http
+        .get(url.clone())
+        .send_with_retry(&format!("download of {url}"))
+        .await?
+        .error_for_status()
+        .wrap_err_with(|| format!("failed to download {url}"))?;
+    if response
+        .content_length()
+        .is_some_and(|len| len > max_file_size)
+    {
+        return Ok(None);
+    }
+    let mut bytes = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .wrap_err_with(|| format!("failed to read the body of {url}"))?
+    {
+        if (bytes.len() + chunk.len()) as u64 > max_file_size {
+            return Ok(None);
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(Some(bytes))
+}
+
+/// Builds the [`merge::MergeOptions`] of a run from its arguments, reading
+/// `--exclude-pda-list` and validating `--remap-program`.
+fn merge_options(args: &Args) -> Result<merge::MergeOptions, RunError> {
+    if args.program_index_out.is_some() && args.sort_by != SortOrder::Program {
+        return Err(RunError::new(
+            Failure::Config,
+            eyre!("--program-index-out requires --sort-by program"),
+        ));
+    }
+    let exclude_pdas = match args.exclude_pda_list.as_deref() {
+        Some(path) => merge::read_address_list(path)
+            .wrap_err("invalid --exclude-pda-list")
+            .or_exit(Failure::Config)?,
+        None => HashSet::new(),
+    };
+
+    let remap_programs: HashMap<Address, Address> = args.remap_programs.iter().copied().collect();
+    if remap_programs.len() < args.remap_programs.len() {
+        return Err(RunError::new(
+            Failure::Config,
+            eyre!("--remap-program lists the same OLD program more than once"),
+        ));
+    }
+    if args.verify_pda && !remap_programs.is_empty() {
+        warn!("--verify-pda runs after --remap-program and will drop every remapped entry");
+    }
+
+    Ok(merge::MergeOptions {
+        require_off_curve: args.require_off_curve,
+        verify_pda: args.verify_pda,
+        sort_by: args.sort_by,
+        pda_list_in: args.pda_list_in.clone(),
+        dead_letter: args.dead_letter.clone(),
+        dedup_bloom_bits: args.dedup_bloom.then_some(args.dedup_bloom_bits),
+        dedup_lookback_secs: args.dedup_lookback_secs,
+        max_file_size: Some(args.max_file_size),
+        trim_seed_padding: args.trim_seed_padding.then_some(args.seed_pad_len),
+        input_format: args.input_format,
+        merge_state: args.merge_state.clone(),
+        fail_on_empty: args.fail_on_empty,
+        exclude_pdas,
+        remap_programs,
+        warn_on_large_seeds: args.warn_on_large_seeds,
+        pda_shard: args.pda_shard,
+        strict_utf8: args.strict_utf8,
+        sqlite_open_retries: args.sqlite_open_retries,
+        skip_future_mtime: args.skip_future_mtime,
+        dedup_hashset_capacity: args.dedup_hashset_capacity,
+        dedup_readahead: args.dedup_hashset_readahead,
+        remote_blobs: Vec::new(),
+    })
+}
+
+/// Uploads `entries` to a database in chunks of `CHUNK_SIZE`, retrying each chunk up to
+/// `--chunk-attempts` times with exponential backoff before giving up on the whole phase; a
+/// fatal Cloudflare API error (see [`is_fatal_api_error`]) gives up at once. Returns the
+/// partial failures the backend reported across all chunks. Chunks that reported failed rows,
+/// and the chunk the phase gave up on, are recorded in `dead_letter`; D1 does not say which
+/// rows failed, so the whole chunk is, and re-processing it only re-inserts the rows that
+/// failed. With `--pipeline-depth`, chunks are rendered ahead of their upload, see
+/// [`UploadBackend::renderer`].
+async fn upload_chunks(
+    backend: &impl UploadBackend,
+    database_id: &str,
+    label: &str,
+    entries: &[PdaSqlite],
+    args: &Args,
+    mut dead_letter: Option<&mut DeadLetter>,
+    mut on_chunk_uploaded: impl AsyncFnMut(usize, &[PdaSqlite]) -> eyre::Result<()>,
+) -> eyre::Result<ImportReport> {
+    let num_chunks = entries.len().div_ceil(CHUNK_SIZE);
+    let attempts = args.chunk_attempts.max(1);
+
+    // Chunks reach the uploads through a channel. With --pipeline-depth, the backend's
+    // renderer fills it from blocking threads while the current chunk uploads; once it holds
+    // that many rendered chunks, rendering waits for the uploads to take one, so memory stays
+    // bounded however far the uploads fall behind. Without it, chunks pass through unrendered
+    // and each renders as it uploads.
+    let renderer = args
+        .pipeline_depth
+        .and_then(|_| backend.renderer(database_id));
+    let (rendered_tx, mut rendered_rx) =
+        mpsc::channel(args.pipeline_depth.map_or(1, NonZeroUsize::get));
+    let render = async move {
+        for chunk in entries.chunks(CHUNK_SIZE) {
+            let rendered = match &renderer {
+                Some(renderer) => {
+                    let (renderer, chunk) = (Arc::clone(renderer), chunk.to_vec());
+                    tokio::task::spawn_blocking(move || renderer(&chunk))
+                        .await
+                        .wrap_err("rendering a chunk panicked")
+                        .and_then(|rendered| rendered)
+                }
+                None => Ok(None),
+            };
+            // A closed channel means the uploads gave up on a chunk.
+            if rendered_tx.send((chunk, rendered)).await.is_err() {
+                break;
+            }
+        }
+    };
+
+    let upload = async move {
+        let mut report = ImportReport::default();
+        let mut chunk_num = 0;
+        while let Some((chunk, rendered)) = rendered_rx.recv().await {
+            chunk_num += 1;
+            let mut rendered = match rendered {
+                Ok(rendered) => rendered,
+                Err(err) => {
+                    let err = err.wrap_err(format!(
+                        "failed to render chunk {chunk_num}/{num_chunks} for {label} database {database_id}"
+                    ));
+                    record_dead_letter(dead_letter.as_deref_mut(), chunk, &format!("{err:#}"));
+                    return Err(err);
+                }
+            };
+            let mut attempt = 1;
+            loop {
+                info!(
+                    "Uploading chunk {chunk_num}/{num_chunks} to {label} database (attempt {attempt}/{attempts}): {} entries",
+                    chunk.len()
+                );
+
+                // A retry renders the chunk again as part of its upload.
+                let result = match rendered.take() {
+                    Some(rendered) => backend.upload_rendered(database_id, chunk, rendered).await,
+                    None => backend.upload(database_id, chunk).await,
+                };
+                match result {
+                    Ok(chunk_report) => {
+                        if !chunk_report.is_clean() {
+                            record_dead_letter(
+                                dead_letter.as_deref_mut(),
+                                chunk,
+                                &format!(
+                                    "{} row(s) of chunk {chunk_num} failed to import into {label} database {database_id}",
+                                    chunk_report.failed_rows
+                                ),
+                            );
+                        }
+                        report.add(chunk_report);
+                        break;
+                    }
+                    Err(err)
+                        if attempt < attempts
+                            && !is_fatal_api_error(&err, &args.halt_on_api_error_codes) =>
+                    {
+                        let backoff = Duration::from_secs(1 << attempt.min(6));
+                        warn!(
+                            "Chunk {chunk_num}/{num_chunks} to {label} database failed on attempt {attempt}/{attempts}, retrying in {backoff:?}: {err:#}"
+                        );
+                        sleep(backoff).await;
+                        attempt += 1;
+                    }
+                    Err(err) => {
+                        let err = err.wrap_err(format!(
+                            "chunk {chunk_num}/{num_chunks} to {label} database {database_id} failed after {attempt} attempt(s)"
+                        ));
+                        record_dead_letter(dead_letter.as_deref_mut(), chunk, &format!("{err:#}"));
+                        return Err(err);
+                    }
+                }
+            }
+
+            info!("Successfully uploaded chunk {chunk_num}/{num_chunks} to {label} database");
+            on_chunk_uploaded(chunk_num, chunk).await?;
+        }
+        Ok(report)
+    };
+    let ((), report) = tokio::join!(render, upload);
+    let report = report?;
+
+    if report.uploaded_bytes > 0 {
+        info!(
+            "Uploaded {} bytes in total to {label} database {database_id}",
+            report.uploaded_bytes
+        );
+    }
+    if !report.is_clean() {
+        error!(
+            "{} rows failed to import into {label} database {database_id} ({} failed statement(s))",
+            report.failed_rows, report.failed_statements
+        );
+    }
+    Ok(report)
+}
+
+/// Records rejected upload entries in `--dead-letter`. A failure to write them is logged
+/// rather than masking the upload's own outcome.
+fn record_dead_letter(dead_letter: Option<&mut DeadLetter>, entries: &[PdaSqlite], reason: &str) {
+    if let Some(dead_letter) = dead_letter
+        && let Err(err) = dead_letter.record(entries, reason)
+    {
+        error!(
+            "Failed to record {} rejected entries: {err:#}",
+            entries.len()
+        );
+    }
+}
+
+/// Appends the final bookmark of an import to `--bookmark-out`. The upload itself has already
+/// succeeded, so failing to record the bookmark is logged rather than failing the run.
+fn record_bookmark(
+    args: &Args,
+    label: &str,
+    database_id: &str,
+    entries: usize,
+    report: &ImportReport,
+) {
+    let Some(path) = args.bookmark_out.as_deref() else {
+        return;
+    };
+    let Some(bookmark) = report.final_bookmark.as_deref() else {
+        warn!("No bookmark reported for {label} database {database_id}, nothing to record");
+        return;
+    };
+    let record = BookmarkRecord::now(database_id, label, bookmark, entries);
+    if let Err(err) = append_bookmark(&record, path) {
+        error!("Failed to record bookmark {bookmark} for {label} database {database_id}: {err:#}");
+    }
+}
+
+/// Finishes a D1 import interrupted while polling, see [`Command::ContinueImport`].
+async fn continue_import(
+    args: &Args,
+    database_id: &str,
+    bookmark: Option<String>,
+    summary: &mut RunSummary,
+) -> Result<(), RunError> {
+    let (Some(api_token), Some(account_id)) = (args.token.as_deref(), args.account_id.as_deref())
+    else {
+        return Err(RunError::new(
+            Failure::Config,
+            eyre!("continue-import requires --token and --account-id"),
+        ));
+    };
+    let report = continue_d1_import(
+        args.cloudflare_api_base.trim_end_matches('/'),
+        api_token,
+        account_id,
+        database_id,
+        bookmark,
+    )
+    .await
+    .wrap_err_with(|| format!("failed to continue import into D1 database {database_id}"))
+    .or_exit(Failure::Upload)?;
+    summary.record_import(&report);
+    Ok(())
+}
+
+/// Prints what a D1 database stores for each of `pdas`, see [`Command::Lookup`].
+async fn lookup(args: &Args, database_id: &str, pdas: &[Address]) -> Result<(), RunError> {
+    let (Some(api_token), Some(account_id)) = (args.token.as_deref(), args.account_id.as_deref())
+    else {
+        return Err(RunError::new(
+            Failure::Config,
+            eyre!("lookup requires --token and --account-id"),
+        ));
+    };
+    let api_base = args.cloudflare_api_base.trim_end_matches('/');
+    let table = table_for(
+        args,
+        &resolve_db_tables(args).or_exit(Failure::Config)?,
+        database_id,
+    )
+    .to_owned();
+
+    for pda in pdas {
+        let stored = lookup_d1_pda(api_base, api_token, account_id, database_id, &table, pda)
+            .await
+            .wrap_err_with(|| format!("failed to look up {pda} in D1 database {database_id}"))
+            .or_exit(Failure::Upload)?;
+        let Some(stored) = stored else {
+            println!("{pda}: not found");
+            continue;
+        };
+        let (seeds, encoding) = merge::decode_seeds(&stored.seed_bytes);
+        let seeds = seeds.iter().map(hex::encode).collect::<Vec<_>>();
+        println!(
+            "{pda}: program_id={} seed_count={} seeds=[{}] ({encoding:?})",
+            stored.program_id,
+            stored.seed_count,
+            seeds.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Intersects the dedup hashset with the pdas of a live D1 database, see
+/// [`Command::CompactDedup`].
+async fn compact_dedup(
+    args: &Args,
+    dedup_hashset_file: &Path,
+    database_id: &str,
+    page_size: usize,
+) -> Result<(), RunError> {
+    let (Some(api_token), Some(account_id)) = (args.token.as_deref(), args.account_id.as_deref())
+    else {
+        return Err(RunError::new(
+            Failure::Config,
+            eyre!("compact-dedup requires --token and --account-id"),
+        ));
+    };
+    let table = table_for(
+        args,
+        &resolve_db_tables(args).or_exit(Failure::Config)?,
+        database_id,
+    )
+    .to_owned();
+
+    info!("Reading every pda of table {table} in D1 database {database_id}");
+    let live = d1_pdas(
+        args.cloudflare_api_base.trim_end_matches('/'),
+        api_token,
+        account_id,
+        database_id,
+        &table,
+        page_size.max(1),
+    )
+    .await
+    .wrap_err_with(|| format!("failed to read pdas from D1 database {database_id}"))
+    .or_exit(Failure::Upload)?;
+    info!("D1 database {database_id} holds {} pdas", live.len());
+    // An empty result far more likely means the wrong database or table than a wiped one.
+    if live.is_empty() {
+        return Err(RunError::new(
+            Failure::Config,
+            eyre!(
+                "D1 database {database_id} has no pdas in {table}; refusing to empty the dedup hashset"
+            ),
+        ));
+    }
+
+    let pruned = merge::retain_dedup_hashset(dedup_hashset_file, &live)
+        .wrap_err("failed to compact dedup hashset")
+        .or_exit(Failure::Merge)?;
+    info!(
+        "Compacted dedup hashset {}: dropped {pruned} pdas no longer in D1 database {database_id}",
+        dedup_hashset_file.display()
+    );
+    Ok(())
+}
+
+/// Runs the subcommands that need neither credentials nor the network.
+pub fn run_command(command: Command) -> Result<(), RunError> {
+    match command {
+        Command::PruneDedup {
+            dedup_hashset_file,
+            keep,
+        } => {
+            let pruned = merge::prune_dedup_hashset(&dedup_hashset_file, &keep)
+                .wrap_err("failed to prune dedup hashset")
+                .or_exit(Failure::Merge)?;
+            info!(
+                "Pruned {pruned} entries from dedup hashset {}",
+                dedup_hashset_file.display()
+            );
+        }
+        Command::ContinueImport { .. } | Command::Lookup { .. } | Command::CompactDedup { .. } => {
+            return Err(RunError::new(
+                Failure::Config,
+                eyre!(
+                    "continue-import, lookup and compact-dedup talk to D1 and must be run through `run`"
+                ),
+            ));
+        }
+        Command::MergeDedup { into, from } => {
+            let (before, after) = merge::merge_dedup_hashsets(&into, &from)
+                .wrap_err("failed to merge dedup hashsets")
+                .or_exit(Failure::Merge)?;
+            info!(
+                "Dedup hashset {} grew from {before} to {after} entries ({} added)",
+                into.display(),
+                after - before
+            );
+        }
+        Command::DiffSources {
+            a,
+            b,
+            only_a_out,
+            only_b_out,
+            both_out,
+        } => {
+            let a_pdas = merge::read_source_pdas(&a)
+                .wrap_err_with(|| format!("failed to read sources in {}", a.display()))
+                .or_exit(Failure::Merge)?;
+            let b_pdas = merge::read_source_pdas(&b)
+                .wrap_err_with(|| format!("failed to read sources in {}", b.display()))
+                .or_exit(Failure::Merge)?;
+
+            let mut only_a: Vec<&Address> = a_pdas.difference(&b_pdas).collect();
+            let mut only_b: Vec<&Address> = b_pdas.difference(&a_pdas).collect();
+            let mut both: Vec<&Address> = a_pdas.intersection(&b_pdas).collect();
+            println!("only in {}: {}", a.display(), only_a.len());
+            println!("only in {}: {}", b.display(), only_b.len());
+            println!("in both: {}", both.len());
+
+            for (addresses, out) in [
+                (&mut only_a, only_a_out),
+                (&mut only_b, only_b_out),
+                (&mut both, both_out),
+            ] {
+                if let Some(out) = out {
+                    addresses.sort_unstable();
+                    merge::write_address_list(addresses.iter().copied(), &out)
+                        .or_exit(Failure::Merge)?;
+                }
+            }
+        }
+        Command::Verify { source } => {
+            let integrity = merge::verify_sqlite_sources(&source)
+                .wrap_err_with(|| format!("failed to verify sources in {}", source.display()))
+                .or_exit(Failure::Merge)?;
+            println!("rows: {}", integrity.rows);
+            println!(
+                "seed checksum mismatches: {}",
+                integrity.checksum_mismatches
+            );
+            println!("seed count mismatches: {}", integrity.seed_count_mismatches);
+            if !integrity.is_clean() {
+                return Err(RunError::new(
+                    Failure::Merge,
+                    eyre!(
+                        "{} of {} rows in {} disagree with their seed bytes",
+                        integrity.checksum_mismatches + integrity.seed_count_mismatches,
+                        integrity.rows,
+                        source.display()
+                    ),
+                ));
+            }
+        }
+        Command::Analyze {
+            source,
+            top,
+            json_out,
+        } => {
+            let mut entries = merge::read_source_entries(&source)
+                .wrap_err_with(|| format!("failed to read sources in {}", source.display()))
+                .or_exit(Failure::Merge)?;
+            entries.par_sort_unstable_by_key(|entry| entry.pda);
+            entries.dedup_by_key(|entry| entry.pda);
+
+            let profile = ProgramProfile::collect(&entries, top);
+            profile.print_table();
+            if let Some(json_out) = json_out {
+                analyze::write_profile(&profile, &json_out).or_exit(Failure::Merge)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::dedup_store::LocalDedupStore;
+
+    fn args(extra: &[&str]) -> Args {
+        let required = ["uploader", "--token", "token", "--account-id", "account"];
+        Args::try_parse_from(required.iter().chain(extra)).unwrap()
+    }
+
+    fn addresses(indexes: impl IntoIterator<Item = u8>) -> HashSet<Address> {
+        indexes
+            .into_iter()
+            .map(|index| Address::from([index; 32]))
+            .collect()
+    }
+
+    fn entry(index: u8) -> PdaSqlite {
+        PdaSqlite {
+            pda: Address::from([index; 32]),
+            seeds: vec![vec![index]],
+            program_id: Address::from([0xAA; 32]),
+        }
+    }
+
+    /// Writes a collector blob of `entries` at `path`, backdated so the scan treats it as
+    /// settled.
+    fn write_settled_blob(path: &Path, entries: &[PdaSqlite]) {
+        merge::write_blob(entries, path).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(std::time::SystemTime::now() - Duration::from_secs(60))
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn merge_only_moves_resumed_files_before_clearing_the_merge_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input");
+        std::fs::create_dir(&input).unwrap();
+        for index in 1..=2 {
+            write_settled_blob(
+                &input.join(format!("pda_collector_{index}.blob")),
+                &[entry(index)],
+            );
+        }
+        let state = dir.path().join("merge-state");
+        let processed = dir.path().join("processed");
+        let output = dir.path().join("merged.blob");
+        let args = args(&[
+            "--path",
+            input.to_str().unwrap(),
+            "--dedup-hashset-file",
+            dir.path().join("dedup").to_str().unwrap(),
+            "--merge-state",
+            state.to_str().unwrap(),
+            "--processed-dir",
+            processed.to_str().unwrap(),
+        ]);
+        // An interrupted run that parsed every file but never finished.
+        merge::merge(
+            args.path.clone(),
+            args.dedup_hashset_file.clone(),
+            &merge_options(&args).unwrap(),
+            &mut PhaseTimings::default(),
+        )
+        .unwrap();
+
+        merge_only(
+            &args,
+            &output,
+            &mut RunSummary::default(),
+            &mut PhaseTimings::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(merge::from_blob(&output).unwrap().len(), 2);
+        assert_eq!(std::fs::read_dir(&input).unwrap().count(), 0);
+        assert_eq!(std::fs::read_dir(&processed).unwrap().count(), 2);
+        assert!(!state.exists());
+    }
+
+    #[tokio::test]
+    async fn merge_only_filters_against_the_per_database_dedup_hashset() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input");
+        std::fs::create_dir(&input).unwrap();
+        write_settled_blob(&input.join("pda_collector_1.blob"), &[entry(1), entry(2)]);
+        let dedup = dir.path().join("dedup");
+        merge::save_dedup_hashset(&addresses([3]), &dedup, DedupFormat::Bincode).unwrap();
+        merge::save_dedup_hashset(
+            &addresses([1]),
+            &with_suffix(&dedup, ".db-target"),
+            DedupFormat::Bincode,
+        )
+        .unwrap();
+        let output = dir.path().join("merged.blob");
+        let args = args(&[
+            "--path",
+            input.to_str().unwrap(),
+            "--dedup-hashset-file",
+            dedup.to_str().unwrap(),
+            "--skip-kv",
+            "--target-db-id",
+            "target",
+            "--dedup-per-database",
+            "--merge-only",
+            output.to_str().unwrap(),
+        ]);
+
+        run(
+            args,
+            &mut RunSummary::default(),
+            &mut PhaseTimings::default(),
+        )
+        .await
+        .unwrap();
+        let merged = merge::from_blob(&output).unwrap();
+        assert_eq!(
+            merged.iter().map(|entry| entry.pda).collect::<Vec<_>>(),
+            [entry(2).pda]
+        );
+    }
+
+    #[tokio::test]
+    async fn health_preflight_reports_every_unhealthy_database() {
+        use serde_json::json;
+        use wiremock::{
+            Mock, MockServer, ResponseTemplate,
+            matchers::{body_partial_json, method, path},
+        };
+
+        let server = MockServer::start().await;
+        let query_path = |database: &str| format!("/accounts/account/d1/database/{database}/query");
+        let query_response = |rows: serde_json::Value| {
+            ResponseTemplate::new(200).set_body_json(json!({
+                "success": true,
+                "errors": [],
+                "result": [{ "results": rows, "success": true }],
+            }))
+        };
+        let columns = |columns: &[(&str, &str)]| {
+            columns
+                .iter()
+                .map(|(name, decl_type)| json!({ "name": name, "type": decl_type }))
+                .collect::<serde_json::Value>()
+        };
+        for database in ["healthy", "drifted"] {
+            Mock::given(method("POST"))
+                .and(path(query_path(database)))
+                .and(body_partial_json(json!({ "sql": "SELECT 1" })))
+                .respond_with(query_response(json!([{ "1": 1 }])))
+                .mount(&server)
+                .await;
+        }
+        Mock::given(method("POST"))
+            .and(path(query_path("unreachable")))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path(query_path("healthy")))
+            .and(body_partial_json(
+                json!({ "sql": "PRAGMA table_info(pda_registry)" }),
+            ))
+            .respond_with(query_response(columns(&schema::expected_d1_columns(
+                false, false,
+            ))))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path(query_path("drifted")))
+            .and(body_partial_json(
+                json!({ "sql": "PRAGMA table_info(pda_registry)" }),
+            ))
+            .respond_with(query_response(columns(&[
+                ("pda", "BLOB"),
+                ("program_id", "BLOB"),
+            ])))
+            .mount(&server)
+            .await;
+
+        let args = args(&["--path", "/sources"]);
+        let check = |database_ids: &'static [&'static str]| {
+            let api_base = server.uri();
+            let args = &args;
+            async move {
+                check_databases_healthy(
+                    args,
+                    &api_base,
+                    "token",
+                    "account",
+                    database_ids,
+                    &HashMap::new(),
+                )
+                .await
+            }
+        };
+        check(&["healthy"]).await.unwrap();
+        let err = check(&["healthy", "drifted", "unreachable"])
+            .await
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            err,
+            "2 of 3 database(s) are unhealthy: drifted, unreachable"
+        );
+    }
+
+    #[tokio::test]
+    async fn remote_blobs_are_retried_and_capped_at_the_max_file_size() {
+        use wiremock::{Mock, MockServer, ResponseTemplate, matchers::path};
+
+        let server = MockServer::start().await;
+        Mock::given(path("/throttled"))
+            .respond_with(ResponseTemplate::new(503).insert_header("Retry-After", "0"))
+            .up_to_n_times(1)
+            .with_priority(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(path("/throttled"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(vec![1; 10]))
+            .mount(&server)
+            .await;
+        Mock::given(path("/oversized"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(vec![2; 11]))
+            .mount(&server)
+            .await;
+        Mock::given(path("/missing"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+        let url = |name: &str| reqwest::Url::parse(&format!("{}/{name}", server.uri())).unwrap();
+
+        let blobs = download_remote_blobs(&[url("throttled"), url("oversized")], 10)
+            .await
+            .unwrap();
+        assert_eq!(blobs.len(), 1);
+        assert_eq!(
+            (blobs[0].url.as_str(), blobs[0].bytes.as_slice()),
+            (url("throttled").as_str(), [1; 10].as_slice())
+        );
+        assert!(download_remote_blobs(&[url("missing")], 10).await.is_err());
+    }
+
+    #[test]
+    fn stop_after_inactive_hands_its_sources_to_the_secondary_backfill() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input");
+        std::fs::create_dir(&input).unwrap();
+        write_settled_blob(
+            &input.join("pda_collector_1.blob"),
+            &[entry(1), entry(2), entry(3)],
+        );
+        let dedup = dir.path().join("dedup");
+        merge::save_dedup_hashset(&addresses([3]), &dedup, DedupFormat::Bincode).unwrap();
+        let processed = dir.path().join("processed");
+        let run_args = |mode: &str| {
+            args(&[
+                "--path",
+                input.to_str().unwrap(),
+                "--dedup-hashset-file",
+                dedup.to_str().unwrap(),
+                "--processed-dir",
+                processed.to_str().unwrap(),
+                "--blue-db-id",
+                "blue",
+                "--green-db-id",
+                "green",
+                mode,
+            ])
+        };
+        let merge = |args: &Args, dedup_path: &Path| {
+            merge::merge(
+                args.path.clone(),
+                dedup_path.to_owned(),
+                &merge_options(args).unwrap(),
+                &mut PhaseTimings::default(),
+            )
+            .unwrap()
+        };
+        let sources = || std::fs::read_dir(&input).unwrap().count();
+        let pending = pending_secondary_path(&dedup);
+
+        // The paused run keeps the dedup state the secondary matches, advances the main one past
+        // the entries it uploaded to the inactive database, and leaves its sources in place.
+        let paused = run_args("--stop-after-inactive");
+        let merged = merge(&paused, &dedup);
+        assert_eq!(merged.entries.len(), 2);
+        snapshot_secondary_dedup(&paused, &dedup, &pending).unwrap();
+        let mut advanced = merged.dedup_hashset;
+        advanced.extend(merged.entries.iter().map(|entry| entry.pda));
+        merge::save_dedup_hashset(&advanced, &dedup, DedupFormat::Bincode).unwrap();
+        settle_source_files(&paused, &merged.files);
+        assert_eq!(sources(), 1);
+        assert!(!processed.exists());
+
+        // The backfill finds the same entries against the pending state, and even once every
+        // database caught up it never moves the sources.
+        let backfill = run_args("--secondary-only");
+        let merged = merge(&backfill, &pending);
+        let entries = backfill_entries(&dedup, merged.entries).unwrap();
+        assert_eq!(
+            entries.iter().map(|entry| entry.pda).collect::<Vec<_>>(),
+            [entry(1).pda, entry(2).pda]
+        );
+        settle_source_files(&backfill, &merged.files);
+        assert_eq!(sources(), 1);
+        assert!(!processed.exists());
+
+        // A full run afterwards finds nothing new and moves them as usual.
+        let full = args(&[
+            "--path",
+            input.to_str().unwrap(),
+            "--processed-dir",
+            processed.to_str().unwrap(),
+        ]);
+        let merged = merge(&full, &dedup);
+        assert!(merged.entries.is_empty());
+        settle_source_files(&full, &merged.files);
+        assert_eq!(sources(), 0);
+    }
+
+    #[test]
+    fn staged_scripts_always_carry_one_run_id() {
+        let metadata = |extra: &[&str]| {
+            upload_options(&args(&[&["--path", "/sources"], extra].concat())).r2_metadata
+        };
+        let pair = |key: &str, value: &str| (key.to_owned(), value.to_owned());
+        assert!(metadata(&[]).is_empty());
+        assert_eq!(metadata(&["--run-id", "run-1"]), [pair("run-id", "run-1")]);
+        assert_eq!(
+            metadata(&["--run-id", "run-1", "--r2-metadata", "team=infra"]),
+            [pair("run-id", "run-1"), pair("team", "infra")]
+        );
+        assert_eq!(
+            metadata(&[
+                "--run-id",
+                "run-1",
+                "--r2-metadata",
+                "team=infra",
+                "--r2-metadata",
+                "Run-Id=manual",
+            ]),
+            [pair("team", "infra"), pair("run-id", "manual")]
+        );
+    }
+
+    #[tokio::test]
+    async fn skip_kv_uploads_to_the_target_with_its_own_dedup_hashset() {
+        use serde_json::json;
+        use wiremock::{
+            Mock, MockServer, Request, ResponseTemplate,
+            matchers::{body_partial_json, method, path},
+        };
+
+        let server = MockServer::start().await;
+        let import_path = "/accounts/account/d1/database/target/import";
+        let api_response = |result: serde_json::Value| {
+            ResponseTemplate::new(200).set_body_json(json!({
+                "success": true,
+                "errors": [],
+                "result": result,
+            }))
+        };
+        Mock::given(method("POST"))
+            .and(path(import_path))
+            .and(body_partial_json(json!({ "action": "init" })))
+            .respond_with(api_response(json!({
+                "upload_url": format!("{}/r2/chunk", server.uri()),
+                "filename": "chunk.sql",
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path("/r2/chunk"))
+            .respond_with(|request: &Request| {
+                ResponseTemplate::new(200)
+                    .insert_header("ETag", format!("\"{:x}\"", md5::compute(&request.body)))
+            })
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path(import_path))
+            .and(body_partial_json(json!({ "action": "ingest" })))
+            .respond_with(api_response(
+                json!({ "success": true, "status": "complete" }),
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input");
+        std::fs::create_dir(&input).unwrap();
+        write_settled_blob(
+            &input.join("pda_collector_1.blob"),
+            &[entry(1), entry(2), entry(3)],
+        );
+        let dedup = dir.path().join("dedup");
+        merge::save_dedup_hashset(&addresses([1]), &dedup, DedupFormat::Bincode).unwrap();
+        let processed = dir.path().join("processed");
+        let mirror = dir.path().join("mirror.sqlite");
+        let args = args(&[
+            "--path",
+            input.to_str().unwrap(),
+            "--dedup-hashset-file",
+            dedup.to_str().unwrap(),
+            "--processed-dir",
+            processed.to_str().unwrap(),
+            "--output-db-path",
+            mirror.to_str().unwrap(),
+            "--cloudflare-api-base",
+            &server.uri(),
+            "--skip-kv",
+            "--target-db-id",
+            "target",
+            "--dedup-per-database",
+        ]);
+
+        let mut summary = RunSummary::default();
+        run(args, &mut summary, &mut PhaseTimings::default())
+            .await
+            .unwrap();
+        // The shared dedup hashset did not hold back any entry, nor did it learn of them.
+        assert_eq!(summary.new_entries, 3);
+        assert_eq!(merge::load_dedup_hashset(&dedup).unwrap(), addresses([1]));
+        assert_eq!(
+            merge::load_dedup_hashset(&with_suffix(&dedup, ".db-target")).unwrap(),
+            addresses(1..=3)
+        );
+        let mirrored: i64 = rusqlite::Connection::open(&mirror)
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM pda_registry", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(mirrored, 3);
+        assert_eq!(std::fs::read_dir(&input).unwrap().count(), 1);
+        assert!(!processed.exists());
+    }
+
+    #[tokio::test]
+    async fn a_later_shard_still_finds_the_sources_of_an_earlier_one() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input");
+        std::fs::create_dir(&input).unwrap();
+        let entries: Vec<PdaSqlite> = (1..=8).map(entry).collect();
+        write_settled_blob(&input.join("pda_collector_1.blob"), &entries);
+        let processed = dir.path().join("processed");
+        let shard = |index: usize| {
+            let output = dir.path().join(format!("shard-{index}.blob"));
+            let args = args(&[
+                "--path",
+                input.to_str().unwrap(),
+                "--dedup-hashset-file",
+                dir.path().join("dedup").to_str().unwrap(),
+                "--processed-dir",
+                processed.to_str().unwrap(),
+                "--pda-shard",
+                &format!("{index}/2"),
+                "--merge-only",
+                output.to_str().unwrap(),
+            ]);
+            async move {
+                run(
+                    args,
+                    &mut RunSummary::default(),
+                    &mut PhaseTimings::default(),
+                )
+                .await
+                .unwrap();
+                merge::from_blob(&output).unwrap()
+            }
+        };
+
+        let first = shard(0).await;
+        assert_eq!(std::fs::read_dir(&input).unwrap().count(), 1);
+        let second = shard(1).await;
+        let mut pdas: Vec<Address> = first.iter().chain(&second).map(|entry| entry.pda).collect();
+        pdas.sort_unstable();
+        assert_eq!(
+            pdas,
+            entries.iter().map(|entry| entry.pda).collect::<Vec<_>>()
+        );
+        assert!(!processed.exists());
+    }
+
+    #[tokio::test]
+    async fn estimate_needs_neither_credentials_nor_kv() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input");
+        std::fs::create_dir(&input).unwrap();
+        write_settled_blob(&input.join("pda_collector_1.blob"), &[entry(1), entry(2)]);
+        let args = Args::try_parse_from([
+            "uploader",
+            "--path",
+            input.to_str().unwrap(),
+            "--dedup-hashset-file",
+            dir.path().join("dedup").to_str().unwrap(),
+            "--estimate",
+        ])
+        .unwrap();
+
+        let mut summary = RunSummary::default();
+        run(args, &mut summary, &mut PhaseTimings::default())
+            .await
+            .unwrap();
+        assert_eq!(summary.new_entries, 2);
+        assert_eq!(summary.active_db_before, None);
+        assert!(!dir.path().join("dedup").exists());
+    }
+
+    #[test]
+    fn dedup_format_defaults_to_the_existing_set() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dedup");
+        let detected = |extra: &[&str]| {
+            let args = args(&[&["--path", "/sources"], extra].concat());
+            resolve_dedup_format(&args, &path).unwrap()
+        };
+        assert_eq!(detected(&[]), DedupFormat::Bincode);
+
+        merge::save_dedup_hashset(&addresses(1..=2), &path, DedupFormat::Text).unwrap();
+        assert_eq!(detected(&[]), DedupFormat::Text);
+        assert_eq!(
+            detected(&["--dedup-hashset-format", "bincode"]),
+            DedupFormat::Bincode
+        );
+
+        merge::save_dedup_hashset(&addresses(1..=2), &path, DedupFormat::Timestamped).unwrap();
+        assert_eq!(detected(&[]), DedupFormat::Timestamped);
+    }
+
+    #[tokio::test]
+    async fn checkpoints_save_the_dedup_store_or_the_pending_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LocalDedupStore {
+            path: dir.path().join("dedup"),
+        };
+        let pending = pending_secondary_path(&store.path);
+        let args = args(&["--path", "/sources"]);
+
+        checkpoint_dedup_hashset(&args, &store, None, &addresses(1..=2))
+            .await
+            .unwrap();
+        assert_eq!(
+            merge::load_dedup_hashset(&store.path).unwrap(),
+            addresses(1..=2)
+        );
+
+        checkpoint_dedup_hashset(&args, &store, Some(&pending), &addresses(1..=3))
+            .await
+            .unwrap();
+        assert_eq!(
+            merge::load_dedup_hashset(&pending).unwrap(),
+            addresses(1..=3)
+        );
+        assert_eq!(
+            merge::load_dedup_hashset(&store.path).unwrap(),
+            addresses(1..=2)
+        );
+
+        let readonly = self::args(&["--path", "/sources", "--dedup-readonly"]);
+        checkpoint_dedup_hashset(&readonly, &store, None, &addresses(1..=4))
+            .await
+            .unwrap();
+        assert_eq!(
+            merge::load_dedup_hashset(&store.path).unwrap(),
+            addresses(1..=2)
+        );
+    }
+}
@@ -0,0 +1,77 @@
+use aws_config::BehaviorVersion;
+use aws_sdk_s3::{Client, primitives::ByteStream};
+use eyre::{Result, WrapErr};
+use log::info;
+use md5::compute as md5_compute;
+
+use crate::{
+    cloudflare::{UploadOptions, build_insert_script},
+    types::{PdaSqlite, S3PayloadFormat},
+};
+
+/// Builds an S3 client from the standard AWS environment (credentials, region), optionally
+/// pointed at an S3-compatible endpoint.
+pub async fn new_s3_client(endpoint: Option<&str>) -> Client {
+    let config = aws_config::defaults(BehaviorVersion::latest()).load().await;
+    let mut builder = aws_sdk_s3::config::Builder::from(&config);
+    if let Some(endpoint) = endpoint {
+        builder = builder.endpoint_url(endpoint).force_path_style(true);
+    }
+    Client::from_conf(builder.build())
+}
+
+/// Writes one chunk of entries as a single object under `{prefix}/{database_identifier}/`.
+/// Objects are keyed by the md5 of their payload, so retried chunks overwrite themselves.
+pub async fn upload_to_s3(
+    client: &Client,
+    bucket: &str,
+    prefix: &str,
+    database_identifier: &str,
+    entries: &[PdaSqlite],
+    format: S3PayloadFormat,
+    options: &UploadOptions,
+) -> Result<()> {
+    if entries.is_empty() {
+        info!("Skip S3 upload for database {database_identifier}: no new entries");
+        return Ok(());
+    }
+
+    let (payload, extension) = match format {
+        S3PayloadFormat::Sql => match build_insert_script(entries, options)? {
+            Some(script) => (script.into_bytes(), "sql"),
+            None => {
+                info!("Skip S3 upload for database {database_identifier}: nothing to insert");
+                return Ok(());
+            }
+        },
+        S3PayloadFormat::Bincode => (
+            bincode::serialize(entries).wrap_err("failed to serialize entries for S3")?,
+            "bincode",
+        ),
+    };
+
+    let checksum = format!("{:x}", md5_compute(&payload));
+    let prefix = prefix.trim_matches('/');
+    let key = if prefix.is_empty() {
+        format!("{database_identifier}/{checksum}.{extension}")
+    } else {
+        format!("{prefix}/{database_identifier}/{checksum}.{extension}")
+    };
+
+    info!(
+        "Uploading {} entries ({} bytes) to s3://{bucket}/{key}",
+        entries.len(),
+        payload.len()
+    );
+
+    client
+        .put_object()
+        .bucket(bucket)
+        .key(&key)
+        .body(ByteStream::from(payload))
+        .send()
+        .await
+        .wrap_err_with(|| format!("failed to upload s3://{bucket}/{key}"))?;
+
+    Ok(())
+}
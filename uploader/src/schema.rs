@@ -0,0 +1,153 @@
+use std::path::Path;
+
+use eyre::{Result, WrapErr, eyre};
+
+/// A column as reported by `PRAGMA table_info`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Column {
+    pub name: String,
+    pub decl_type: String,
+}
+
+/// Value of the `seed_checksum` column for a row's `seed_bytes`.
+pub fn seed_checksum(seed_bytes: &[u8]) -> u32 {
+    crc32fast::hash(seed_bytes)
+}
+
+/// Columns the uploader writes to the D1 table.
+pub fn expected_d1_columns(
+    with_seeds_json: bool,
+    with_checksums: bool,
+) -> Vec<(&'static str, &'static str)> {
+    let mut columns = vec![
+        ("pda", "BLOB"),
+        ("program_id", "BLOB"),
+        ("seed_count", "INTEGER"),
+        ("seed_bytes", "BLOB"),
+    ];
+    if with_seeds_json {
+        columns.push(("seeds_json", "TEXT"));
+    }
+    if with_checksums {
+        columns.push(("seed_checksum", "INTEGER"));
+    }
+    columns
+}
+
+/// Columns `--create-indexes` indexes in the D1 table, for analysts filtering on them.
+pub const INDEXED_COLUMNS: &[&str] = &["seed_count", "program_id"];
+
+/// `CREATE INDEX` statements for [`INDEXED_COLUMNS`] of `table`. Index names include the table,
+/// since `--db-table` can put several tables in one database.
+pub fn index_statements(table: &str) -> Vec<String> {
+    INDEXED_COLUMNS
+        .iter()
+        .map(|column| {
+            format!("CREATE INDEX IF NOT EXISTS idx_{table}_{column} ON {table}({column})")
+        })
+        .collect()
+}
+
+/// Columns `from_sqlite` reads from collector sqlite files.
+pub fn expected_source_columns() -> Vec<(&'static str, &'static str)> {
+    vec![
+        ("pda", "BLOB"),
+        ("program_id", "BLOB"),
+        ("seed_bytes", "BLOB"),
+    ]
+}
+
+pub fn sqlite_columns(path: &Path, table: &str) -> Result<Vec<Column>> {
+    let conn = rusqlite::Connection::open(path)
+        .wrap_err_with(|| format!("failed to open sqlite file {}", path.display()))?;
+    let mut stmt = conn
+        .prepare(&format!("PRAGMA table_info({table})"))
+        .wrap_err_with(|| format!("failed to read schema of {}", path.display()))?;
+    let columns = stmt
+        .query_map([], |row| {
+            Ok(Column {
+                name: row.get("name")?,
+                decl_type: row.get("type")?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .wrap_err_with(|| format!("failed to read schema of {}", path.display()))?;
+    Ok(columns)
+}
+
+/// Lists every expected column that is missing from `actual` or declared with another type.
+pub fn schema_problems(expected: &[(&str, &str)], actual: &[Column]) -> Vec<String> {
+    let mut problems = Vec::new();
+    for (name, decl_type) in expected {
+        match actual.iter().find(|column| column.name == *name) {
+            None => problems.push(format!("missing column {name} {decl_type}")),
+            Some(column) if !column.decl_type.eq_ignore_ascii_case(decl_type) => {
+                problems.push(format!(
+                    "column {name} is {}, expected {decl_type}",
+                    column.decl_type
+                ))
+            }
+            Some(_) => {}
+        }
+    }
+    problems
+}
+
+/// Columns present in `source` but absent from `target`.
+pub fn extra_columns<'a>(source: &'a [Column], target: &[Column]) -> Vec<&'a str> {
+    source
+        .iter()
+        .filter(|column| !target.iter().any(|other| other.name == column.name))
+        .map(|column| column.name.as_str())
+        .collect()
+}
+
+pub fn ensure_no_drift(label: &str, expected: &[(&str, &str)], actual: &[Column]) -> Result<()> {
+    let problems = schema_problems(expected, actual);
+    if problems.is_empty() {
+        return Ok(());
+    }
+
+    Err(eyre!(
+        "schema drift in {label}:\n  {}",
+        problems.join("\n  ")
+    ))
+}
+
+/// `CREATE TABLE IF NOT EXISTS` for `columns`, keyed by `pda` like `init.sql`.
+pub fn create_table_statement(table: &str, columns: &[(&str, &str)]) -> String {
+    let columns = columns
+        .iter()
+        .map(|&(name, decl_type)| match name {
+            "pda" => format!("{name} {decl_type} PRIMARY KEY"),
+            "program_id" | "seed_count" | "seed_bytes" => format!("{name} {decl_type} NOT NULL"),
+            _ => format!("{name} {decl_type}"),
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!("CREATE TABLE IF NOT EXISTS {table} ({columns}) WITHOUT ROWID;")
+}
+
+/// Runs insert scripts against a fresh in-memory sqlite table with the D1 schema, so SQL the
+/// uploader generates is proven to parse and execute before it reaches D1. Fails on the first
+/// statement sqlite rejects and returns the number of rows the table ends up with.
+pub fn execute_in_memory(
+    table: &str,
+    columns: &[(&str, &str)],
+    scripts: impl IntoIterator<Item = String>,
+) -> Result<u64> {
+    let conn = rusqlite::Connection::open_in_memory()
+        .wrap_err("failed to open in-memory sqlite database")?;
+    conn.execute_batch(&create_table_statement(table, columns))
+        .wrap_err("failed to create in-memory table")?;
+    for (index, script) in scripts.into_iter().enumerate() {
+        conn.execute_batch(&script)
+            .wrap_err_with(|| format!("script {} failed to execute", index + 1))?;
+    }
+    let rows = conn
+        .query_row(&format!("SELECT count(*) FROM {table}"), [], |row| {
+            row.get::<_, i64>(0)
+        })
+        .wrap_err("failed to count in-memory rows")?;
+    Ok(rows as u64)
+}
@@ -0,0 +1,234 @@
+use log::{info, warn};
+use rayon::prelude::*;
+
+use crate::types::PdaSqlite;
+
+/// Most seeds `create_program_address` accepts, bump included.
+pub const MAX_SEEDS: usize = 16;
+/// Longest single seed `create_program_address` accepts.
+pub const MAX_SEED_LEN: usize = 32;
+
+/// Inclusive upper bounds of the seed-count buckets; larger counts land in a final bucket.
+const SEED_COUNT_BOUNDS: &[usize] = &[0, 1, 2, 3, 4, 8, MAX_SEEDS];
+/// Inclusive upper bounds of the seed-length buckets; longer seeds land in a final bucket.
+const SEED_LEN_BOUNDS: &[usize] = &[0, 8, 16, MAX_SEED_LEN, 64, 256];
+
+/// Counts of values falling into fixed, inclusive ranges.
+#[derive(Debug, Clone)]
+struct Histogram {
+    upper_bounds: &'static [usize],
+    counts: Vec<u64>,
+    max: usize,
+}
+
+impl Histogram {
+    fn new(upper_bounds: &'static [usize]) -> Self {
+        Self {
+            upper_bounds,
+            counts: vec![0; upper_bounds.len() + 1],
+            max: 0,
+        }
+    }
+
+    fn record(&mut self, value: usize) {
+        let bucket = self.upper_bounds.partition_point(|&bound| bound < value);
+        self.counts[bucket] += 1;
+        self.max = self.max.max(value);
+    }
+
+    fn merge(mut self, other: Self) -> Self {
+        for (count, other) in self.counts.iter_mut().zip(other.counts) {
+            *count += other;
+        }
+        self.max = self.max.max(other.max);
+        self
+    }
+
+    /// `(label, count)` for every bucket, e.g. `("5-8", 12)` or `(">16", 3)`.
+    fn buckets(&self) -> impl Iterator<Item = (String, u64)> + '_ {
+        let lowers = std::iter::once(0).chain(self.upper_bounds.iter().map(|bound| bound + 1));
+        let labels = lowers.zip(self.upper_bounds).map(|(lower, &upper)| {
+            if lower == upper {
+                upper.to_string()
+            } else {
+                format!("{lower}-{upper}")
+            }
+        });
+        let overflow = format!(">{}", self.upper_bounds.last().copied().unwrap_or(0));
+        labels
+            .chain(std::iter::once(overflow))
+            .zip(self.counts.iter().copied())
+    }
+
+    fn log(&self, title: &str) {
+        let total: u64 = self.counts.iter().sum();
+        info!("{title} (max {}):", self.max);
+        for (label, count) in self.buckets() {
+            let share = if total > 0 {
+                count as f64 / total as f64 * 100.0
+            } else {
+                0.0
+            };
+            info!("  {label:>8}  {count:>12}  {share:>5.1}%");
+        }
+    }
+}
+
+/// Seed count and seed size distribution over a set of entries, used to gauge how many
+/// entries exceed Solana's seed limits before anything enforces them.
+#[derive(Debug, Clone)]
+pub struct SeedStats {
+    entries: u64,
+    /// Entries with more than [`MAX_SEEDS`] seeds.
+    too_many_seeds: u64,
+    /// Entries with at least one seed longer than [`MAX_SEED_LEN`].
+    with_oversized_seed: u64,
+    /// Individual seeds longer than [`MAX_SEED_LEN`].
+    oversized_seeds: u64,
+    seed_counts: Histogram,
+    seed_lens: Histogram,
+}
+
+impl Default for SeedStats {
+    fn default() -> Self {
+        Self {
+            entries: 0,
+            too_many_seeds: 0,
+            with_oversized_seed: 0,
+            oversized_seeds: 0,
+            seed_counts: Histogram::new(SEED_COUNT_BOUNDS),
+            seed_lens: Histogram::new(SEED_LEN_BOUNDS),
+        }
+    }
+}
+
+impl SeedStats {
+    pub fn collect(entries: &[PdaSqlite]) -> Self {
+        entries
+            .par_iter()
+            .fold(Self::default, |mut stats, entry| {
+                stats.record(entry);
+                stats
+            })
+            .reduce(Self::default, Self::merge)
+    }
+
+    fn record(&mut self, entry: &PdaSqlite) {
+        self.entries += 1;
+        self.seed_counts.record(entry.seeds.len());
+        if entry.seeds.len() > MAX_SEEDS {
+            self.too_many_seeds += 1;
+        }
+
+        let mut oversized = 0;
+        for seed in &entry.seeds {
+            self.seed_lens.record(seed.len());
+            if seed.len() > MAX_SEED_LEN {
+                oversized += 1;
+            }
+        }
+        if oversized > 0 {
+            self.with_oversized_seed += 1;
+            self.oversized_seeds += oversized;
+        }
+    }
+
+    fn merge(self, other: Self) -> Self {
+        Self {
+            entries: self.entries + other.entries,
+            too_many_seeds: self.too_many_seeds + other.too_many_seeds,
+            with_oversized_seed: self.with_oversized_seed + other.with_oversized_seed,
+            oversized_seeds: self.oversized_seeds + other.oversized_seeds,
+            seed_counts: self.seed_counts.merge(other.seed_counts),
+            seed_lens: self.seed_lens.merge(other.seed_lens),
+        }
+    }
+
+    /// Logs both histograms, then warns about entries beyond the seed limits.
+    pub fn log(&self) {
+        info!("Seed statistics over {} entries", self.entries);
+        self.seed_counts.log("Seeds per entry");
+        self.seed_lens.log("Seed length in bytes");
+
+        if self.too_many_seeds > 0 {
+            warn!(
+                "{} entries have more than {MAX_SEEDS} seeds",
+                self.too_many_seeds
+            );
+        }
+        if self.with_oversized_seed > 0 {
+            warn!(
+                "{} entries have seeds longer than {MAX_SEED_LEN} bytes ({} oversized seeds)",
+                self.with_oversized_seed, self.oversized_seeds
+            );
+        }
+        if self.too_many_seeds == 0 && self.with_oversized_seed == 0 {
+            info!("All entries are within the {MAX_SEEDS}-seed and {MAX_SEED_LEN}-byte limits");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_address::Address;
+
+    use super::*;
+
+    fn entry(seed_lens: &[usize]) -> PdaSqlite {
+        PdaSqlite {
+            pda: Address::from([1; 32]),
+            seeds: seed_lens.iter().map(|&len| vec![0; len]).collect(),
+            program_id: Address::from([2; 32]),
+        }
+    }
+
+    #[test]
+    fn seeds_land_in_their_buckets_and_limits_are_counted() {
+        let entries = vec![
+            entry(&[]),
+            entry(&[32]),
+            entry(&[5, 33]),
+            entry(&[1; 17]),
+            entry(&[300, 300]),
+        ];
+        let stats = SeedStats::collect(&entries);
+
+        assert_eq!(stats.entries, 5);
+        assert_eq!(stats.too_many_seeds, 1);
+        assert_eq!(stats.with_oversized_seed, 2);
+        assert_eq!(stats.oversized_seeds, 3);
+
+        let seed_counts: Vec<(String, u64)> = stats.seed_counts.buckets().collect();
+        assert_eq!(
+            seed_counts,
+            [
+                ("0", 1),
+                ("1", 1),
+                ("2", 2),
+                ("3", 0),
+                ("4", 0),
+                ("5-8", 0),
+                ("9-16", 0),
+                (">16", 1),
+            ]
+            .map(|(label, count)| (label.to_owned(), count))
+        );
+        assert_eq!(stats.seed_counts.max, 17);
+
+        let seed_lens: Vec<(String, u64)> = stats.seed_lens.buckets().collect();
+        assert_eq!(
+            seed_lens,
+            [
+                ("0", 0),
+                ("1-8", 18),
+                ("9-16", 0),
+                ("17-32", 1),
+                ("33-64", 1),
+                ("65-256", 0),
+                (">256", 2),
+            ]
+            .map(|(label, count)| (label.to_owned(), count))
+        );
+        assert_eq!(stats.seed_lens.max, 300);
+    }
+}
@@ -0,0 +1,272 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufWriter, Write},
+    path::Path,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use eyre::{Result, WrapErr};
+use log::info;
+use serde::Serialize;
+
+use crate::{cloudflare::ImportReport, types::PdaSqlite};
+
+/// Wall-clock time spent in one pipeline phase.
+#[derive(Debug, Clone, Serialize)]
+pub struct PhaseTiming {
+    pub phase: String,
+    pub secs: f64,
+}
+
+/// Phase timings in the order the phases ran. Recording is cheap, so the pipeline always
+/// records; `--profile` only controls whether the breakdown is reported.
+#[derive(Debug, Default)]
+pub struct PhaseTimings {
+    phases: Vec<PhaseTiming>,
+}
+
+impl PhaseTimings {
+    pub fn record(&mut self, phase: impl Into<String>, elapsed: Duration) {
+        self.phases.push(PhaseTiming {
+            phase: phase.into(),
+            secs: elapsed.as_secs_f64(),
+        });
+    }
+
+    /// Runs `f` and records its wall-clock time under `phase`.
+    pub fn time<T>(&mut self, phase: impl Into<String>, f: impl FnOnce() -> T) -> T {
+        let started = Instant::now();
+        let output = f();
+        self.record(phase, started.elapsed());
+        output
+    }
+
+    pub fn phases(&self) -> &[PhaseTiming] {
+        &self.phases
+    }
+
+    /// Appends the phases of `other`, recorded separately, e.g. on another thread.
+    pub fn extend(&mut self, other: PhaseTimings) {
+        self.phases.extend(other.phases);
+    }
+
+    /// Logs the breakdown as an aligned table, with each phase's share of the recorded total.
+    pub fn log_table(&self) {
+        let total: f64 = self.phases.iter().map(|timing| timing.secs).sum();
+        let width = self
+            .phases
+            .iter()
+            .map(|timing| timing.phase.len())
+            .max()
+            .unwrap_or(0)
+            .max("phase".len());
+
+        info!("Phase timings:");
+        info!("{:<width$}  {:>10}  {:>6}", "phase", "seconds", "share");
+        for timing in &self.phases {
+            let share = if total > 0.0 {
+                timing.secs / total * 100.0
+            } else {
+                0.0
+            };
+            info!(
+                "{:<width$}  {:>10.3}  {:>5.1}%",
+                timing.phase, timing.secs, share
+            );
+        }
+        info!("{:<width$}  {:>10.3}", "total", total);
+    }
+}
+
+/// Machine-readable outcome of a run, written with `--summary-json`.
+#[derive(Debug, Default, Serialize)]
+pub struct RunSummary {
+    /// The `--run-id` every log line of the run carries.
+    pub run_id: String,
+    pub exit_code: u8,
+    pub error: Option<String>,
+    pub active_db_before: Option<String>,
+    pub active_db_after: Option<String>,
+    pub source_files: usize,
+    pub new_entries: usize,
+    pub failed_targets: Vec<String>,
+    /// Statements and rows D1 reported as failed within otherwise successful imports.
+    pub failed_statements: usize,
+    pub failed_rows: usize,
+    /// Bytes uploaded to R2 across every D1 import.
+    pub uploaded_bytes: u64,
+    /// Present only with `--rent-lamports-per-byte-year`.
+    pub rent: Option<RentEstimate>,
+    /// Present only with `--profile`.
+    pub phases: Option<Vec<PhaseTiming>>,
+}
+
+impl RunSummary {
+    /// Adds the outcome of one successful upload to the totals.
+    pub fn record_import(&mut self, report: &ImportReport) {
+        self.failed_statements += report.failed_statements;
+        self.failed_rows += report.failed_rows;
+        self.uploaded_bytes += report.uploaded_bytes;
+    }
+}
+
+/// Rough on-chain storage cost of the accounts behind a run's new entries, taking each
+/// account to hold a fixed base plus its seeds.
+#[derive(Debug, Clone, Serialize)]
+pub struct RentEstimate {
+    pub accounts: usize,
+    pub account_bytes: u64,
+    pub lamports_per_byte_year: u64,
+    pub lamports_per_year: u64,
+    /// Lamports to keep every account rent-exempt, which takes two years of rent.
+    pub rent_exempt_lamports: u64,
+}
+
+impl RentEstimate {
+    /// Years of rent an account must hold to be rent-exempt.
+    const EXEMPTION_YEARS: u64 = 2;
+
+    pub fn collect(
+        entries: &[PdaSqlite],
+        account_base_bytes: u64,
+        lamports_per_byte_year: u64,
+    ) -> Self {
+        let account_bytes = entries
+            .iter()
+            .map(|entry| {
+                let seed_bytes: usize = entry.seeds.iter().map(Vec::len).sum();
+                account_base_bytes.saturating_add(seed_bytes as u64)
+            })
+            .fold(0u64, u64::saturating_add);
+        let lamports_per_year = account_bytes.saturating_mul(lamports_per_byte_year);
+        let estimate = Self {
+            accounts: entries.len(),
+            account_bytes,
+            lamports_per_byte_year,
+            lamports_per_year,
+            rent_exempt_lamports: lamports_per_year.saturating_mul(Self::EXEMPTION_YEARS),
+        };
+        info!(
+            "Estimated rent footprint of {} new accounts: {account_bytes} bytes, {lamports_per_year} lamports/year, {} lamports to be rent-exempt",
+            estimate.accounts, estimate.rent_exempt_lamports
+        );
+        estimate
+    }
+}
+
+/// A random version 4 UUID naming a run started without `--run-id`.
+pub fn new_run_id() -> String {
+    let mut bytes: [u8; 16] = rand::random();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = hex::encode(bytes);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+pub fn write_summary(summary: &RunSummary, path: &Path) -> Result<()> {
+    let file = File::create(path)
+        .wrap_err_with(|| format!("failed to create summary file {}", path.display()))?;
+    let mut writer = BufWriter::new(file);
+    serde_json::to_writer_pretty(&mut writer, summary)
+        .wrap_err_with(|| format!("failed to write summary file {}", path.display()))?;
+    writeln!(writer)?;
+    writer.flush()?;
+    info!("Wrote run summary to {}", path.display());
+    Ok(())
+}
+
+/// D1 time-travel bookmark left by one run's import into one database, appended to
+/// `--bookmark-out` as a JSON line.
+#[derive(Debug, Serialize)]
+pub struct BookmarkRecord<'a> {
+    pub database_id: &'a str,
+    pub label: &'a str,
+    pub bookmark: &'a str,
+    /// Unix seconds when the import completed.
+    pub recorded_at: u64,
+    /// Entries the run uploaded to the database.
+    pub entries: usize,
+}
+
+impl<'a> BookmarkRecord<'a> {
+    pub fn now(database_id: &'a str, label: &'a str, bookmark: &'a str, entries: usize) -> Self {
+        Self {
+            database_id,
+            label,
+            bookmark,
+            recorded_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            entries,
+        }
+    }
+}
+
+/// Appends `record` to `path`, so the file keeps the restore point of every run.
+pub fn append_bookmark(record: &BookmarkRecord, path: &Path) -> Result<()> {
+    let mut line = serde_json::to_string(record).wrap_err("failed to encode bookmark")?;
+    line.push('\n');
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(line.as_bytes()))
+        .wrap_err_with(|| format!("failed to append bookmark to {}", path.display()))?;
+    info!(
+        "Recorded bookmark {} for {} database {} in {}",
+        record.bookmark,
+        record.label,
+        record.database_id,
+        path.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_address::Address;
+
+    use super::*;
+
+    fn entry(seeds: &[usize]) -> PdaSqlite {
+        PdaSqlite {
+            pda: Address::from([1; 32]),
+            seeds: seeds.iter().map(|&len| vec![0; len]).collect(),
+            program_id: Address::from([0xAA; 32]),
+        }
+    }
+
+    #[test]
+    fn rent_covers_the_base_and_seeds_of_every_account() {
+        let estimate = RentEstimate::collect(&[entry(&[3, 2]), entry(&[1])], 128, 3480);
+        assert_eq!(estimate.accounts, 2);
+        assert_eq!(estimate.account_bytes, (128 + 5) + (128 + 1));
+        assert_eq!(estimate.lamports_per_year, 262 * 3480);
+        assert_eq!(estimate.rent_exempt_lamports, 2 * 262 * 3480);
+
+        let empty = RentEstimate::collect(&[], 128, 3480);
+        assert_eq!((empty.accounts, empty.rent_exempt_lamports), (0, 0));
+    }
+
+    #[test]
+    fn rent_saturates_instead_of_overflowing() {
+        let estimate = RentEstimate::collect(&[entry(&[1]), entry(&[1])], u64::MAX - 1, 1);
+        assert_eq!(estimate.account_bytes, u64::MAX);
+        assert_eq!(estimate.rent_exempt_lamports, u64::MAX);
+
+        let estimate = RentEstimate::collect(&[entry(&[1])], 1, u64::MAX / 2);
+        assert_eq!(estimate.lamports_per_year, u64::MAX - 1);
+        assert_eq!(estimate.rent_exempt_lamports, u64::MAX);
+
+        let estimate = RentEstimate::collect(&[entry(&[1])], 1, u64::MAX);
+        assert_eq!(estimate.lamports_per_year, u64::MAX);
+    }
+}
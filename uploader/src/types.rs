@@ -1,9 +1,25 @@
-use std::path::PathBuf;
+use std::{
+    ffi::OsString,
+    fmt,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
+use eyre::{WrapErr, eyre};
+use reqwest::{
+    Url,
+    header::{HeaderName, HeaderValue},
+};
 use serde::{Deserialize, Serialize};
 use solana_address::Address;
 
+use crate::{
+    cloudflare::{CLOUDFLARE_API_BASE, DEFAULT_ENTRIES_PER_STATEMENT, DEFAULT_TABLE_NAME},
+    merge::DEFAULT_SQLITE_OPEN_RETRIES,
+};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[repr(C)]
 pub struct PdaSqlite {
@@ -12,23 +28,184 @@ pub struct PdaSqlite {
     pub program_id: Address,
 }
 
+/// Human-editable form of a [`PdaSqlite`]: base58 addresses and hex-encoded seeds. Addresses
+/// read back may also be hex, see [`parse_address`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PdaJson {
+    pub pda: String,
+    pub program_id: String,
+    pub seeds: Vec<String>,
+}
+
+/// One row of a CSV export: base58 addresses and `;`-separated hex seeds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PdaCsvRow {
+    pub pda: String,
+    pub program_id: String,
+    pub seeds: String,
+}
+
+impl From<PdaCsvRow> for PdaJson {
+    fn from(row: PdaCsvRow) -> Self {
+        Self {
+            pda: row.pda,
+            program_id: row.program_id,
+            seeds: row
+                .seeds
+                .split(';')
+                .filter(|seed| !seed.is_empty())
+                .map(str::to_owned)
+                .collect(),
+        }
+    }
+}
+
+impl From<&PdaSqlite> for PdaJson {
+    fn from(entry: &PdaSqlite) -> Self {
+        Self {
+            pda: entry.pda.to_string(),
+            program_id: entry.program_id.to_string(),
+            seeds: entry.seeds.iter().map(hex::encode).collect(),
+        }
+    }
+}
+
+impl TryFrom<PdaJson> for PdaSqlite {
+    type Error = eyre::Report;
+
+    fn try_from(entry: PdaJson) -> eyre::Result<Self> {
+        let seeds = entry
+            .seeds
+            .iter()
+            .map(|seed| hex::decode(seed).wrap_err_with(|| format!("invalid hex seed {seed:?}")))
+            .collect::<eyre::Result<Vec<_>>>()?;
+
+        Ok(Self {
+            pda: parse_address(&entry.pda).wrap_err("invalid pda")?,
+            program_id: parse_address(&entry.program_id).wrap_err("invalid program_id")?,
+            seeds,
+        })
+    }
+}
+
 #[derive(Debug, Clone, Parser)]
+#[command(subcommand_negates_reqs = true)]
 pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// Path to the directory containing hashmaps
-    #[arg(short, long)]
-    pub path: PathBuf,
+    #[arg(short, long, required_unless_present_any = ["pda_list_in", "urls"])]
+    pub path: Option<PathBuf>,
+
+    /// Download a collector blob from this http(s) URL and merge it alongside the scanned
+    /// directory. Repeatable. A URL whose path ends in `.zst` is decompressed; downloads are
+    /// not tracked by --merge-state or moved by --processed-dir
+    #[arg(long = "url", value_name = "URL", value_parser = parse_http_url)]
+    pub urls: Vec<Url>,
+
+    /// JSON array, or JSON lines, of entries (`{"pda", "program_id", "seeds": [hex]}`) to
+    /// upload alongside, or instead of, the scanned directory. Listed entries are uploaded even
+    /// if the dedup hashset holds their pda. Accepts a --dead-letter file
+    #[arg(long)]
+    pub pda_list_in: Option<PathBuf>,
+
+    /// Append every rejected entry to this file as a JSON line with a `reason`: entries
+    /// dropped by --require-off-curve, --verify-pda or --skip-unserializable, and the chunks of
+    /// an upload that failed or reported failed rows. Re-process it with --pda-list-in
+    #[arg(long)]
+    pub dead_letter: Option<PathBuf>,
+
+    /// Drop entries whose seeds cannot be rendered into the upload's SQL, logging them and
+    /// recording them in --dead-letter, instead of failing their whole chunk. They are dropped
+    /// before --new-entries-out, --export-parquet and --program-index-out are written
+    #[arg(long)]
+    pub skip_unserializable: bool,
+
+    /// Skip (with a warning) any blob or sqlite file, --url downloads included, larger than this
+    /// many bytes before parsing
+    #[arg(long, default_value_t = 16 << 30)]
+    pub max_file_size: u64,
+
+    /// Skip (with a warning) blob files whose names are not valid UTF-8. By default their names
+    /// are matched as raw bytes and they are parsed like any other
+    #[arg(long)]
+    pub strict_utf8: bool,
 
-    /// Path of existing dedup hashset
+    /// Skip blob files whose mtime is more than 5s in the future. By default they are blamed
+    /// on clock skew (e.g. an NFS server running ahead), warned about, and parsed
+    #[arg(long)]
+    pub skip_future_mtime: bool,
+
+    /// Retry opening a sqlite file this many times, with exponential backoff, while it reports
+    /// busy or locked (common on NFS and SMB mounts). Other open errors fail immediately
+    #[arg(long, default_value_t = DEFAULT_SQLITE_OPEN_RETRIES)]
+    pub sqlite_open_retries: u32,
+
+    /// Parse every regular file under --path with this format regardless of its name; `auto`
+    /// keeps the name-based detection. Files still being written (fresh, or next to a `.lock`
+    /// or `.partial` marker) are skipped either way
+    #[arg(long, value_enum, default_value_t = InputFormat::Auto)]
+    pub input_format: InputFormat,
+
+    /// Path of existing dedup hashset, or an `s3://bucket/key` URI (with `--s3-endpoint` for
+    /// R2) that is downloaded before the merge and replaced after the save
     #[arg(short, long, default_value = "/tmp/dedup")]
     pub dedup_hashset_file: PathBuf,
 
-    /// Cloudflare token
-    #[arg(short, long)]
-    pub token: String,
+    /// Format the dedup hashset is saved in. Defaults to the format of the existing set, so a
+    /// run never converts it unasked, and to bincode for a new set. Loading detects every
+    /// format on its own
+    #[arg(long, value_enum)]
+    pub dedup_hashset_format: Option<DedupFormat>,
 
-    /// Cloudflare account id
-    #[arg(short, long)]
-    pub account_id: String,
+    /// How durably the dedup hashset is written. `none` and `rename-only` save seconds on slow
+    /// disks at the risk of a truncated or stale set after a power loss
+    #[arg(long, value_enum, default_value_t = FsyncMode::Full)]
+    pub fsync_mode: FsyncMode,
+
+    /// Upload again the pdas added to the dedup hashset within this many seconds, e.g. to
+    /// re-push entries that may not have landed during a D1 incident. Requires
+    /// `--dedup-hashset-format timestamped`
+    #[arg(long)]
+    pub dedup_lookback_secs: Option<u64>,
+
+    /// Expected number of addresses in a text-format dedup hashset, to size the set before
+    /// loading it instead of growing it; bincode sets record their own length
+    #[arg(long)]
+    pub dedup_hashset_capacity: Option<usize>,
+
+    /// Load the dedup hashset on another thread while scanning the input directory, instead of
+    /// before the scan, to cut startup time with a large set
+    #[arg(long)]
+    pub dedup_hashset_readahead: bool,
+
+    /// Load the dedup hashset for deduplication but never write it back, checkpoints included
+    #[arg(long)]
+    pub dedup_readonly: bool,
+
+    /// Dedup through a bloom filter built from the persisted hashset instead of loading the
+    /// whole set; bloom positives are confirmed exactly against a streamed pass over the set.
+    /// The filter is persisted next to the hashset as `<file>.bloom` and reused across runs
+    #[arg(long)]
+    pub dedup_bloom: bool,
+
+    /// Size of the dedup bloom filter in bits
+    #[arg(long, default_value_t = 1 << 30)]
+    pub dedup_bloom_bits: u64,
+
+    /// Cloudflare token. Not needed with --merge-only or --estimate, which never talk to
+    /// Cloudflare
+    #[arg(short, long, required_unless_present_any = ["merge_only", "estimate"])]
+    pub token: Option<String>,
+
+    /// Cloudflare account id. Not needed with --merge-only or --estimate
+    #[arg(short, long, required_unless_present_any = ["merge_only", "estimate"])]
+    pub account_id: Option<String>,
+
+    /// Cloudflare API base URL, for routing D1 and KV calls through an API gateway or proxy
+    #[arg(long, default_value = CLOUDFLARE_API_BASE)]
+    pub cloudflare_api_base: String,
 
     /// Blue D1 database id
     #[arg(long, default_value = "e0d3e70f-8b45-4906-865f-cc54ac1ae3bb")]
@@ -37,4 +214,784 @@ pub struct Args {
     /// Green D1 database id
     #[arg(long, default_value = "b174381a-dfee-4d35-a6e0-8a18a23c7092")]
     pub green_db_id: Option<String>,
+
+    /// Toggle `ACTIVE_DB` without asking. Interactive runs (stdin is a terminal) otherwise stop
+    /// for a y/N confirmation of the toggle before Step 1 uploads anything; non-interactive runs
+    /// never prompt
+    #[arg(long, short = 'y')]
+    pub yes: bool,
+
+    /// Break-glass: treat this database as active without reading `ACTIVE_DB` from KV. The
+    /// Step 2 toggle still writes KV, restoring it to a consistent state
+    #[arg(long, value_enum)]
+    pub force_active_db: Option<DbLabel>,
+
+    /// Write the base58 pdas of the new entries (after dedup) to this file, one per line
+    #[arg(long)]
+    pub new_entries_out: Option<PathBuf>,
+
+    /// Also write the new entries (after dedup) to this Parquet file for the data lake
+    #[arg(long)]
+    pub export_parquet: Option<PathBuf>,
+
+    /// Move the parsed blob and csv files into this directory once the run succeeds, named
+    /// after a hash of their full path so files sharing a basename never overwrite each other
+    #[arg(long)]
+    pub processed_dir: Option<PathBuf>,
+
+    /// Also insert each chunk into this local sqlite database once its upload to the inactive
+    /// database succeeds, creating the table if needed, for an offline queryable copy
+    #[arg(long)]
+    pub output_db_path: Option<PathBuf>,
+
+    /// Append each database's post-import D1 time-travel bookmark to this file as a JSON line,
+    /// with a timestamp and the run's entry count, to later restore D1 to the state after a run
+    #[arg(long)]
+    pub bookmark_out: Option<PathBuf>,
+
+    /// Additional replica database ids updated after the secondary in Step 3 (repeatable)
+    #[arg(long = "replica-db-id")]
+    pub replica_db_ids: Vec<String>,
+
+    /// Hotfix mode: upload to the inactive database and toggle, skipping Step 3 (secondary and
+    /// replicas) and leaving the dedup hashset and source files untouched. Unlike a run that
+    /// merely skips the secondary, nothing is recorded as uploaded, so the next normal run
+    /// uploads the same entries again and catches the secondary up via `INSERT OR IGNORE`
+    #[arg(long)]
+    pub trim_to_active_only: bool,
+
+    /// Canary mode: upload to the inactive database, toggle, save the dedup hashset and exit 0
+    /// before Step 3, printing a JSON resume token on stdout. The dedup hashset as it was
+    /// before the run, which the secondary database still matches, is kept at
+    /// `<dedup-hashset-file>.pending-secondary` until the secondary is backfilled; upload runs
+    /// refuse to start while it exists, except --secondary-only. Source files are left in place
+    /// for the backfill
+    #[arg(long, conflicts_with_all = ["trim_to_active_only", "dedup_readonly"])]
+    pub stop_after_inactive: bool,
+
+    /// Upload the merged entries to --target-db-id alone, never reading or writing
+    /// `ACTIVE_DB`, e.g. to pre-populate each database before a planned cutover in separate
+    /// runs. The dedup hashset is shared with every other run unless --dedup-per-database,
+    /// and the source files stay in place for those runs instead of moving to --processed-dir
+    #[arg(
+        long,
+        requires = "target_db_id",
+        conflicts_with_all = ["force_active_db", "stop_after_inactive", "secondary_only", "trim_to_active_only"]
+    )]
+    pub skip_kv: bool,
+
+    /// Database the --skip-kv upload goes to
+    #[arg(long, requires = "skip_kv")]
+    pub target_db_id: Option<String>,
+
+    /// Keep a dedup hashset of its own for --target-db-id, at `<dedup-hashset-file>.db-<ID>`
+    /// (and likewise for --merge-state), so pre-populating one database does not mark its
+    /// entries as uploaded for another
+    #[arg(long, requires = "target_db_id")]
+    pub dedup_per_database: bool,
+
+    /// Backfill the secondary database a --stop-after-inactive run left behind: merge the
+    /// sources against its pending dedup state, keep the entries production already serves,
+    /// and upload them to the database `ACTIVE_DB` marks inactive and any replicas, without
+    /// touching `ACTIVE_DB` or the main dedup hashset. The pending state is removed once every
+    /// database caught up. Source files are never moved to --processed-dir
+    #[arg(
+        long,
+        requires_all = ["blue_db_id", "green_db_id"],
+        conflicts_with_all = ["stop_after_inactive", "trim_to_active_only", "dedup_readonly"]
+    )]
+    pub secondary_only: bool,
+
+    /// Keep going when a Step 3 database fails; the dedup hashset is still saved and the
+    /// failed databases are reported for a later backfill
+    #[arg(long)]
+    pub continue_on_replica_error: bool,
+
+    /// After toggling ACTIVE_DB, poll it until it reads back the new value before Step 3;
+    /// roll back and abort if it doesn't converge
+    #[arg(long)]
+    pub verify_after_toggle: bool,
+
+    /// How long `--verify-after-toggle` waits for the toggle to propagate
+    #[arg(long, default_value_t = 60)]
+    pub verify_toggle_timeout_secs: u64,
+
+    /// Sink for the merged entries
+    #[arg(long, value_enum, default_value_t = BackendKind::CloudflareD1)]
+    pub backend: BackendKind,
+
+    /// Bucket written by `--backend s3`
+    #[arg(long, required_if_eq("backend", "s3"))]
+    pub s3_bucket: Option<String>,
+
+    /// Key prefix for objects written by `--backend s3`
+    #[arg(long, default_value = "pda-directory")]
+    pub s3_prefix: String,
+
+    /// Endpoint of an S3-compatible store; credentials and region come from the AWS environment
+    #[arg(long)]
+    pub s3_endpoint: Option<String>,
+
+    /// Payload written per chunk by `--backend s3`
+    #[arg(long, value_enum, default_value_t = S3PayloadFormat::Sql)]
+    pub s3_format: S3PayloadFormat,
+
+    /// Log output format; filtering still follows `RUST_LOG`
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+
+    /// Identifier of this run, attached to every log line, the --summary-json summary and each
+    /// staged script as `run-id`, unless --r2-metadata sets its own. A random UUID when unset
+    #[arg(long, value_parser = parse_run_id)]
+    pub run_id: Option<String>,
+
+    /// Log a breakdown of wall-clock time per phase at the end of the run
+    #[arg(long)]
+    pub profile: bool,
+
+    /// Write a JSON summary of the run (exit code, counts, failed targets, and phase timings
+    /// with `--profile`) to this file, on success and failure alike
+    #[arg(long)]
+    pub summary_json: Option<PathBuf>,
+
+    /// Write the merge's deduplication breakdown (files per source type, initial entries,
+    /// entries deduped within the run and against the dedup hashset, new entries) as JSON to
+    /// this file
+    #[arg(long)]
+    pub dedup_stats_json: Option<PathBuf>,
+
+    /// Only log warnings and errors; an explicit `RUST_LOG` still takes precedence
+    #[arg(short, long, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Log debug output; an explicit `RUST_LOG` still takes precedence
+    #[arg(short, long)]
+    pub verbose: bool,
+
+    /// Before uploading, verify the source sqlite and target D1 schemas contain the expected columns
+    #[arg(long)]
+    pub check_schema: bool,
+
+    /// Before uploading, check that the inactive, secondary and every replica database answer
+    /// a trivial query and hold the expected schema, aborting before any upload or toggle if
+    /// one does not
+    #[arg(long)]
+    pub require_all_replicas_healthy: bool,
+
+    /// Attempts per chunk upload before the run is aborted
+    #[arg(long, default_value_t = 3)]
+    pub chunk_attempts: usize,
+
+    /// Treat this Cloudflare API error code as fatal, failing the chunk without retrying it, on
+    /// top of the known auth and not-found codes (repeatable)
+    #[arg(long = "halt-on-api-error-code")]
+    pub halt_on_api_error_codes: Vec<u64>,
+
+    /// Render the SQL of up to N chunks on blocking threads while the current chunk uploads,
+    /// instead of rendering each chunk when its upload starts. Rendering pauses once N chunks
+    /// wait, bounding the memory they take
+    #[arg(long)]
+    pub pipeline_depth: Option<NonZeroUsize>,
+
+    /// Give up on a single chunk's D1 import after this many seconds, counting it as a failed
+    /// attempt so only that chunk is retried
+    #[arg(long)]
+    pub chunk_timeout_secs: Option<u64>,
+
+    /// Save the dedup hashset every N chunks uploaded to the secondary database, so a crash
+    /// during Step 3 only loses the chunks since the last checkpoint. An `s3://` dedup hashset
+    /// is published at every checkpoint
+    #[arg(long)]
+    pub checkpoint_every: Option<NonZeroUsize>,
+
+    /// Table the import scripts insert into
+    #[arg(long, default_value = DEFAULT_TABLE_NAME, value_parser = parse_table_name)]
+    pub table_name: String,
+
+    /// Insert into a different table for one database, as `DB=TABLE` where DB is `blue`,
+    /// `green`, or a database id. Repeatable; other databases use `--table-name`
+    #[arg(long = "db-table", value_name = "DB=TABLE", value_parser = parse_db_table)]
+    pub db_tables: Vec<(String, String)>,
+
+    /// After each D1 database finishes importing, create indexes on `seed_count` and
+    /// `program_id` if missing. Created after the import rather than before, since maintaining
+    /// them slows down bulk inserts
+    #[arg(long)]
+    pub create_indexes: bool,
+
+    /// How chunks reach D1: the R2-staged file import, direct query API calls (much lower
+    /// latency for small runs), or `auto` to pick per chunk by size
+    #[arg(long, value_enum, default_value_t = UploadMode::Import)]
+    pub upload_mode: UploadMode,
+
+    /// Largest chunk `--upload-mode auto` sends through the query API
+    #[arg(long, default_value_t = 1000)]
+    pub query_mode_max_entries: usize,
+
+    /// Rows per INSERT statement in generated SQL
+    #[arg(long, default_value_t = NonZeroUsize::new(DEFAULT_ENTRIES_PER_STATEMENT).expect("nonzero"))]
+    pub entries_per_statement: NonZeroUsize,
+
+    /// When D1 rejects a statement as too large, halve --entries-per-statement (down to 2) and
+    /// retry that chunk, instead of failing it
+    #[arg(long)]
+    pub entries_per_statement_auto: bool,
+
+    /// Split each chunk's D1 import into files of at most this many entries, each imported to
+    /// completion before the next
+    #[arg(long)]
+    pub max_insert_rows_per_file: Option<NonZeroUsize>,
+
+    /// Split each chunk's D1 import into files whose SQL takes at most this many bytes, halving
+    /// a file until it fits
+    #[arg(long)]
+    pub max_file_bytes: Option<usize>,
+
+    /// Upload a fresh copy of each chunk's script even when D1 reports it already has the file
+    /// from an earlier run, instead of resuming that import. For when the earlier upload was
+    /// corrupt or partial
+    #[arg(long)]
+    pub force_reupload: bool,
+
+    /// `Content-Type` of each script staged in R2 for a D1 import, e.g. `application/sql`.
+    /// Unset sends none
+    #[arg(long, value_name = "TYPE")]
+    pub r2_content_type: Option<String>,
+
+    /// Attach `x-amz-meta-KEY: VALUE` to each script staged in R2 for a D1 import, next to the
+    /// `run-id` every staged script carries along with its `entry-count` and `first-pda`.
+    /// Repeatable; a `run-id` given here replaces the --run-id one
+    #[arg(long = "r2-metadata", value_name = "KEY=VALUE", value_parser = parse_r2_metadata)]
+    pub r2_metadata: Vec<(String, String)>,
+
+    /// Also populate the `seeds_json` column (JSON array of hex seeds) for ad-hoc SQL queries
+    #[arg(long)]
+    pub with_seeds_json: bool,
+
+    /// Also populate the `seed_checksum` column (crc32 of `seed_bytes`), which is verified
+    /// whenever a sqlite file carrying it is read back as a source
+    #[arg(long)]
+    pub with_checksums: bool,
+
+    /// Wrap generated SQL in a single BEGIN/COMMIT transaction. Only applies to SQL written to
+    /// non-D1 sinks (`--backend s3 --s3-format sql`); D1's importer rejects explicit
+    /// transactions
+    #[arg(long)]
+    pub transactional: bool,
+
+    /// Drop entries whose pda is on the ed25519 curve (wallet addresses recorded as PDAs)
+    #[arg(long)]
+    pub require_off_curve: bool,
+
+    /// Strip trailing zero bytes from seeds that are exactly `--seed-pad-len` bytes long, for
+    /// collectors that right-pad seeds. Ambiguous: a seed that really ends in zero is trimmed
+    /// too, so this is opt-in
+    #[arg(long)]
+    pub trim_seed_padding: bool,
+
+    /// Width collectors pad seeds to; only seeds of exactly this length are trimmed
+    #[arg(long, default_value_t = 32)]
+    pub seed_pad_len: usize,
+
+    /// Make the parse phase resumable: record each fully parsed source file in this manifest
+    /// and spill its entries to `<path>.spill`, so a rerun after an interrupted merge skips
+    /// those files. Cleared once the run completes
+    #[arg(long)]
+    pub merge_state: Option<PathBuf>,
+
+    /// File of base58 or hex pdas (one per line, `#` comments allowed) that must never be
+    /// uploaded; matching entries are dropped from every source on every run
+    #[arg(long)]
+    pub exclude_pda_list: Option<PathBuf>,
+
+    /// Re-attribute entries of program OLD to program NEW (base58 or hex), e.g. after a program
+    /// migration. Repeatable. Dedup is keyed by pda alone, so pdas already in the dedup hashset
+    /// (and the rows already in D1) keep their old program id; prune them from the hashset and
+    /// update D1 separately to move those. Remapped entries no longer pass `--verify-pda`,
+    /// since their seeds derive under OLD
+    #[arg(long = "remap-program", value_name = "OLD=NEW", value_parser = parse_program_remap)]
+    pub remap_programs: Vec<(Address, Address)>,
+
+    /// Keep only the pdas of shard INDEX of TOTAL (0-based), so several instances can split a
+    /// large backfill between them. The dedup hashset and merge state files get a
+    /// `.shard-INDEX-of-TOTAL` suffix so instances never clobber each other's, and the source
+    /// files stay in place for the other shards instead of moving to --processed-dir. Only
+    /// shard 0 toggles `ACTIVE_DB` after its Step 1; the others upload to both databases and
+    /// leave it
+    #[arg(long, value_name = "INDEX/TOTAL", value_parser = parse_pda_shard)]
+    pub pda_shard: Option<PdaShard>,
+
+    /// Log a histogram of seed counts and seed sizes across the new entries, flagging those
+    /// beyond Solana's 16-seed and 32-byte limits. Nothing is dropped
+    #[arg(long)]
+    pub warn_on_large_seeds: bool,
+
+    /// Drop entries whose seeds and program id do not derive their pda, checked on all cores
+    #[arg(long, alias = "parallel-verify")]
+    pub verify_pda: bool,
+
+    /// Upload only a uniformly random sample of this many new entries; only the sampled pdas
+    /// are added to the dedup hashset
+    #[arg(long)]
+    pub sample: Option<usize>,
+
+    /// Seed for --sample, for a reproducible subset
+    #[arg(long, requires = "sample")]
+    pub sample_seed: Option<u64>,
+
+    /// Exit with code 6 instead of succeeding when the merge produces no new entries, or with
+    /// code 3 when source files were parsed but none of them held a single entry
+    #[arg(long)]
+    pub fail_on_empty: bool,
+
+    /// Before uploading, execute the generated SQL against an in-memory sqlite table with the
+    /// D1 schema and abort if any statement fails or a row goes missing
+    #[arg(long)]
+    pub validate_sql: bool,
+
+    /// Run the merge in a dedicated pool of this many threads instead of rayon's global pool
+    /// (one thread per core). Either way it runs off the async runtime's worker threads
+    #[arg(long)]
+    pub merge_threads: Option<NonZeroUsize>,
+
+    /// Merge, then print the predicted upload time and chunk count and exit without uploading,
+    /// reading `ACTIVE_DB` or saving the dedup hashset
+    #[arg(long)]
+    pub estimate: bool,
+
+    /// Estimate the on-chain rent of the accounts behind the new entries at this many lamports
+    /// per byte-year, e.g. 3480, and add it to the --summary-json summary
+    #[arg(long, value_name = "LAMPORTS")]
+    pub rent_lamports_per_byte_year: Option<u64>,
+
+    /// Bytes every account is assumed to take on top of its seeds, for
+    /// --rent-lamports-per-byte-year. Defaults to the 128 bytes of account metadata
+    #[arg(long, default_value_t = 128, requires = "rent_lamports_per_byte_year")]
+    pub rent_account_base_bytes: u64,
+
+    /// Merge and deduplicate the sources into this single versioned blob and exit, to compact
+    /// an ingest directory. An OUTPUT ending in `.zst` is written zstd-compressed. Never talks
+    /// to Cloudflare. Entries are filtered against the dedup hashset a normal run would use,
+    /// including its --dedup-per-database and --pda-shard suffixes, but the hashset is only
+    /// read, never saved; with --processed-dir the merged source files are moved there
+    #[arg(long, value_name = "OUTPUT")]
+    pub merge_only: Option<PathBuf>,
+
+    /// Upload throughput to R2 assumed by --estimate, in megabits per second
+    #[arg(long, default_value_t = 40.0, requires = "estimate")]
+    pub estimate_mbps: f64,
+
+    /// Seconds D1 is assumed to spend ingesting each chunk after its upload, for --estimate
+    #[arg(long, default_value_t = 45.0, requires = "estimate")]
+    pub estimate_ingest_secs_per_chunk: f64,
+
+    /// Order of the uploaded entries. Changing it changes the generated SQL byte for byte, and
+    /// with it the ETag of every chunk
+    #[arg(long, value_enum, default_value_t = SortOrder::Pda)]
+    pub sort_by: SortOrder,
+
+    /// Write a JSON index of each program's entry count and first row among the uploaded
+    /// entries to this file, plus its byte range in the blob with --merge-only, where the
+    /// index is built as the blob is written. Requires --sort-by program
+    #[arg(long)]
+    pub program_index_out: Option<PathBuf>,
+}
+
+/// One of `total` disjoint slices of the pda space, selected with `--pda-shard`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PdaShard {
+    pub index: u64,
+    pub total: u64,
+}
+
+impl PdaShard {
+    /// Whether `pda` belongs to this shard. Pdas are hash outputs, so their leading 8 bytes
+    /// spread evenly across shards.
+    pub fn contains(&self, pda: &Address) -> bool {
+        let mut prefix = [0; 8];
+        prefix.copy_from_slice(&pda.as_ref()[..8]);
+        u64::from_be_bytes(prefix) % self.total == self.index
+    }
+
+    /// Whether this shard's instance toggles `ACTIVE_DB`. Every instance uploads to both
+    /// databases, so a single toggle suffices, and more would flip it back and forth.
+    pub fn toggles_active_db(&self) -> bool {
+        self.index == 0
+    }
+
+    /// `path` with this shard's suffix, for files each instance must keep to itself.
+    pub fn suffixed(&self, path: &Path) -> PathBuf {
+        let mut path = OsString::from(path.as_os_str());
+        path.push(format!(".shard-{}-of-{}", self.index, self.total));
+        PathBuf::from(path)
+    }
+}
+
+impl fmt::Display for PdaShard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.index, self.total)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum BackendKind {
+    /// Cloudflare D1 import through R2
+    CloudflareD1,
+    /// S3-compatible object store
+    S3,
+}
+
+/// Label of one of the two blue/green databases, as stored under `ACTIVE_DB`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DbLabel {
+    Blue,
+    Green,
+}
+
+impl DbLabel {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DbLabel::Blue => "blue",
+            DbLabel::Green => "green",
+        }
+    }
+}
+
+/// On-disk layout of the dedup hashset.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum DedupFormat {
+    /// `bincode` serialization of the set, compact and required by `--dedup-bloom`
+    #[default]
+    Bincode,
+    /// Sorted base58 addresses, one per line, for diffing and checking into git
+    Text,
+    /// `bincode` map of each address to the unix second it was added, behind a magic header,
+    /// required by `--dedup-lookback-secs`. Sets in the other formats migrate with every
+    /// address stamped 0
+    Timestamped,
+}
+
+/// How durably the dedup hashset is written before the run moves on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum FsyncMode {
+    /// Sync the written file before renaming it over the old set
+    #[default]
+    Full,
+    /// Skip syncing entirely, for ephemeral and test environments
+    None,
+    /// Sync only the directory after the rename, persisting the rename but not necessarily
+    /// the file's contents
+    RenameOnly,
+}
+
+/// How files under `--path` are recognized and parsed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum InputFormat {
+    /// `pda_collector_*.blob[.zst]` as blobs, `*.sqlite` as sqlite and `*.csv[.gz]` as CSV,
+    /// ignoring anything else
+    #[default]
+    Auto,
+    /// Every regular file is a blob
+    Blob,
+    /// Every regular file is a sqlite database
+    Sqlite,
+    /// Every regular file is a `pda,program_id,seeds` CSV export, gzipped if named `*.gz`
+    Csv,
+}
+
+/// Order of the merged entries, and therefore of the rows in each upload chunk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum SortOrder {
+    /// By pda
+    #[default]
+    Pda,
+    /// By `(program_id, pda)`, grouping each program's entries together
+    Program,
+}
+
+/// How the D1 backend sends each chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum UploadMode {
+    /// Stage the chunk's script in R2 and run it through D1's import API
+    Import,
+    /// Send the chunk's INSERT statements directly through D1's query API, skipping R2
+    Query,
+    /// `query` for chunks of at most --query-mode-max-entries entries, `import` otherwise
+    Auto,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum S3PayloadFormat {
+    /// The same INSERT script that would be imported into D1
+    Sql,
+    /// The raw bincode-encoded `Vec<PdaSqlite>` chunk
+    Bincode,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// Human-readable `env_logger` lines
+    Text,
+    /// One JSON object per record with timestamp, level, target and message
+    Json,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum Command {
+    /// Shrink the dedup hashset to the addresses listed in a keep file
+    PruneDedup {
+        /// Path of the dedup hashset to prune
+        #[arg(short, long, default_value = "/tmp/dedup")]
+        dedup_hashset_file: PathBuf,
+
+        /// File of base58 or hex addresses (one per line) to retain
+        #[arg(short, long)]
+        keep: PathBuf,
+    },
+    /// Poll a D1 import that is already running to completion without uploading anything, e.g.
+    /// after a run died while polling. Uses the top-level --token and --account-id
+    ContinueImport {
+        /// D1 database whose import to finish
+        #[arg(long)]
+        database_id: String,
+
+        /// Last bookmark the import reported, as logged when it was ingested
+        #[arg(long)]
+        bookmark: Option<String>,
+    },
+    /// Look pdas up in a D1 database and print their stored program id, seed count and decoded
+    /// seeds. Uses the top-level --token, --account-id and --table-name
+    Lookup {
+        /// D1 database to query
+        #[arg(long)]
+        database_id: String,
+
+        /// Base58 or hex pdas to look up
+        #[arg(long = "pda", required = true, num_args = 1.., value_parser = parse_address_arg)]
+        pdas: Vec<Address>,
+    },
+    /// Drop dedup entries whose pdas are no longer in a live D1 database, rewriting the set
+    /// atomically. Uses the top-level --token, --account-id and --table-name
+    CompactDedup {
+        /// Path of the dedup hashset to compact
+        #[arg(short, long, default_value = "/tmp/dedup")]
+        dedup_hashset_file: PathBuf,
+
+        /// D1 database whose pdas are kept
+        #[arg(long)]
+        database_id: String,
+
+        /// Pdas read from D1 per query
+        #[arg(long, default_value_t = 10_000)]
+        page_size: usize,
+    },
+    /// Union other dedup hashsets, e.g. from another machine, into one
+    MergeDedup {
+        /// Dedup hashset to merge into, created if missing
+        #[arg(long)]
+        into: PathBuf,
+
+        /// Dedup hashsets to merge from
+        #[arg(long, required = true, num_args = 1..)]
+        from: Vec<PathBuf>,
+    },
+    /// Report the pda overlap between two collector output directories
+    DiffSources {
+        /// First directory of blob/sqlite files
+        #[arg(long)]
+        a: PathBuf,
+
+        /// Second directory of blob/sqlite files
+        #[arg(long)]
+        b: PathBuf,
+
+        /// Write the pdas found only in A to this file, one per line
+        #[arg(long)]
+        only_a_out: Option<PathBuf>,
+
+        /// Write the pdas found only in B to this file, one per line
+        #[arg(long)]
+        only_b_out: Option<PathBuf>,
+
+        /// Write the pdas found in both to this file, one per line
+        #[arg(long)]
+        both_out: Option<PathBuf>,
+    },
+    /// Check the sqlite files of a collector output directory for rows whose stored
+    /// `seed_checksum` or `seed_count` disagrees with their `seed_bytes`, failing if any do.
+    /// Never touches the dedup hashset or D1
+    Verify {
+        /// Directory of sqlite files
+        #[arg(long)]
+        source: PathBuf,
+    },
+    /// Profile the program id and seed count distribution of a collector output directory,
+    /// without touching the dedup hashset or D1
+    Analyze {
+        /// Directory of blob/sqlite files
+        #[arg(long)]
+        source: PathBuf,
+
+        /// Number of programs with the most entries to list
+        #[arg(long, default_value_t = 20)]
+        top: usize,
+
+        /// Also write the profile to this file as JSON
+        #[arg(long)]
+        json_out: Option<PathBuf>,
+    },
+}
+
+/// Accepts plain SQL identifiers only, since table names are interpolated into the import
+/// script unquoted.
+fn parse_table_name(name: &str) -> Result<String, String> {
+    let mut chars = name.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if valid {
+        Ok(name.to_owned())
+    } else {
+        Err(format!(
+            "{name:?} is not a valid table name, expected [A-Za-z_][A-Za-z0-9_]*"
+        ))
+    }
+}
+
+fn parse_db_table(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((database, table)) if !database.is_empty() => {
+            Ok((database.to_owned(), parse_table_name(table)?))
+        }
+        _ => Err(format!("expected DB=TABLE, got {value:?}")),
+    }
+}
+
+fn parse_run_id(value: &str) -> Result<String, String> {
+    if value.is_empty() || HeaderValue::from_str(value).is_err() {
+        return Err(format!("{value:?} is not a valid run id"));
+    }
+    Ok(value.to_owned())
+}
+
+fn parse_r2_metadata(value: &str) -> Result<(String, String), String> {
+    let Some((key, value)) = value.split_once('=').filter(|(key, _)| !key.is_empty()) else {
+        return Err(format!("expected KEY=VALUE, got {value:?}"));
+    };
+    HeaderName::from_bytes(format!("x-amz-meta-{key}").as_bytes())
+        .map_err(|_| format!("{key:?} is not a valid header name"))?;
+    HeaderValue::from_str(value).map_err(|_| format!("{value:?} is not a valid header value"))?;
+    Ok((key.to_ascii_lowercase(), value.to_owned()))
+}
+
+/// Parses an address given in base58, or as 64 hex digits with or without a `0x` prefix. A
+/// 32-byte address takes at most 44 base58 characters, so 64 hex digits are never base58.
+pub fn parse_address(value: &str) -> eyre::Result<Address> {
+    let hex_digits = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+        .unwrap_or(value);
+    if hex_digits.len() == 64 && hex_digits.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        let mut bytes = [0; 32];
+        hex::decode_to_slice(hex_digits, &mut bytes)
+            .map_err(|err| eyre!("invalid hex address {value:?}: {err}"))?;
+        return Ok(Address::from(bytes));
+    }
+    Address::from_str(value).map_err(|err| eyre!("invalid address {value:?}: {err}"))
+}
+
+fn parse_address_arg(value: &str) -> Result<Address, String> {
+    parse_address(value).map_err(|err| err.to_string())
+}
+
+fn parse_http_url(value: &str) -> Result<Url, String> {
+    let url = Url::parse(value).map_err(|err| format!("invalid URL {value:?}: {err}"))?;
+    match url.scheme() {
+        "http" | "https" => Ok(url),
+        scheme => Err(format!("expected an http(s) URL, got a {scheme} URL")),
+    }
+}
+
+fn parse_program_remap(value: &str) -> Result<(Address, Address), String> {
+    let (old, new) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected OLD=NEW, got {value:?}"))?;
+    Ok((parse_address_arg(old)?, parse_address_arg(new)?))
+}
+
+fn parse_pda_shard(value: &str) -> Result<PdaShard, String> {
+    let (index, total) = value
+        .split_once('/')
+        .ok_or_else(|| format!("expected INDEX/TOTAL, got {value:?}"))?;
+    let parse = |number: &str| {
+        number
+            .parse::<u64>()
+            .map_err(|err| format!("invalid shard number {number:?}: {err}"))
+    };
+    let shard = PdaShard {
+        index: parse(index)?,
+        total: parse(total)?,
+    };
+    if shard.index >= shard.total {
+        return Err(format!(
+            "shard index must be below the shard total, got {shard}"
+        ));
+    }
+    Ok(shard)
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[test]
+    fn merge_only_needs_no_cloudflare_credentials() {
+        let parse =
+            |args: &[&str]| Args::try_parse_from(["uploader", "--path", "in"].iter().chain(args));
+        let args = parse(&["--merge-only", "out.blob"]).unwrap();
+        assert_eq!(args.merge_only.as_deref(), Some(Path::new("out.blob")));
+        assert_eq!((args.token, args.account_id), (None, None));
+
+        let err = parse(&[]).unwrap_err();
+        assert_eq!(err.kind(), clap::error::ErrorKind::MissingRequiredArgument);
+        assert!(parse(&["--token", "token"]).is_err());
+        assert!(parse(&["--token", "token", "--account-id", "account"]).is_ok());
+        assert!(parse(&["--estimate"]).is_ok());
+    }
+
+    #[test]
+    fn pda_shards_split_every_pda_exactly_once() {
+        let shards: Vec<PdaShard> = (0..3)
+            .map(|index| parse_pda_shard(&format!("{index}/3")).unwrap())
+            .collect();
+        for seed in 0..=255u8 {
+            let mut bytes = [seed; 32];
+            bytes[..8].copy_from_slice(&(u64::from(seed) * 0x0101_0101).to_be_bytes());
+            let pda = Address::from(bytes);
+            assert_eq!(
+                shards.iter().filter(|shard| shard.contains(&pda)).count(),
+                1
+            );
+        }
+        let toggling: Vec<u64> = shards
+            .iter()
+            .filter(|shard| shard.toggles_active_db())
+            .map(|shard| shard.index)
+            .collect();
+        assert_eq!(toggling, [0]);
+        assert_eq!(
+            shards[1].suffixed(Path::new("/tmp/dedup.db-abc")),
+            Path::new("/tmp/dedup.db-abc.shard-1-of-3")
+        );
+    }
+
+    #[test]
+    fn pda_shards_must_be_below_their_total() {
+        assert!(parse_pda_shard("3/3").is_err());
+        assert!(parse_pda_shard("0/0").is_err());
+        assert!(parse_pda_shard("1").is_err());
+    }
 }
@@ -0,0 +1,376 @@
+//! Drives `upload_to_d1` through init → R2 upload → ingest → poll, and `query_insert_d1`
+//! through the query API, against a mock Cloudflare API, so both upload flows can be exercised
+//! without touching real infrastructure. The D1 backend's pre-rendered upload runs through the
+//! same import flow, as do chunks split across several import files.
+
+use serde_json::{Value, json};
+use solana_address::Address;
+use uploader::{
+    CloudflareD1Backend, PdaSqlite, UploadBackend,
+    cloudflare::{ImportReport, UploadOptions, query_insert_d1, upload_to_d1},
+    types::UploadMode,
+};
+use wiremock::{
+    Mock, MockBuilder, MockServer, Request, Respond, ResponseTemplate,
+    matchers::{body_partial_json, method, path},
+};
+
+const ACCOUNT: &str = "account";
+const DATABASE: &str = "database";
+const IMPORT_PATH: &str = "/accounts/account/d1/database/database/import";
+const QUERY_PATH: &str = "/accounts/account/d1/database/database/query";
+
+/// Acknowledges the R2 upload the way R2 does: with the md5 of the body as a quoted ETag.
+struct EchoEtag;
+
+impl Respond for EchoEtag {
+    fn respond(&self, request: &Request) -> ResponseTemplate {
+        ResponseTemplate::new(200)
+            .insert_header("ETag", format!("\"{:x}\"", md5::compute(&request.body)))
+    }
+}
+
+fn entries() -> Vec<PdaSqlite> {
+    (1..=3u8)
+        .map(|index| PdaSqlite {
+            pda: Address::from([index; 32]),
+            seeds: vec![b"seed".to_vec(), vec![index]],
+            program_id: Address::from([0xAA; 32]),
+        })
+        .collect()
+}
+
+fn api_response(result: Value) -> ResponseTemplate {
+    ResponseTemplate::new(200).set_body_json(json!({
+        "success": true,
+        "errors": [],
+        "result": result,
+    }))
+}
+
+fn import_action(action: &str) -> MockBuilder {
+    Mock::given(method("POST"))
+        .and(path(IMPORT_PATH))
+        .and(body_partial_json(json!({ "action": action })))
+}
+
+/// Mounts the init, R2 upload, and ingest steps, leaving the poll responses to each test.
+async fn mock_until_ingest() -> MockServer {
+    let server = MockServer::start().await;
+
+    import_action("init")
+        .respond_with(api_response(json!({
+            "upload_url": format!("{}/r2/chunk", server.uri()),
+            "filename": "chunk.sql",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("PUT"))
+        .and(path("/r2/chunk"))
+        .respond_with(EchoEtag)
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    import_action("ingest")
+        .and(body_partial_json(json!({ "filename": "chunk.sql" })))
+        .respond_with(api_response(json!({
+            "success": true,
+            "status": "active",
+            "at_bookmark": "bookmark-1",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    server
+}
+
+async fn upload(server: &MockServer) -> eyre::Result<ImportReport> {
+    upload_to_d1(
+        &server.uri(),
+        "token",
+        ACCOUNT,
+        DATABASE,
+        &entries(),
+        &UploadOptions::default(),
+        None,
+    )
+    .await
+}
+
+#[tokio::test]
+async fn import_polls_through_to_completion() {
+    let server = mock_until_ingest().await;
+
+    import_action("poll")
+        .respond_with(api_response(json!({
+            "success": true,
+            "status": "active",
+            "at_bookmark": "bookmark-2",
+        })))
+        .up_to_n_times(1)
+        .with_priority(1)
+        .expect(1)
+        .mount(&server)
+        .await;
+    import_action("poll")
+        .and(body_partial_json(
+            json!({ "current_bookmark": "bookmark-2" }),
+        ))
+        .respond_with(api_response(json!({
+            "success": true,
+            "status": "complete",
+            "messages": ["Imported 3 rows"],
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let report = upload(&server).await.unwrap();
+    assert!(report.is_clean());
+}
+
+#[tokio::test]
+async fn failed_import_is_reported() {
+    let server = mock_until_ingest().await;
+
+    import_action("poll")
+        .respond_with(api_response(json!({
+            "success": false,
+            "status": "error",
+            "error": "no such table: pda_registry",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let err = upload(&server).await.unwrap_err();
+    assert!(
+        err.to_string().contains("no such table: pda_registry"),
+        "unexpected error: {err:#}"
+    );
+}
+
+#[tokio::test]
+async fn upload_matches_the_script() {
+    let server = mock_until_ingest().await;
+    import_action("poll")
+        .respond_with(api_response(
+            json!({ "success": true, "status": "complete" }),
+        ))
+        .mount(&server)
+        .await;
+
+    let report = upload(&server).await.unwrap();
+
+    let script = uploader::build_insert_script(&entries(), &UploadOptions::default())
+        .unwrap()
+        .unwrap();
+    let requests = server.received_requests().await.unwrap();
+    let init: Value = requests
+        .iter()
+        .filter(|request| request.url.path() == IMPORT_PATH)
+        .map(|request| request.body_json::<Value>().unwrap())
+        .find(|body| body["action"] == "init")
+        .unwrap();
+    assert_eq!(report.uploaded_bytes, script.len() as u64);
+    assert_eq!(
+        init["etag"],
+        format!("{:x}", md5::compute(script.as_bytes()))
+    );
+}
+
+#[tokio::test]
+async fn query_mode_sends_the_statements_without_importing() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path(QUERY_PATH))
+        .respond_with(api_response(json!([
+            { "results": [], "success": true },
+            { "results": [], "success": true },
+        ])))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let options = UploadOptions {
+        entries_per_statement: 2,
+        ..UploadOptions::default()
+    };
+    let report = query_insert_d1(
+        &server.uri(),
+        "token",
+        ACCOUNT,
+        DATABASE,
+        &entries(),
+        &options,
+        None,
+    )
+    .await
+    .unwrap();
+
+    assert!(report.is_clean());
+    let script = uploader::build_insert_script(&entries(), &options)
+        .unwrap()
+        .unwrap();
+    let requests = server.received_requests().await.unwrap();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].body_json::<Value>().unwrap()["sql"], script);
+}
+
+#[tokio::test]
+async fn prerendered_chunk_uploads_its_rendered_script() {
+    let server = mock_until_ingest().await;
+    import_action("poll")
+        .respond_with(api_response(
+            json!({ "success": true, "status": "complete" }),
+        ))
+        .mount(&server)
+        .await;
+
+    let backend = CloudflareD1Backend {
+        api_base: server.uri(),
+        api_token: "token".to_owned(),
+        account_id: ACCOUNT.to_owned(),
+        options: UploadOptions::default(),
+        table_overrides: Default::default(),
+        chunk_timeout: None,
+        upload_mode: UploadMode::Import,
+        query_mode_max_entries: 0,
+    };
+    let renderer = backend.renderer(DATABASE).unwrap();
+    let rendered = renderer(&entries()).unwrap().unwrap();
+    assert_eq!(
+        Some(&rendered),
+        uploader::build_insert_script(&entries(), &UploadOptions::default())
+            .unwrap()
+            .as_ref()
+    );
+
+    // The upload takes the script as given instead of rendering it again.
+    let rendered = format!("{rendered}-- rendered ahead\n");
+    let report = backend
+        .upload_rendered(DATABASE, &entries(), rendered.clone())
+        .await
+        .unwrap();
+    assert!(report.is_clean());
+    let requests = server.received_requests().await.unwrap();
+    let put = requests
+        .iter()
+        .find(|request| request.url.path() == "/r2/chunk")
+        .unwrap();
+    assert_eq!(put.body, rendered.into_bytes());
+}
+
+#[tokio::test]
+async fn staged_script_carries_its_content_type_and_metadata() {
+    let server = mock_until_ingest().await;
+    import_action("poll")
+        .respond_with(api_response(
+            json!({ "success": true, "status": "complete" }),
+        ))
+        .mount(&server)
+        .await;
+
+    let options = UploadOptions {
+        r2_content_type: Some("application/sql".to_owned()),
+        r2_metadata: vec![("run-id".to_owned(), "run-42".to_owned())],
+        ..UploadOptions::default()
+    };
+    let report = upload_to_d1(
+        &server.uri(),
+        "token",
+        ACCOUNT,
+        DATABASE,
+        &entries(),
+        &options,
+        None,
+    )
+    .await
+    .unwrap();
+    assert!(report.is_clean());
+
+    let requests = server.received_requests().await.unwrap();
+    let put = requests
+        .iter()
+        .find(|request| request.url.path() == "/r2/chunk")
+        .unwrap();
+    let header = |name: &str| put.headers.get(name).unwrap().to_str().unwrap();
+    assert_eq!(header("content-type"), "application/sql");
+    assert_eq!(header("x-amz-meta-run-id"), "run-42");
+    assert_eq!(header("x-amz-meta-entry-count"), "3");
+    assert_eq!(header("x-amz-meta-first-pda"), entries()[0].pda.to_string());
+}
+
+#[tokio::test]
+async fn oversized_chunk_is_split_across_import_files() {
+    let server = MockServer::start().await;
+    import_action("init")
+        .respond_with(api_response(json!({
+            "upload_url": format!("{}/r2/chunk", server.uri()),
+            "filename": "chunk.sql",
+        })))
+        .expect(2)
+        .mount(&server)
+        .await;
+    Mock::given(method("PUT"))
+        .and(path("/r2/chunk"))
+        .respond_with(EchoEtag)
+        .expect(2)
+        .mount(&server)
+        .await;
+    import_action("ingest")
+        .respond_with(api_response(
+            json!({ "success": true, "status": "complete" }),
+        ))
+        .expect(2)
+        .mount(&server)
+        .await;
+
+    let options = UploadOptions {
+        max_rows_per_file: std::num::NonZeroUsize::new(2),
+        ..UploadOptions::default()
+    };
+    let report = upload_to_d1(
+        &server.uri(),
+        "token",
+        ACCOUNT,
+        DATABASE,
+        &entries(),
+        &options,
+        None,
+    )
+    .await
+    .unwrap();
+    assert!(report.is_clean());
+
+    let entries = entries();
+    let expected: Vec<Vec<u8>> = [&entries[..2], &entries[2..]]
+        .into_iter()
+        .map(|part| {
+            uploader::build_insert_script(part, &options)
+                .unwrap()
+                .unwrap()
+                .into_bytes()
+        })
+        .collect();
+    let uploaded: Vec<Vec<u8>> = server
+        .received_requests()
+        .await
+        .unwrap()
+        .into_iter()
+        .filter(|request| request.url.path() == "/r2/chunk")
+        .map(|request| request.body)
+        .collect();
+    assert_eq!(uploaded, expected);
+    assert_eq!(
+        report.uploaded_bytes,
+        expected
+            .iter()
+            .map(|script| script.len() as u64)
+            .sum::<u64>()
+    );
+}
@@ -0,0 +1,170 @@
+//! Drives the D1 readers built on the query API, `lookup_d1_pda` and `d1_pdas`, against a
+//! mock Cloudflare API, checking the parameters they bind and how they parse the rows that
+//! come back.
+
+use serde_json::{Value, json};
+use solana_address::Address;
+use uploader::cloudflare::{StoredEntry, d1_pdas, lookup_d1_pda};
+use wiremock::{
+    Mock, MockServer, ResponseTemplate,
+    matchers::{body_partial_json, method, path},
+};
+
+const ACCOUNT: &str = "account";
+const DATABASE: &str = "database";
+const QUERY_PATH: &str = "/accounts/account/d1/database/database/query";
+
+/// A query API response holding one successful result set of `rows`.
+fn query_response(rows: Value) -> ResponseTemplate {
+    ResponseTemplate::new(200).set_body_json(json!({
+        "success": true,
+        "errors": [],
+        "result": [{ "results": rows, "success": true }],
+    }))
+}
+
+#[tokio::test]
+async fn lookup_binds_the_pda_and_parses_the_row() {
+    let server = MockServer::start().await;
+    let pda = Address::from([0x01; 32]);
+    Mock::given(method("POST"))
+        .and(path(QUERY_PATH))
+        .and(body_partial_json(
+            json!({ "params": [hex::encode([0x01; 32])] }),
+        ))
+        .respond_with(query_response(json!([{
+            "program_id": hex::encode_upper([0xAA; 32]),
+            "seed_count": 2,
+            "seed_bytes": "0A0B",
+        }])))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let stored = lookup_d1_pda(
+        &server.uri(),
+        "token",
+        ACCOUNT,
+        DATABASE,
+        "pda_registry",
+        &pda,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        stored,
+        Some(StoredEntry {
+            program_id: Address::from([0xAA; 32]),
+            seed_count: 2,
+            seed_bytes: vec![0x0A, 0x0B],
+        })
+    );
+}
+
+#[tokio::test]
+async fn lookup_of_a_missing_pda_is_none() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path(QUERY_PATH))
+        .respond_with(query_response(json!([])))
+        .mount(&server)
+        .await;
+
+    let stored = lookup_d1_pda(
+        &server.uri(),
+        "token",
+        ACCOUNT,
+        DATABASE,
+        "pda_registry",
+        &Address::from([0x02; 32]),
+    )
+    .await
+    .unwrap();
+    assert_eq!(stored, None);
+}
+
+#[tokio::test]
+async fn lookup_rejects_a_malformed_program_id() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path(QUERY_PATH))
+        .respond_with(query_response(json!([{
+            "program_id": "AABB",
+            "seed_count": 0,
+            "seed_bytes": "",
+        }])))
+        .mount(&server)
+        .await;
+
+    let err = lookup_d1_pda(
+        &server.uri(),
+        "token",
+        ACCOUNT,
+        DATABASE,
+        "pda_registry",
+        &Address::from([0x03; 32]),
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "invalid program_id in D1 database database: expected 32 bytes, got 2 (starts with aabb)"
+    );
+}
+
+#[tokio::test]
+async fn pda_pages_are_keyed_on_the_last_pda_read() {
+    let server = MockServer::start().await;
+    let pda_hex = |index: u8| hex::encode_upper([index; 32]);
+    // Two full pages of two, then a short page that ends the scan.
+    for (after, page) in [
+        (String::new(), vec![1, 2]),
+        (pda_hex(2), vec![3, 4]),
+        (pda_hex(4), vec![5]),
+    ] {
+        let rows: Vec<Value> = page
+            .into_iter()
+            .map(|index| json!({ "pda": pda_hex(index) }))
+            .collect();
+        Mock::given(method("POST"))
+            .and(path(QUERY_PATH))
+            .and(body_partial_json(json!({ "params": [after] })))
+            .respond_with(query_response(Value::from(rows)))
+            .expect(1)
+            .mount(&server)
+            .await;
+    }
+
+    let pdas = d1_pdas(&server.uri(), "token", ACCOUNT, DATABASE, "pda_registry", 2)
+        .await
+        .unwrap();
+    assert_eq!(
+        pdas,
+        (1..=5).map(|index| Address::from([index; 32])).collect()
+    );
+}
+
+#[tokio::test]
+async fn pda_pages_reject_a_malformed_pda() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path(QUERY_PATH))
+        .respond_with(query_response(json!([{ "pda": "0102" }])))
+        .mount(&server)
+        .await;
+
+    let err = d1_pdas(
+        &server.uri(),
+        "token",
+        ACCOUNT,
+        DATABASE,
+        "pda_registry",
+        10,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "invalid pda in D1 database database: expected 32 bytes, got 2 (starts with 0102)"
+    );
+}
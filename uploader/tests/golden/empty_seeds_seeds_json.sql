@@ -0,0 +1,3 @@
+INSERT OR IGNORE INTO pda_registry (pda, program_id, seed_count, seed_bytes, seeds_json) VALUES
+(X'0505050505050505050505050505050505050505050505050505050505050505', X'0606060606060606060606060606060606060606060606060606060606060606', 0, X'00000000', '[]'),
+(X'0707070707070707070707070707070707070707070707070707070707070707', X'0606060606060606060606060606060606060606060606060606060606060606', 1, X'0100000000000000', '[""]');
@@ -0,0 +1,2 @@
+INSERT OR IGNORE INTO pda_registry (pda, program_id, seed_count, seed_bytes) VALUES
+(X'0101010101010101010101010101010101010101010101010101010101010101', X'0202020202020202020202020202020202020202020202020202020202020202', 2, X'02000000050000007661756C74020000000304');